@@ -14,7 +14,9 @@ use crate::stringtable::{StringId, StringTable};
 use crate::uint256::Uint256;
 use derivative::Derivative;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap, HashSet};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
 
 /// This is a map of the types at a given location, with the Vec<String> representing the module path
 /// and the usize representing the `StringId` of the type at that location.
@@ -35,6 +37,58 @@ pub struct Attributes {
     #[serde(skip)]
     /// Whether generated instructions should be printed to the console.
     pub codegen_print: bool,
+    #[serde(skip)]
+    /// Warnings silenced for this node and everything beneath it.
+    pub allow: Lints,
+}
+
+/// A set of warnings that can be silenced with an `#[allow(...)]` attribute, one bit per lint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lints(u16);
+
+impl Lints {
+    /// Values and args that are assigned or declared but never used.
+    pub const UNUSED: Lints = Lints(1 << 0);
+    /// Statements that can never be executed.
+    pub const UNREACHABLE: Lints = Lints(1 << 1);
+    /// Side effects in positions that are expected to be pure.
+    pub const IMPURE: Lints = Lints(1 << 2);
+    /// Constant shifts and address casts that lose set bits.
+    pub const LOSSY_CONSTANTS: Lints = Lints(1 << 3);
+    /// Comparisons of a value against itself.
+    pub const SELF_COMPARISON: Lints = Lints(1 << 4);
+    /// Funcs whose entire body is a trap, for programs compiled with `--warn-unimplemented`.
+    pub const UNIMPLEMENTED: Lints = Lints(1 << 5);
+    /// Values silently widened to `any`, for programs compiled with `--no-implicit-any`.
+    pub const IMPLICIT_ANY: Lints = Lints(1 << 6);
+    /// Ifs that just yield `true` or `false` depending on their condition.
+    pub const BOOL_IF: Lints = Lints(1 << 7);
+    /// Locals holding values that can't be freely duplicated which are copied into other locals.
+    pub const NON_COPY: Lints = Lints(1 << 8);
+
+    /// Finds the lint named `name` in an `#[allow(...)]` attribute.
+    pub fn from_name(name: &str) -> Option<Lints> {
+        match name {
+            "unused" => Some(Lints::UNUSED),
+            "unreachable" => Some(Lints::UNREACHABLE),
+            "impure" => Some(Lints::IMPURE),
+            "lossy_constants" => Some(Lints::LOSSY_CONSTANTS),
+            "self_comparison" => Some(Lints::SELF_COMPARISON),
+            "unimplemented" => Some(Lints::UNIMPLEMENTED),
+            "implicit_any" => Some(Lints::IMPLICIT_ANY),
+            "bool_if" => Some(Lints::BOOL_IF),
+            "non_copy" => Some(Lints::NON_COPY),
+            _ => None,
+        }
+    }
+
+    pub fn contains(self, lints: Lints) -> bool {
+        self.0 & lints.0 == lints.0
+    }
+
+    pub fn insert(&mut self, lints: Lints) {
+        self.0 |= lints.0;
+    }
 }
 
 impl DebugInfo {
@@ -79,14 +133,23 @@ pub enum TopLevelDecl {
 }
 
 /// Type Declaration, contains the StringId corresponding to the type name, and the underlying Type.
+///
+/// The declared name is a transparent alias. The `Nominal` type referring to it is resolved
+/// through the `TypeTree` whenever types are compared, so the alias and its definition are
+/// mutually assignable, while diagnostics still print the alias name.
 #[derive(Debug, Clone)]
 pub struct TypeDecl {
     pub name: StringId,
     pub tipe: Type,
+    pub location: Option<Location>,
 }
 
-pub fn new_type_decl(name: StringId, tipe: Type) -> TypeDecl {
-    TypeDecl { name, tipe }
+pub fn new_type_decl(name: StringId, tipe: Type, location: Option<Location>) -> TypeDecl {
+    TypeDecl {
+        name,
+        tipe,
+        location,
+    }
 }
 
 /// A type in the mini language.
@@ -108,6 +171,8 @@ pub enum Type {
     Any,
     Every,
     Option(Box<Type>),
+    /// Unions are untagged: a member is stored as is, and `unioncast` trusts the caller about which
+    /// member is present, so there's no runtime tag to choose a representation for.
     Union(Vec<Type>),
     Nominal(Vec<String>, StringId, #[serde(default)] Vec<Type>),
     GenericSlot(usize),
@@ -162,22 +227,277 @@ impl AbstractSyntaxTree for Type {
 impl Type {
     /// Gets the representation of a `Nominal` type, based on the types in `type_tree`, returns self
     /// if the type is not `Nominal`, or a `CompileError` if the type of `self` cannot be resolved in
-    /// `type_tree`.
+    /// `type_tree` or is defined in terms of itself.
     pub fn rep(&self, type_tree: &TypeTree) -> Result<Self, CompileError> {
+        self.rep_located(type_tree, &HashMap::new())
+    }
+
+    /// Like `rep`, but an alias defined in terms of itself is reported at its entry in `locations`.
+    ///
+    /// An alias is cyclic when its own definition leads back to it, as in `type A = B; type B = A;`
+    /// or `type G<T> = G<option<T>>;`. Aliases that only reappear in the type arguments given,
+    /// as in `base<base<uint>>`, are resolved one after the other.
+    pub fn rep_located(
+        &self,
+        type_tree: &TypeTree,
+        locations: &HashMap<(Vec<String>, usize), Location>,
+    ) -> Result<Self, CompileError> {
+        if !matches!(self, Type::Nominal(..)) {
+            return Ok(self.clone());
+        }
+
+        let mut current = Unresolved {
+            tipe: self,
+            slots: None,
+            origin: Rc::new(vec![]),
+        };
+
+        loop {
+            match current.tipe {
+                Type::GenericSlot(slot) if current.slots.is_some() => {
+                    current = current
+                        .slots
+                        .as_ref()
+                        .and_then(|slots| slots.get(*slot))
+                        .cloned()
+                        .ok_or_else(|| missing_specialization(*slot))?;
+                }
+                Type::Nominal(path, id, spec) => {
+                    let key = (path.clone(), *id);
+                    let (tipe, name) = type_tree.get(&key).ok_or_else(|| {
+                        CompileError::new_type_error(
+                            format!("No type at {:?}, {}", path, id),
+                            vec![],
+                        )
+                    })?;
+                    if current.origin.contains(&(path, *id)) {
+                        return Err(CompileError::new_type_error(
+                            format!("cyclic type definition for {}", Color::red(name)),
+                            locations.get(&key).into_iter().cloned().collect(),
+                        ));
+                    }
+                    let slots = spec
+                        .iter()
+                        .map(|tipe| Unresolved {
+                            tipe,
+                            slots: current.slots.clone(),
+                            origin: current.origin.clone(),
+                        })
+                        .collect();
+                    let mut origin = (*current.origin).clone();
+                    origin.push((path, *id));
+                    current = Unresolved {
+                        tipe,
+                        slots: Some(Rc::new(slots)),
+                        origin: Rc::new(origin),
+                    };
+                }
+                _ => return current.specialize(),
+            }
+        }
+    }
+
+    /// Determines whether values of this type can be map keys. Keys are found by hashing and
+    /// comparing them, so they must be plain values: integers, bools, addresses, and `bytes32`s,
+    /// along with tuples, structs, fixed arrays, arrays, options, and unions made of them. Funcs,
+    /// maps, buffers, and `void` are rejected. Types whose contents aren't known, like `any` and
+    /// generics, are accepted.
+    pub fn is_map_key(&self, type_tree: &TypeTree) -> bool {
+        !self.contains(type_tree, &|tipe| {
+            matches!(
+                tipe,
+                Type::Func(..) | Type::Map(..) | Type::Buffer | Type::Void
+            )
+        })
+    }
+
+    /// Determines whether values of this type mean anything outside the program, as the args and
+    /// returns of its entry points must. Funcs and closures are code points within the program, so
+    /// any type containing one is rejected.
+    pub fn is_serializable(&self, type_tree: &TypeTree) -> bool {
+        !self.contains_func(type_tree)
+    }
+
+    /// Determines whether this type holds a func anywhere within it.
+    pub fn contains_func(&self, type_tree: &TypeTree) -> bool {
+        self.contains(type_tree, &|tipe| matches!(tipe, Type::Func(..)))
+    }
+
+    /// Determines whether this type, or any type a value of it holds, satisfies `found`. Nominal
+    /// types are searched as what they stand for once their type arguments are filled in, and a
+    /// type that contains itself is only searched once. The args and returns of funcs aren't held
+    /// by them, so they aren't searched, and neither are the contents of types that don't resolve.
+    fn contains(&self, type_tree: &TypeTree, found: &dyn Fn(&Type) -> bool) -> bool {
+        self.contains_inner(type_tree, found, &mut HashSet::new())
+    }
+
+    fn contains_inner(
+        &self,
+        type_tree: &TypeTree,
+        found: &dyn Fn(&Type) -> bool,
+        seen: &mut HashSet<Type>,
+    ) -> bool {
+        if found(self) {
+            return true;
+        }
+        match self {
+            Type::Tuple(entries) | Type::Union(entries) => entries
+                .iter()
+                .any(|entry| entry.contains_inner(type_tree, found, seen)),
+            Type::Struct(fields) => fields
+                .iter()
+                .any(|field| field.tipe.contains_inner(type_tree, found, seen)),
+            Type::Array(inner) | Type::FixedArray(inner, _) | Type::Option(inner) => {
+                inner.contains_inner(type_tree, found, seen)
+            }
+            Type::Map(key, value) => {
+                key.contains_inner(type_tree, found, seen)
+                    || value.contains_inner(type_tree, found, seen)
+            }
+            Type::Nominal(..) => {
+                seen.insert(self.clone())
+                    && match self.rep(type_tree) {
+                        Ok(rep) => rep.contains_inner(type_tree, found, seen),
+                        Err(_) => false,
+                    }
+            }
+            _ => false,
+        }
+    }
+
+    /// Describes how values of this type are written as JSON, for services exchanging them with a
+    /// program, as a JSON Schema. Integers are decimal strings, since JSON numbers can't hold 256
+    /// bits, while `bytes32`, addresses, and buffers are hex strings. Tuples and fixed arrays are
+    /// arrays of their elements, structs are objects keyed by field name, maps are arrays of
+    /// key-value pairs, and a `None` is `null`. Unions carry no tag, so a union value is written as
+    /// its member is. Nominal types are described once in `defs`, keyed by their qualified names,
+    /// and referenced from there. Funcs can't leave a program, so types containing them are errors.
+    pub fn json_schema(
+        &self,
+        type_tree: &TypeTree,
+        defs: &mut BTreeMap<String, serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
+        let string = |pattern: &str| json!({ "type": "string", "pattern": pattern });
+        let sized = |items: serde_json::Value, length: usize| json!({ "type": "array", "prefixItems": items, "minItems": length, "maxItems": length });
+        let list = |types: &[Type], defs: &mut BTreeMap<_, _>| {
+            types
+                .iter()
+                .map(|tipe| tipe.json_schema(type_tree, defs))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match self {
+            Type::Void => json!({ "type": "null" }),
+            Type::Uint => string("^[0-9]+$"),
+            Type::Int => string("^-?[0-9]+$"),
+            Type::Bool => json!({ "type": "boolean" }),
+            Type::Bytes32 => string("^0x[0-9a-fA-F]{64}$"),
+            Type::EthAddress => string("^0x[0-9a-fA-F]{40}$"),
+            Type::Buffer => string("^0x([0-9a-fA-F]{2})*$"),
+            Type::Any | Type::GenericSlot(_) | Type::Generic(_) => json!({}),
+            Type::Every => json!({ "not": {} }),
+            Type::Tuple(types) => sized(json!(list(types, defs)?), types.len()),
+            Type::Union(types) => json!({ "anyOf": list(types, defs)? }),
+            Type::Array(inner) => {
+                json!({ "type": "array", "items": inner.json_schema(type_tree, defs)? })
+            }
+            Type::FixedArray(inner, size) => json!({
+                "type": "array",
+                "items": inner.json_schema(type_tree, defs)?,
+                "minItems": size,
+                "maxItems": size,
+            }),
+            Type::Option(inner) => {
+                json!({ "anyOf": [{ "type": "null" }, inner.json_schema(type_tree, defs)?] })
+            }
+            Type::Map(key, value) => {
+                let pair = list(&[(**key).clone(), (**value).clone()], defs)?;
+                json!({ "type": "array", "items": sized(json!(pair), 2) })
+            }
+            Type::Struct(fields) => {
+                let mut properties = serde_json::Map::new();
+                for field in fields {
+                    let schema = field.tipe.json_schema(type_tree, defs)?;
+                    properties.insert(field.name.clone(), schema);
+                }
+                let names: Vec<_> = fields.iter().map(|field| &field.name).collect();
+                json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": names,
+                    "additionalProperties": false,
+                })
+            }
+            Type::Nominal(path, ..) => {
+                let written = self
+                    .display_source(type_tree)
+                    .unwrap_or_else(|| self.print(type_tree));
+                let name = format!("{}::{}", path.join("::"), written);
+                if !defs.contains_key(&name) {
+                    // recursive types refer to themselves, so claim the name before descending
+                    defs.insert(name.clone(), json!({}));
+                    let schema = self
+                        .rep(type_tree)
+                        .map_err(|err| err.description)
+                        .and_then(|rep| rep.json_schema(type_tree, defs));
+                    match schema {
+                        Ok(schema) => defs.insert(name.clone(), schema),
+                        // drop the claim so a failed type leaves no empty schema behind
+                        Err(why) => {
+                            defs.remove(&name);
+                            return Err(why);
+                        }
+                    };
+                }
+                json!({ "$ref": format!("#/$defs/{}", json_pointer_token(&name)) })
+            }
+            Type::Func(..) => {
+                return Err(format!(
+                    "Type {} has no JSON representation",
+                    Color::red(self.print(type_tree))
+                ))
+            }
+        })
+    }
+
+    /// Gets the number of slots a value of this type spreads across when stored as a tuple.
+    ///
+    /// Tuples, structs, and fixed arrays occupy one slot per element, `Void` occupies none, and
+    /// everything else fits in a single slot. Since a field takes one slot whatever its type, a
+    /// struct's layout depends only on how many fields it has, so it's kept in declared order,
+    /// which `unsafecast` and `asm` code are free to rely on.
+    pub fn size_in_slots(&self, type_tree: &TypeTree) -> Result<usize, String> {
         let mut base_type = self.clone();
+        let mut seen = HashSet::new();
 
         while let Type::Nominal(path, id, spec) = base_type.clone() {
+            if !seen.insert((path.clone(), id)) {
+                return Err(format!(
+                    "Type {} is recursive and has no size",
+                    Color::red(self.print(type_tree))
+                ));
+            }
             base_type = type_tree
-                .get(&(path.clone(), id))
-                .cloned()
-                .ok_or(CompileError::new_type_error(
-                    format!("No type at {:?}, {}", path, id),
-                    vec![],
-                ))?
+                .get(&(path, id))
+                .ok_or_else(|| format!("Type {} could not be resolved", Color::red(id)))?
                 .0
-                .make_specific(&spec)?;
+                .make_specific(&spec)
+                .map_err(|err| err.description)?;
         }
-        Ok(base_type)
+
+        Ok(match base_type {
+            Type::Void => 0,
+            Type::Tuple(entries) => entries.len(),
+            Type::Struct(fields) => fields.len(),
+            Type::FixedArray(_, size) => size,
+            Type::GenericSlot(_) | Type::Generic(_) => {
+                return Err(format!(
+                    "Size of generic type {} is not known at compile time",
+                    Color::red(self.print(type_tree))
+                ))
+            }
+            _ => 1,
+        })
     }
 
     /// Finds all nominal sub-types present under a type
@@ -318,15 +638,7 @@ impl Type {
             }
         });
         match failure {
-            Some(slot) => Err(CompileError::new(
-                "Generics error",
-                format!(
-                    "Specialization {} is missing its {} type.",
-                    Color::red("<1st, 2nd, ...>"),
-                    Color::red(human_readable_index(slot + 1))
-                ),
-                vec![],
-            )),
+            Some(slot) => Err(missing_specialization(slot)),
             None => Ok(tipe),
         }
     }
@@ -469,6 +781,42 @@ impl Type {
         }
     }
 
+    /// Combines two types that may each contain `every` into one that both are assignable to,
+    /// replacing each `every` with the corresponding part of the other type.  Returns `None` if
+    /// the types differ in some other way.
+    pub fn join(&self, other: &Type) -> Option<Type> {
+        Some(match (self, other) {
+            (Type::Every, _) => other.clone(),
+            (_, Type::Every) => self.clone(),
+            (Type::Tuple(left), Type::Tuple(right)) if left.len() == right.len() => Type::Tuple(
+                left.iter()
+                    .zip(right)
+                    .map(|(left, right)| left.join(right))
+                    .collect::<Option<_>>()?,
+            ),
+            (Type::Struct(left), Type::Struct(right)) if left.len() == right.len() => Type::Struct(
+                left.iter()
+                    .zip(right)
+                    .map(|(left, right)| match left.name == right.name {
+                        true => Some(StructField::new(
+                            left.name.clone(),
+                            left.tipe.join(&right.tipe)?,
+                            left.debug_info,
+                        )),
+                        false => None,
+                    })
+                    .collect::<Option<_>>()?,
+            ),
+            (Type::FixedArray(left, size), Type::FixedArray(right, size2)) if size == size2 => {
+                Type::FixedArray(Box::new(left.join(right)?), *size)
+            }
+            (Type::Array(left), Type::Array(right)) => Type::Array(Box::new(left.join(right)?)),
+            (Type::Option(left), Type::Option(right)) => Type::Option(Box::new(left.join(right)?)),
+            _ if self == other => self.clone(),
+            _ => return None,
+        })
+    }
+
     /// Returns true if rhs is a subtype of self, and false otherwise
     pub fn assignable(
         &self,
@@ -797,70 +1145,181 @@ impl Type {
             })
     }
 
+    /// Finds a named type whose default value, if this type's default were made, would have to
+    /// contain itself and thus be infinitely large. Options and maps are empty by default and so
+    /// break such cycles, while new arrays are filled with default elements and don't. Returns the
+    /// type's name, the fields leading back to it, and where those fields are declared.
+    pub fn recursive_default(
+        &self,
+        type_tree: &TypeTree,
+    ) -> Option<(String, String, Vec<Location>)> {
+        type Key = (Vec<String>, StringId);
+        type Step = (String, Option<Location>);
+
+        fn search(
+            tipe: &Type,
+            type_tree: &TypeTree,
+            open: &mut Vec<(Key, usize)>,
+            finite: &mut HashSet<Key>,
+            trail: &mut Vec<Step>,
+        ) -> Option<(String, String, Vec<Location>)> {
+            let mut step = |step: String, location, tipe: &Type| {
+                trail.push((step, location));
+                let found = search(tipe, type_tree, open, finite, trail);
+                trail.pop();
+                found
+            };
+
+            match tipe {
+                Type::Nominal(path, id, _) => {
+                    let key = (path.clone(), *id);
+                    let (declared, name) = type_tree.get(&key)?;
+                    if let Some((_, start)) = open.iter().find(|(open, _)| *open == key) {
+                        let cycle = &trail[*start..];
+                        return Some((
+                            name.clone(),
+                            cycle.iter().map(|(step, _)| step.as_str()).collect(),
+                            cycle.iter().filter_map(|(_, location)| *location).collect(),
+                        ));
+                    }
+                    if finite.contains(&key) {
+                        return None;
+                    }
+                    open.push((key.clone(), trail.len()));
+                    let found = search(declared, type_tree, open, finite, trail);
+                    open.pop();
+                    if found.is_none() {
+                        finite.insert(key);
+                    }
+                    found
+                }
+                Type::Tuple(types) => types
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, tipe)| step(format!(".{}", index), None, tipe)),
+                Type::Struct(fields) => fields.iter().find_map(|field| {
+                    let location = field.debug_info.location;
+                    step(format!(".{}", field.name), location, &field.tipe)
+                }),
+                Type::Array(tipe) | Type::FixedArray(tipe, _) => {
+                    step(String::from("[0]"), None, tipe)
+                }
+                // only the first member provides the default value
+                Type::Union(types) => search(types.first()?, type_tree, open, finite, trail),
+                _ => None,
+            }
+        }
+
+        search(
+            self,
+            type_tree,
+            &mut vec![],
+            &mut HashSet::new(),
+            &mut vec![],
+        )
+    }
+
+    /// Determines whether values of this type can be freely duplicated. Every mini value is
+    /// currently copied when it's used, but funcs may capture state and so are treated as values
+    /// that shouldn't be, as is anything containing one. Since nothing is known about `any` and
+    /// type parameters, they're presumed to be plain data.
+    pub fn is_copy(&self, type_tree: &TypeTree) -> bool {
+        !self.contains_func(type_tree)
+    }
+
     /// Returns the default value for the type
     pub fn default_value(&self, type_tree: &TypeTree) -> Value {
+        self.default_layout(type_tree).0
+    }
+
+    /// Returns the default value for the type along with how many tuples deep it nests, which is
+    /// tallied as the value is built.
+    pub fn default_layout(&self, type_tree: &TypeTree) -> (Value, usize) {
         match self {
-            Type::Any => Value::none(),
-            Type::Buffer => Value::new_buffer(vec![]),
+            Type::Any => (Value::none(), 1),
+            Type::Buffer => (Value::new_buffer(vec![]), 0),
             Type::Uint | Type::Int | Type::Bytes32 | Type::EthAddress | Type::Bool => {
-                Value::from(0)
+                (Value::from(0), 0)
             }
             Type::Union(types) => {
                 // the first's default has to be right by definition
-                types[0].default_value(type_tree)
+                types[0].default_layout(type_tree)
             }
             Type::Tuple(types) => {
-                Value::new_tuple(types.iter().map(|v| v.default_value(type_tree)).collect())
+                let (values, depths): (Vec<_>, Vec<_>) =
+                    types.iter().map(|v| v.default_layout(type_tree)).unzip();
+                let depth = 1 + depths.into_iter().max().unwrap_or(0);
+                (Value::new_tuple(values), depth)
             }
             Type::Struct(fields) => {
-                let field_values = fields
+                let (field_values, depths): (Vec<_>, Vec<_>) = fields
                     .iter()
-                    .map(|f| f.tipe.default_value(type_tree))
-                    .collect();
-                TupleTree::fold_into_tuple(field_values)
+                    .map(|f| f.tipe.default_layout(type_tree))
+                    .unzip();
+                let depth = TupleTree::new(field_values.len(), false)
+                    .leaf_depths()
+                    .into_iter()
+                    .zip(depths)
+                    .map(|(slots, depth)| slots + depth)
+                    .max()
+                    .unwrap_or(1);
+                (TupleTree::fold_into_tuple(field_values), depth)
             }
             Type::Func(..) => {
                 // the error codepoint
-                Value::CodePoint(CodePt::Null)
+                (Value::CodePoint(CodePt::Null), 0)
             }
-            Type::Option(_) => Value::new_tuple(vec![Value::Int(Uint256::zero())]),
-            Type::Map(_, _) => Value::new_tuple(vec![
-                Value::from(0), // kvs.mini builtin_kvsNew() unsafe casts on 0
-                Value::from(0), // set size to 0
-            ]),
-            Type::Array(t) => {
-                let fixed = Type::FixedArray(t.clone(), 1).default_value(type_tree);
+            Type::Option(_) => (Value::new_tuple(vec![Value::Int(Uint256::zero())]), 1),
+            Type::Map(_, _) => (
                 Value::new_tuple(vec![
-                    Value::from(1), // size
-                    Value::from(1), // topstep
-                    fixed,          // array.mini builtin_arrayNew() unsafe casts this
-                ])
+                    Value::from(0), // kvs.mini builtin_kvsNew() unsafe casts on 0
+                    Value::from(0), // set size to 0
+                ]),
+                1,
+            ),
+            Type::Array(t) => {
+                let (fixed, depth) = Type::FixedArray(t.clone(), 1).default_layout(type_tree);
+                (
+                    Value::new_tuple(vec![
+                        Value::from(1), // size
+                        Value::from(1), // topstep
+                        fixed,          // array.mini builtin_arrayNew() unsafe casts this
+                    ]),
+                    depth + 1,
+                )
             }
             Type::FixedArray(t, size) => {
                 // emulate array.mini builtin_arrayNew()
-                fn emulated_builtin(size: usize, mut base: Value) -> Value {
+                fn emulated_builtin(
+                    size: usize,
+                    mut base: Value,
+                    mut depth: usize,
+                ) -> (Value, usize) {
                     let mut chunk = 1;
                     while (8 * chunk < size) {
                         chunk = 8 * chunk;
                         base = Value::new_tuple(vec![base; 8]);
+                        depth += 1;
                     }
-                    Value::new_tuple(vec![
+                    let value = Value::new_tuple(vec![
                         Value::from(size),  // size
                         Value::from(chunk), // topstep
                         Value::new_tuple(vec![base; 8]),
-                    ])
+                    ]);
+                    (value, depth + 2)
                 }
-                emulated_builtin(*size, t.default_value(type_tree))
+                let (base, depth) = t.default_layout(type_tree);
+                emulated_builtin(*size, base, depth)
             }
             Type::Nominal(..) => {
                 let tipe = self.rep(type_tree).unwrap_or(Type::Any);
-                tipe.default_value(type_tree)
+                tipe.default_layout(type_tree)
             }
             Type::Generic(_) | Type::GenericSlot(_) => {
                 // This can happen with builtins, but we plan on refactoring all builtins out.
                 // In the future we'll make this panic since you should always know the specified value
                 // for whatever generic type you're getting the default for.
-                Value::none()
+                (Value::none(), 1)
             }
             x => panic!(
                 "Tried to get the default value for type {}",
@@ -878,6 +1337,76 @@ impl Type {
         self.display_indented(0, "::", None, false, type_tree).0
     }
 
+    /// Renders the type in mini syntax that parses back into an equivalent type. Nominal types are
+    /// named as declared, without their paths, so the output reparses to the same type in the
+    /// module declaring them. Generic slots, empty structs, empty unions, and nominal types missing
+    /// from `type_tree` can't be written in source, so `None` is returned for types containing them.
+    pub fn display_source(&self, type_tree: &TypeTree) -> Option<String> {
+        let list = |types: &[Type]| {
+            types
+                .iter()
+                .map(|tipe| tipe.display_source(type_tree))
+                .collect::<Option<Vec<_>>>()
+                .map(|displayed| displayed.join(", "))
+        };
+        Some(match self {
+            Type::Void
+            | Type::Uint
+            | Type::Int
+            | Type::Bool
+            | Type::Bytes32
+            | Type::EthAddress
+            | Type::Buffer
+            | Type::Any
+            | Type::Every => self.display(),
+            Type::Tuple(subtypes) if subtypes.len() == 1 => format!("({},)", list(subtypes)?),
+            Type::Tuple(subtypes) => format!("({})", list(subtypes)?),
+            Type::Array(tipe) => format!("[]{}", tipe.display_source(type_tree)?),
+            Type::FixedArray(tipe, size) => {
+                format!("[{}]{}", size, tipe.display_source(type_tree)?)
+            }
+            Type::Struct(fields) if !fields.is_empty() => format!(
+                "struct {{ {} }}",
+                fields
+                    .iter()
+                    .map(|field| Some(format!(
+                        "{}: {}",
+                        field.name,
+                        field.tipe.display_source(type_tree)?
+                    )))
+                    .collect::<Option<Vec<_>>>()?
+                    .join(", ")
+            ),
+            Type::Func(prop, args, ret) => format!(
+                "{}{}func({}){}",
+                if prop.view { "view " } else { "" },
+                if prop.write { "write " } else { "" },
+                list(args)?,
+                match **ret {
+                    Type::Void => String::new(),
+                    _ => format!(" -> {}", ret.display_source(type_tree)?),
+                }
+            ),
+            Type::Map(key, val) => format!(
+                "map<{}, {}>",
+                key.display_source(type_tree)?,
+                val.display_source(type_tree)?
+            ),
+            Type::Option(tipe) => format!("option<{}>", tipe.display_source(type_tree)?),
+            Type::Union(types) if !types.is_empty() => format!("union<{}>", list(types)?),
+            Type::Nominal(path, id, spec) => {
+                let (_, name) = type_tree.get(&(path.clone(), *id))?;
+                match spec.len() {
+                    0 => name.clone(),
+                    _ => format!("{}<{}>", name, list(spec)?),
+                }
+            }
+            Type::Struct(_) | Type::Union(_) | Type::GenericSlot(_) | Type::Generic(_) => {
+                return None
+            }
+        })
+    }
+
     pub fn display_separator(
         &self,
         separator: &str,
@@ -997,7 +1526,7 @@ impl Type {
                                 out.push_str(&(displayed + ", "));
                                 type_set.extend(subtypes);
                             }
-                            out.push_str("> ");
+                            out.push('>');
                             // TODO: Make this work for recursive generics
                             /*out.push_str(&format!(
                                 "> := {}",
@@ -1083,7 +1612,7 @@ impl Type {
                     include_pathname,
                     type_tree,
                 );
-                (format!("option<{}> ", display), subtypes)
+                (format!("option<{}>", display), subtypes)
             }
             Type::Union(types) => {
                 let mut s = String::from("union<");
@@ -1107,6 +1636,58 @@ impl Type {
     }
 }
 
+/// A type that `rep` has yet to resolve, made of a type written in a declaration or given to `rep`,
+/// the types its `GenericSlot`s stand for, and the aliases whose definitions it was taken from.
+#[derive(Clone)]
+struct Unresolved<'a> {
+    tipe: &'a Type,
+    slots: Option<Rc<Vec<Unresolved<'a>>>>,
+    origin: Rc<Vec<(&'a Vec<String>, usize)>>,
+}
+
+impl Unresolved<'_> {
+    /// Fills in the `GenericSlot`s of this type.
+    fn specialize(&self) -> Result<Type, CompileError> {
+        match &self.slots {
+            Some(slots) => self.tipe.make_specific(
+                &slots
+                    .iter()
+                    .map(Unresolved::specialize)
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => Ok(self.tipe.clone()),
+        }
+    }
+}
+
+/// Escapes `name` as one token of a JSON pointer in a URI fragment, since the names of
+/// specialized generic types hold `<`, `,`, and spaces.
+fn json_pointer_token(name: &str) -> String {
+    name.replace('~', "~0")
+        .replace('/', "~1")
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The error for a specialization without a type for `slot`.
+fn missing_specialization(slot: usize) -> CompileError {
+    CompileError::new(
+        "Generics error",
+        format!(
+            "Specialization {} is missing its {} type.",
+            Color::red("<1st, 2nd, ...>"),
+            Color::red(human_readable_index(slot + 1))
+        ),
+        vec![],
+    )
+}
+
 /// Checks generic parameter names for those that may be duplicates or unused.
 pub fn check_generic_parameters(
     params: Vec<(StringId, DebugInfo)>,
@@ -1390,6 +1971,16 @@ pub struct AssignRef {
     pub id: StringId,
     pub shadow: bool,
     pub debug_info: DebugInfo,
+    /// Whether a newly declared local was marked `mut`, permitting it to be reassigned.
+    #[serde(default)]
+    pub mutable: bool,
+    /// The type a newly declared local was annotated with, if any.
+    #[serde(default)]
+    pub tipe: Option<Type>,
+    /// For a hidden local standing in for a nested tuple pattern, the names that pattern binds.
+    /// These are split off into a follow-up `let` by `Statement::destructure_nested`.
+    #[serde(default)]
+    pub pattern: Option<Vec<AssignRef>>,
 }
 
 impl AssignRef {
@@ -1398,29 +1989,70 @@ impl AssignRef {
             id,
             shadow,
             debug_info,
+            mutable: false,
+            tipe: None,
+            pattern: None,
+        }
+    }
+
+    /// Declares a new local that may be reassigned.
+    pub fn new_mut(id: StringId, debug_info: DebugInfo) -> Self {
+        Self {
+            id,
+            shadow: true,
+            debug_info,
+            mutable: true,
+            tipe: None,
+            pattern: None,
         }
     }
+
+    /// Annotates the local with the type it's declared to have.
+    pub fn with_type(mut self, tipe: Type) -> Self {
+        self.tipe = Some(tipe);
+        self
+    }
+
+    /// Marks the local as holding a tuple that's further destructured into `pattern`.
+    pub fn with_pattern(mut self, pattern: Vec<AssignRef>) -> Self {
+        self.pattern = Some(pattern);
+        self
+    }
 }
 
 /// Field of a struct, contains field name and underlying type.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Eq, Serialize, Deserialize, Derivative)]
+#[derivative(Hash, PartialEq)]
 pub struct StructField {
     pub name: String,
     pub tipe: Type,
+    /// Where the field was declared, which plays no part in the struct's type.
+    #[serde(skip)]
+    #[derivative(Hash = "ignore", PartialEq = "ignore")]
+    pub debug_info: DebugInfo,
 }
 
 impl StructField {
-    pub fn new(name: String, tipe: Type) -> StructField {
-        StructField { name, tipe }
+    pub fn new(name: String, tipe: Type, debug_info: DebugInfo) -> StructField {
+        StructField {
+            name,
+            tipe,
+            debug_info,
+        }
     }
 }
 
 /// Argument to a function, contains field name and underlying type.
+///
+/// Arguments written as a tuple pattern, like `(a, b): (uint, uint)`, are given a hidden name,
+/// and the names in the pattern are bound from it when the func begins.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FuncArg {
     pub name: StringId,
     pub tipe: Type,
     pub debug_info: DebugInfo,
+    #[serde(default)]
+    pub pattern: Option<Vec<AssignRef>>,
 }
 
 pub fn new_func_arg(name: StringId, tipe: Type, debug_info: DebugInfo) -> FuncArg {
@@ -1428,6 +2060,7 @@ pub fn new_func_arg(name: StringId, tipe: Type, debug_info: DebugInfo) -> FuncAr
         name,
         tipe,
         debug_info,
+        pattern: None,
     }
 }
 
@@ -1583,6 +2216,117 @@ impl Statement {
     pub fn new(kind: StatementKind, debug_info: DebugInfo) -> Self {
         Self { kind, debug_info }
     }
+
+    /// Desugars `for (key, value) in map { body }` into a while loop over the map's keys.
+    ///
+    /// The map is evaluated once before the loop, so the body iterating over a snapshot of it,
+    /// and reassigning the original map inside the body has no effect on which entries are
+    /// visited. The `hidden` ids name the snapshot, its keys, and the loop index.
+    pub fn new_map_loop(
+        key: StringId,
+        value: StringId,
+        map: Expr,
+        body: CodeBlock,
+        hidden: [StringId; 3],
+        debug_info: DebugInfo,
+    ) -> Self {
+        let [map_id, keys_id, index_id] = hidden;
+        let expr = |kind| Expr::new(kind, debug_info);
+        let stat = |kind| Statement::new(kind, debug_info);
+        let var = |id| expr(ExprKind::VariableRef(id, vec![]));
+        let bind = |id, value| {
+            stat(StatementKind::Let(
+                vec![AssignRef::new(id, true, debug_info)],
+                value,
+            ))
+        };
+
+        let iteration = CodeBlock::new(
+            vec![
+                bind(
+                    key,
+                    expr(ExprKind::ArrayOrMapRef(
+                        Box::new(var(keys_id)),
+                        Box::new(var(index_id)),
+                    )),
+                ),
+                stat(StatementKind::Assign(
+                    index_id,
+                    expr(ExprKind::Binary(
+                        BinaryOp::Plus,
+                        Box::new(var(index_id)),
+                        Box::new(expr(ExprKind::Constant(Constant::Uint(Uint256::one())))),
+                    )),
+                )),
+                stat(StatementKind::Expression(expr(ExprKind::IfLet(
+                    value,
+                    Box::new(expr(ExprKind::ArrayOrMapRef(
+                        Box::new(var(map_id)),
+                        Box::new(var(key)),
+                    ))),
+                    body,
+                    None,
+                )))),
+            ],
+            None,
+        );
+
+        let condition = expr(ExprKind::Binary(
+            BinaryOp::LessThan,
+            Box::new(var(index_id)),
+            Box::new(expr(ExprKind::UnaryOp(
+                UnaryOp::Len,
+                Box::new(var(keys_id)),
+            ))),
+        ));
+
+        stat(StatementKind::Expression(expr(ExprKind::CodeBlock(
+            CodeBlock::new(
+                vec![
+                    bind(map_id, map),
+                    bind(keys_id, expr(ExprKind::MapKeys(Box::new(var(map_id))))),
+                    stat(StatementKind::Let(
+                        vec![AssignRef::new_mut(index_id, debug_info)],
+                        expr(ExprKind::Constant(Constant::Uint(Uint256::zero()))),
+                    )),
+                    stat(StatementKind::While(condition, iteration)),
+                ],
+                None,
+            ),
+        ))))
+    }
+
+    /// Splits a `let` with nested tuple patterns, like `let (a, (b, c)) = pair;`, into a `let` of
+    /// the outer tuple, whose nested parts go to hidden locals, followed by a `let` destructuring
+    /// each of those. Other statements are left as is.
+    pub fn destructure_nested(self) -> Vec<Statement> {
+        let debug_info = self.debug_info;
+        match self.kind {
+            StatementKind::Let(mut assigned, expr)
+                if assigned.iter().any(|local| local.pattern.is_some()) =>
+            {
+                let mut nested = vec![];
+                for local in &mut assigned {
+                    if let Some(pattern) = local.pattern.take() {
+                        let debug_info =
+                            DebugInfo::new(local.debug_info.location, debug_info.attributes);
+                        let value = Expr::new(ExprKind::VariableRef(local.id, vec![]), debug_info);
+                        nested.push(Statement::new(
+                            StatementKind::Let(pattern, value),
+                            debug_info,
+                        ));
+                    }
+                }
+                let mut stats = vec![Statement::new(
+                    StatementKind::Let(assigned, expr),
+                    debug_info,
+                )];
+                stats.extend(nested.into_iter().flat_map(Statement::destructure_nested));
+                stats
+            }
+            kind => vec![Statement::new(kind, debug_info)],
+        }
+    }
 }
 
 /// A raw statement containing no debug information that has not yet been type checked.
@@ -1596,7 +2340,29 @@ pub enum StatementKind {
     Let(Vec<AssignRef>, Expr),
     While(Expr, CodeBlock),
     DebugPrint(Expr),
-    Assert(Expr),
+    Assert(Expr, AssertLevel),
+    AssertEq(Expr, Expr, bool),
+}
+
+/// How essential an assertion is, which decides the builds that keep it. Levels are ordered from
+/// the most essential to the least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AssertLevel {
+    /// Assertions written `assert`, `assert_eq`, or `assert_ne`.
+    Release,
+    /// Assertions written `debug_assert`, which only checked builds need.
+    Debug,
+}
+
+impl AssertLevel {
+    /// Finds the level named `name` on the command line.
+    pub fn from_name(name: &str) -> Option<AssertLevel> {
+        match name {
+            "release" => Some(AssertLevel::Release),
+            "debug" => Some(AssertLevel::Debug),
+            _ => None,
+        }
+    }
 }
 
 /// An identifier or array index for left-hand-side substructure assignments
@@ -1621,6 +2387,7 @@ pub enum Constant {
     Int(Uint256),
     Bool(bool),
     Option(OptionConst),
+    Address(Uint256),
 }
 
 impl OptionConst {
@@ -1651,6 +2418,7 @@ impl Constant {
             Constant::Int(_) => Type::Int,
             Constant::Bool(_) => Type::Bool,
             Constant::Option(inner) => inner.type_of(),
+            Constant::Address(_) => Type::EthAddress,
         }
     }
 
@@ -1661,6 +2429,7 @@ impl Constant {
             Constant::Int(i) => Value::Int(i.clone()),
             Constant::Bool(b) => Value::Int(Uint256::from_bool(*b)),
             Constant::Option(c) => c.value(),
+            Constant::Address(a) => Value::Int(a.clone()),
         }
     }
 }
@@ -1692,12 +2461,15 @@ pub enum ExprKind {
     Tuple(Vec<Expr>),
     NewArray(Box<Expr>, Type),
     NewFixedArray(usize, Box<Expr>),
+    ArrayLiteral(Vec<Expr>),
     NewMap(Type, Type),
+    MapKeys(Box<Expr>),
     NewUnion(Vec<Type>, Box<Expr>),
     ArrayOrMapMod(Box<Expr>, Box<Expr>, Box<Expr>),
     StructMod(Box<Expr>, String, Box<Expr>),
     Cast(Box<Expr>, Type),
     UnsafeCast(Box<Expr>, Type),
+    SizeOf(Type),
     Asm(Type, Vec<Instruction>, Vec<Expr>),
     Error,
     GetGas,
@@ -1709,6 +2481,7 @@ pub enum ExprKind {
     UnionCast(Box<Expr>, Type),
     NewBuffer,
     Quote(Vec<u8>),
+    IncludeBytes(String),
     Closure(Func),
 }
 
@@ -1836,6 +2609,133 @@ pub struct CodeBlock {
 
 impl CodeBlock {
     pub fn new(body: Vec<Statement>, ret_expr: Option<Box<Expr>>) -> Self {
+        let body = body
+            .into_iter()
+            .flat_map(Statement::destructure_nested)
+            .collect();
         Self { body, ret_expr }
     }
 }
+
+#[test]
+fn test_is_copy() {
+    let type_tree = TypeTree::new();
+    let properties = FuncProperties::new(false, false, false, false, true, 1, 1);
+    let func = Type::Func(properties, vec![Type::Uint], Box::new(Type::Uint));
+
+    assert!(Type::Uint.is_copy(&type_tree));
+    assert!(Type::Map(Box::new(Type::Bytes32), Box::new(Type::Any)).is_copy(&type_tree));
+    assert!(!func.is_copy(&type_tree));
+    assert!(!Type::Tuple(vec![Type::Uint, func.clone()]).is_copy(&type_tree));
+    assert!(!Type::Option(Box::new(Type::Array(Box::new(func.clone())))).is_copy(&type_tree));
+
+    // type boxed<T> = struct { v: T }
+    let mut type_tree = TypeTree::new();
+    let path = vec!["test".to_string()];
+    let field = StructField::new("v".to_string(), Type::GenericSlot(0), DebugInfo::default());
+    type_tree.insert(
+        (path.clone(), 0),
+        (Type::Struct(vec![field]), "boxed".to_string()),
+    );
+    let boxed = |inner: Type| Type::Nominal(path.clone(), 0, vec![inner]);
+
+    assert!(boxed(Type::Uint).is_copy(&type_tree));
+    assert!(!boxed(func.clone()).is_copy(&type_tree));
+    assert!(!Type::Tuple(vec![boxed(Type::Uint), boxed(func)]).is_copy(&type_tree));
+}
+
+#[test]
+fn test_nominal_default_value() {
+    let path = vec!["accounts".to_string()];
+    let balance = Type::Struct(vec![
+        StructField::new("owner".to_string(), Type::EthAddress, DebugInfo::default()),
+        StructField::new(
+            "pending".to_string(),
+            Type::Option(Box::new(Type::Uint)),
+            DebugInfo::default(),
+        ),
+    ]);
+    let mut type_tree = TypeTree::new();
+    type_tree.insert((path.clone(), 0), (balance.clone(), "Balance".to_string()));
+    let named = Type::Nominal(path, 0, vec![]);
+
+    assert_eq!(
+        named.default_value(&type_tree),
+        balance.default_value(&type_tree)
+    );
+    assert_eq!(
+        Type::FixedArray(Box::new(named), 3).default_value(&type_tree),
+        Type::FixedArray(Box::new(balance), 3).default_value(&type_tree),
+    );
+}
+
+#[test]
+fn test_rep_cycle() {
+    let path = vec!["aliases".to_string()];
+    let mut type_tree = TypeTree::new();
+    type_tree.insert(
+        (path.clone(), 0),
+        (Type::Nominal(path.clone(), 1, vec![]), "Ping".to_string()),
+    );
+    type_tree.insert(
+        (path.clone(), 1),
+        (Type::Nominal(path.clone(), 0, vec![]), "Pong".to_string()),
+    );
+    type_tree.insert((path.clone(), 2), (Type::Uint, "Amount".to_string()));
+    type_tree.insert(
+        (path.clone(), 3),
+        (Type::Nominal(path.clone(), 2, vec![]), "Price".to_string()),
+    );
+
+    let error = Type::Nominal(path.clone(), 0, vec![])
+        .rep(&type_tree)
+        .unwrap_err();
+    assert!(error.description.contains("cyclic type definition"));
+    assert_eq!(
+        Type::Nominal(path.clone(), 3, vec![])
+            .rep(&type_tree)
+            .unwrap(),
+        Type::Uint
+    );
+
+    // type base<T> = T;
+    // type grow<T> = grow<option<T>>;
+    // type wrap<T> = base<wrap<option<T>>>;
+    let nominal = |id, spec| Type::Nominal(path.clone(), id, spec);
+    let option = |tipe| Type::Option(Box::new(tipe));
+    type_tree.insert(
+        (path.clone(), 4),
+        (Type::GenericSlot(0), "base".to_string()),
+    );
+    type_tree.insert(
+        (path.clone(), 5),
+        (
+            nominal(5, vec![option(Type::GenericSlot(0))]),
+            "grow".to_string(),
+        ),
+    );
+    type_tree.insert(
+        (path.clone(), 6),
+        (
+            nominal(4, vec![nominal(6, vec![option(Type::GenericSlot(0))])]),
+            "wrap".to_string(),
+        ),
+    );
+
+    let nested = nominal(4, vec![nominal(4, vec![nominal(3, vec![])])]);
+    assert_eq!(nested.rep(&type_tree).unwrap(), Type::Uint);
+
+    let location = Location {
+        line: crate::pos::Line::from(4),
+        ..Location::default()
+    };
+    let locations = vec![((path.clone(), 5), location)].into_iter().collect();
+    let error = nominal(5, vec![Type::Uint])
+        .rep_located(&type_tree, &locations)
+        .unwrap_err();
+    assert!(error.description.contains("grow"));
+    assert_eq!(error.locations, vec![location]);
+
+    let error = nominal(6, vec![Type::Uint]).rep(&type_tree).unwrap_err();
+    assert!(error.description.contains("wrap"));
+}
@@ -0,0 +1,327 @@
+/*
+ * Copyright 2021, Offchain Labs, Inc. All rights reserved.
+ */
+
+//! Builds control-flow graphs over the statements of typechecked funcs
+
+use super::ast::Type;
+use super::typecheck::{
+    TypeCheckedCodeBlock, TypeCheckedExpr, TypeCheckedExprKind, TypeCheckedStatement,
+    TypeCheckedStatementKind,
+};
+use std::collections::BTreeSet;
+
+/// Identifies a block of a `ControlFlowGraph` by its index.
+pub type BlockId = usize;
+
+/// A run of statements that always execute together, from the first to the last.
+#[derive(Debug, Default)]
+pub struct BasicBlock<'a> {
+    /// The statements of the block, in order. A statement whose expression branches, like
+    /// `let x = if ... { ... } else { ... };`, is placed in the block where its branches rejoin,
+    /// while a `while` statement is placed in the block that tests its condition.
+    pub statements: Vec<&'a TypeCheckedStatement>,
+    /// The blocks control may pass to once this one ends.
+    pub successors: Vec<BlockId>,
+}
+
+/// The basic blocks of a func's code and the edges between them, which are formed by ifs, loops,
+/// shortcut operators, returns, and `?`. Control starts in `ENTRY`, and every return, including
+/// those of `?`, leads to `EXIT`, which holds no statements. Mini has no `break`, so a `loop` is
+/// only left by returning, and expressions of type `every` end their block without successors.
+#[derive(Debug)]
+pub struct ControlFlowGraph<'a> {
+    /// Every block of the graph, indexed by `BlockId`.
+    pub blocks: Vec<BasicBlock<'a>>,
+    /// The block control is in after the last statement, should it get there.
+    end: BlockId,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    /// The block control starts in.
+    pub const ENTRY: BlockId = 0;
+    /// The block every return leads to.
+    pub const EXIT: BlockId = 1;
+
+    /// Builds the graph of `code`, the statements of a func's body as kept in the `code` of a
+    /// `TypeCheckedFunc`.
+    pub fn new(code: &'a [TypeCheckedStatement]) -> Self {
+        let mut graph = ControlFlowGraph {
+            blocks: vec![BasicBlock::default(), BasicBlock::default()],
+            end: Self::ENTRY,
+        };
+        graph.end = graph.add_statements(Self::ENTRY, code);
+        graph
+    }
+
+    /// Returns every block control can reach from `ENTRY`.
+    pub fn reachable(&self) -> BTreeSet<BlockId> {
+        let mut seen = BTreeSet::new();
+        let mut work_list = vec![Self::ENTRY];
+        while let Some(block) = work_list.pop() {
+            if seen.insert(block) {
+                work_list.extend(&self.blocks[block].successors);
+            }
+        }
+        seen
+    }
+
+    /// Determines whether control can run past the last statement without returning.
+    pub fn falls_through(&self) -> bool {
+        self.reachable().contains(&self.end)
+    }
+
+    fn add_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock::default());
+        self.blocks.len() - 1
+    }
+
+    fn add_edge(&mut self, from: BlockId, to: BlockId) {
+        self.blocks[from].successors.push(to);
+    }
+
+    /// Ends block `from`, passing control to `to` if there is one, and returns a new block for
+    /// whatever follows, which can't be reached through `from`.
+    fn terminate(&mut self, from: BlockId, to: Option<BlockId>) -> BlockId {
+        if let Some(to) = to {
+            self.add_edge(from, to);
+        }
+        self.add_block()
+    }
+
+    /// Adds `code` to the graph starting in block `current`, returning the block control is in
+    /// once the statements are done.
+    fn add_statements(
+        &mut self,
+        mut current: BlockId,
+        code: &'a [TypeCheckedStatement],
+    ) -> BlockId {
+        for stat in code {
+            current = match &stat.kind {
+                TypeCheckedStatementKind::While(cond, body) => {
+                    let header = self.add_block();
+                    self.add_edge(current, header);
+                    let test = self.add_expr(header, cond);
+                    self.blocks[test].statements.push(stat);
+                    let body_start = self.add_block();
+                    self.add_edge(test, body_start);
+                    let body_end = self.add_code_block(body_start, body);
+                    self.add_edge(body_end, header);
+                    let after = self.add_block();
+                    self.add_edge(test, after);
+                    after
+                }
+                TypeCheckedStatementKind::ReturnVoid() => {
+                    self.blocks[current].statements.push(stat);
+                    self.terminate(current, Some(Self::EXIT))
+                }
+                TypeCheckedStatementKind::Return(expr) => {
+                    let current = self.add_expr(current, expr);
+                    self.blocks[current].statements.push(stat);
+                    self.terminate(current, Some(Self::EXIT))
+                }
+                TypeCheckedStatementKind::Expression(expr)
+                | TypeCheckedStatementKind::SetLocals(_, expr)
+                | TypeCheckedStatementKind::AssignGlobal(_, expr)
+                | TypeCheckedStatementKind::DebugPrint(expr)
+                | TypeCheckedStatementKind::Assert(expr, _) => {
+                    let current = self.add_expr(current, expr);
+                    self.blocks[current].statements.push(stat);
+                    current
+                }
+                TypeCheckedStatementKind::AssertEq(left, right, ..) => {
+                    let current = self.add_exprs(current, vec![left, right]);
+                    self.blocks[current].statements.push(stat);
+                    current
+                }
+            };
+        }
+        current
+    }
+
+    fn add_code_block(&mut self, current: BlockId, block: &'a TypeCheckedCodeBlock) -> BlockId {
+        let current = self.add_statements(current, &block.body);
+        match &block.ret_expr {
+            Some(expr) => self.add_expr(current, expr),
+            None => current,
+        }
+    }
+
+    fn add_exprs<I>(&mut self, current: BlockId, exprs: I) -> BlockId
+    where
+        I: IntoIterator<Item = &'a TypeCheckedExpr>,
+    {
+        exprs
+            .into_iter()
+            .fold(current, |current, expr| self.add_expr(current, expr))
+    }
+
+    /// Adds the branches within `expr` to the graph, starting in block `current`, and returns the
+    /// block control is in once `expr` has been evaluated.
+    fn add_expr(&mut self, current: BlockId, expr: &'a TypeCheckedExpr) -> BlockId {
+        let end = match &expr.kind {
+            TypeCheckedExprKind::If(cond, block, else_block, _)
+            | TypeCheckedExprKind::IfLet(_, cond, block, else_block, _) => {
+                let test = self.add_expr(current, cond);
+                let then_start = self.add_block();
+                self.add_edge(test, then_start);
+                let then_end = self.add_code_block(then_start, block);
+                let after = self.add_block();
+                self.add_edge(then_end, after);
+                match else_block {
+                    Some(else_block) => {
+                        let else_start = self.add_block();
+                        self.add_edge(test, else_start);
+                        let else_end = self.add_code_block(else_start, else_block);
+                        self.add_edge(else_end, after);
+                    }
+                    None => self.add_edge(test, after),
+                }
+                after
+            }
+            TypeCheckedExprKind::ShortcutOr(left, right)
+            | TypeCheckedExprKind::ShortcutAnd(left, right) => {
+                let test = self.add_expr(current, left);
+                let right_start = self.add_block();
+                self.add_edge(test, right_start);
+                let right_end = self.add_expr(right_start, right);
+                let after = self.add_block();
+                self.add_edge(test, after);
+                self.add_edge(right_end, after);
+                after
+            }
+            TypeCheckedExprKind::CodeBlock(block) => self.add_code_block(current, block),
+            TypeCheckedExprKind::Loop(block, _) => {
+                let body_start = self.add_block();
+                self.add_edge(current, body_start);
+                let body_end = self.add_code_block(body_start, block);
+                self.terminate(body_end, Some(body_start))
+            }
+            TypeCheckedExprKind::Try(inner, _) => {
+                let test = self.add_expr(current, inner);
+                self.add_edge(test, Self::EXIT);
+                let after = self.add_block();
+                self.add_edge(test, after);
+                after
+            }
+            TypeCheckedExprKind::UnaryOp(_, inner, _)
+            | TypeCheckedExprKind::Variant(inner)
+            | TypeCheckedExprKind::SetGas(inner)
+            | TypeCheckedExprKind::TupleRef(inner, ..)
+            | TypeCheckedExprKind::NewFixedArray(_, inner, _)
+            | TypeCheckedExprKind::Cast(inner, _) => self.add_expr(current, inner),
+            TypeCheckedExprKind::Binary(_, left, right, _)
+            | TypeCheckedExprKind::FixedArrayRef(left, right, ..)
+            | TypeCheckedExprKind::StructMod(left, _, _, right, _) => {
+                self.add_exprs(current, vec![&**left, &**right])
+            }
+            TypeCheckedExprKind::Trinary(_, first, second, third, _)
+            | TypeCheckedExprKind::FixedArrayMod(first, second, third, ..) => {
+                self.add_exprs(current, vec![&**first, &**second, &**third])
+            }
+            TypeCheckedExprKind::FunctionCall(func, args, ..) => {
+                self.add_exprs(current, std::iter::once(&**func).chain(args))
+            }
+            TypeCheckedExprKind::Tuple(exprs, _) | TypeCheckedExprKind::Asm(_, _, exprs) => {
+                self.add_exprs(current, exprs)
+            }
+            TypeCheckedExprKind::NewBuffer
+            | TypeCheckedExprKind::Quote(..)
+            | TypeCheckedExprKind::IncludeBytes(..)
+            | TypeCheckedExprKind::LocalVariableRef(..)
+            | TypeCheckedExprKind::GlobalVariableRef(..)
+            | TypeCheckedExprKind::FuncRef(..)
+            | TypeCheckedExprKind::Const(..)
+            | TypeCheckedExprKind::ClosureLoad(..)
+            | TypeCheckedExprKind::Error
+            | TypeCheckedExprKind::GetGas => current,
+        };
+        match expr.get_type() == Type::Every {
+            true => self.terminate(end, None),
+            false => end,
+        }
+    }
+}
+
+#[test]
+fn test_control_flow_graph() {
+    use super::ast::DebugInfo;
+    use crate::mavm::Value;
+    use crate::uint256::Uint256;
+
+    let debug_info = DebugInfo::default();
+    let stat = |kind| TypeCheckedStatement { kind, debug_info };
+    let expr = |kind| TypeCheckedExpr::new(kind, debug_info);
+    let number = |value| {
+        expr(TypeCheckedExprKind::Const(
+            Value::Int(Uint256::from_u64(value)),
+            Type::Uint,
+        ))
+    };
+    let cond = expr(TypeCheckedExprKind::LocalVariableRef(0, Type::Bool));
+
+    // while cond { if cond { return 1; } } followed by the code given
+    let code = |rest: Vec<TypeCheckedStatement>| {
+        let early_return = expr(TypeCheckedExprKind::If(
+            Box::new(cond.clone()),
+            TypeCheckedCodeBlock {
+                body: vec![stat(TypeCheckedStatementKind::Return(number(1)))],
+                ret_expr: None,
+                scope: None,
+            },
+            None,
+            Type::Void,
+        ));
+        let body = TypeCheckedCodeBlock {
+            body: vec![stat(TypeCheckedStatementKind::Expression(early_return))],
+            ret_expr: None,
+            scope: None,
+        };
+        let mut code = vec![stat(TypeCheckedStatementKind::While(cond.clone(), body))];
+        code.extend(rest);
+        code
+    };
+
+    let returning = code(vec![stat(TypeCheckedStatementKind::Return(number(2)))]);
+    let graph = ControlFlowGraph::new(&returning);
+    let edges: Vec<_> = graph
+        .blocks
+        .iter()
+        .map(|block| block.successors.clone())
+        .collect();
+    // entry, exit, the loop's test, the if's test, the return, after the return, after the if,
+    // the final return, and after the final return
+    assert_eq!(
+        edges,
+        vec![
+            vec![2],
+            vec![],
+            vec![3, 7],
+            vec![4, 6],
+            vec![1],
+            vec![6],
+            vec![2],
+            vec![1],
+            vec![],
+        ]
+    );
+    assert_eq!(graph.blocks[2].statements, vec![&returning[0]]);
+    assert_eq!(graph.blocks[7].statements, vec![&returning[1]]);
+    assert!(!graph.reachable().contains(&5));
+    assert!(!graph.falls_through());
+
+    let missing = code(vec![]);
+    assert!(ControlFlowGraph::new(&missing).falls_through());
+
+    let looping = vec![stat(TypeCheckedStatementKind::Expression(expr(
+        TypeCheckedExprKind::Loop(
+            TypeCheckedCodeBlock {
+                body: vec![],
+                ret_expr: None,
+                scope: None,
+            },
+            Type::Every,
+        ),
+    )))];
+    assert!(!ControlFlowGraph::new(&looping).falls_through());
+}
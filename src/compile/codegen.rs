@@ -7,7 +7,7 @@
 use super::ast::{BinaryOp, DebugInfo, GlobalVar, TrinaryOp, Type, UnaryOp};
 use super::typecheck::{TypeCheckedFunc, TypeCheckedNode};
 use crate::compile::typecheck::{
-    AbstractSyntaxTree, TypeCheckedExprKind, TypeCheckedStatementKind,
+    AbstractSyntaxTree, TypeCheckedExpr, TypeCheckedExprKind, TypeCheckedStatementKind,
 };
 use crate::compile::CompileError;
 use crate::console::Color;
@@ -33,8 +33,6 @@ struct Codegen<'a> {
     func_labels: &'a HashMap<StringId, Label>,
     /// List of globals this func has access to.
     globals: &'a HashMap<StringId, GlobalVar>,
-    /// Whether to elide debug-only constructs like assert().
-    release_build: bool,
     /// The open set of scopes
     scopes: Vec<Scope>,
     /// The next slot available for assignment
@@ -141,7 +139,6 @@ pub fn mavm_codegen_func(
     string_table: &StringTable,
     globals: &HashMap<StringId, GlobalVar>,
     func_labels: &HashMap<StringId, Label>,
-    release_build: bool,
 ) -> Result<(Vec<Instruction>, LabelGenerator, u32), CompileError> {
     let mut code = vec![];
     let debug = func.debug_info;
@@ -205,7 +202,6 @@ pub fn mavm_codegen_func(
         string_table,
         func_labels,
         globals,
-        release_build,
         scopes: vec![Scope::default()],
         next_assignable_slot: 0,
     };
@@ -317,6 +313,32 @@ fn codegen(
             };
         }
 
+        // finishes an assert that has jumped to $ok_label if it passed, leaving its $operands on
+        // the stack. A failure prints them along with what failed and where, then errors.
+        macro_rules! assert_outcome {
+            ($name:expr, $details:expr, $operands:expr, $ok_label:expr) => {
+                let place = match debug.location {
+                    Some(location) => format!("on line {}", location.line),
+                    None => String::from("at an unknown location"),
+                };
+                let text = format!("{} {} failed with{}", $name, place, $details);
+                let mut fields = vec![Value::from(text.as_ref())];
+                fields.resize($operands + 1, Value::none());
+                cgen.code.push(opcode!(Noop, Value::new_tuple(fields)));
+                for index in (1..=$operands).rev() {
+                    cgen.code.push(opcode!(Tset, Value::from(index)));
+                }
+                cgen.code.push(opcode!(DebugPrint));
+                cgen.code.push(opcode!(Error));
+
+                // passing state
+                cgen.code.push(opcode!(@Label($ok_label)));
+                for _ in 0..$operands {
+                    cgen.code.push(opcode!(Pop));
+                }
+            };
+        }
+
         match node {
             TypeCheckedNode::Statement(stat) => {
                 match &mut stat.kind {
@@ -364,11 +386,7 @@ fn codegen(
                         expr!(expr);
                         cgen.code.push(opcode!(DebugPrint));
                     }
-                    TypeCheckedStatementKind::Assert(expr) => {
-                        if cgen.release_build {
-                            // Release builds don't include asserts
-                            continue;
-                        }
+                    TypeCheckedStatementKind::Assert(expr, _) => {
                         expr!(expr);
 
                         let ok_label = cgen.label_gen.next();
@@ -378,20 +396,28 @@ fn codegen(
                         cgen.code.push(opcode!(Tget, Value::from(0)));
                         cgen.code.push(opcode!(Cjump, Value::Label(ok_label)));
 
-                        // failure state
-                        let line = debug.location.expect("no location").line;
-                        let text = format!("assert on line {} failed with", line);
-                        let tuple =
-                            Value::new_tuple(vec![Value::from(text.as_ref()), Value::none()]);
+                        // failure state, which prints the value paired with the condition
                         cgen.code.push(opcode!(Tget, Value::from(1)));
-                        cgen.code.push(opcode!(Noop, tuple));
-                        cgen.code.push(opcode!(Tset, Value::from(1)));
-                        cgen.code.push(opcode!(DebugPrint));
-                        cgen.code.push(opcode!(Error));
+                        assert_outcome!("assert", "", 1, ok_label);
+                    }
+                    TypeCheckedStatementKind::AssertEq(left, right, equal, types) => {
+                        expr!(left);
+                        expr!(right);
 
-                        // passing state
-                        cgen.code.push(opcode!(@Label(ok_label)));
-                        cgen.code.push(opcode!(Pop));
+                        let ok_label = cgen.label_gen.next();
+
+                        // Compare copies of the operands
+                        cgen.code.push(opcode!(Dup1));
+                        cgen.code.push(opcode!(Dup1));
+                        cgen.code.push(opcode!(Equal));
+                        if !*equal {
+                            cgen.code.push(opcode!(IsZero));
+                        }
+                        cgen.code.push(opcode!(Cjump, Value::Label(ok_label)));
+
+                        // failure state, which prints both operands
+                        let name = if *equal { "assert_eq" } else { "assert_ne" };
+                        assert_outcome!(name, format!(" {}", types), 2, ok_label);
                     }
                     TypeCheckedStatementKind::While(cond, body) => {
                         let loop_slot = cgen.next_slot();
@@ -477,6 +503,9 @@ fn codegen(
                     TypeCheckedExprKind::Const(val, _) => {
                         cgen.code.push(opcode!(Noop, val.clone()))
                     }
+                    TypeCheckedExprKind::IncludeBytes(path) => {
+                        error!("include of {} wasn't read before codegen", path)
+                    }
                     TypeCheckedExprKind::Quote(bytes) => {
                         cgen.code.push(opcode!(
                             Noop,
@@ -529,6 +558,22 @@ fn codegen(
                         }
                     }
                     TypeCheckedExprKind::TupleRef(expr, offset, width, _) => {
+                        // transforms after typechecking may substitute the tuple being indexed,
+                        // so check the access against it whenever its fields are known
+                        let fields = match &expr.kind {
+                            TypeCheckedExprKind::Tuple(fields, _) => Some(fields.len()),
+                            TypeCheckedExprKind::Const(Value::Tuple(fields), _) => {
+                                Some(fields.len())
+                            }
+                            _ => None,
+                        };
+                        if offset >= width || fields.map_or(false, |fields| fields != *width) {
+                            error!(
+                                "tuple of {} fields indexed at {}",
+                                fields.unwrap_or(*width),
+                                offset
+                            );
+                        }
                         expr!(expr);
                         cgen.code.push(opcode!(@TupleGet(*offset, *width)));
                     }
@@ -556,6 +601,14 @@ fn codegen(
                             tuple_size *= 8;
                         }
                     }
+                    TypeCheckedExprKind::FixedArrayRef(expr1, expr2, size, _)
+                        if *size <= TUPLE_SIZE && constant_index(expr2, *size).is_some() =>
+                    {
+                        // a small array is a single tuple, so a known index reads its slot directly
+                        let index = constant_index(expr2, *size).unwrap();
+                        expr!(expr1);
+                        cgen.code.push(opcode!(Tget, Value::from(index)));
+                    }
                     TypeCheckedExprKind::FixedArrayRef(expr1, expr2, size, _) => {
                         expr!(expr1, 0);
                         expr!(expr2, 1);
@@ -854,3 +907,153 @@ fn codegen(
 
     Ok(())
 }
+
+/// Returns the index `expr` always evaluates to, if it's a constant within an array of `size`.
+fn constant_index(expr: &TypeCheckedExpr, size: usize) -> Option<usize> {
+    match &expr.kind {
+        TypeCheckedExprKind::Const(Value::Int(index), _) => {
+            index.to_usize().filter(|index| *index < size)
+        }
+        _ => None,
+    }
+}
+
+/// Generates the code of a func `main` that takes no args and returns `expr`, a `uint`.
+#[cfg(test)]
+fn codegen_returning(
+    expr: TypeCheckedExpr,
+) -> Result<(Vec<Instruction>, LabelGenerator, u32), CompileError> {
+    use super::ast::FuncProperties;
+    use super::typecheck::TypeCheckedStatement;
+    use std::collections::BTreeSet;
+
+    let debug_info = expr.debug_info;
+    let prop = FuncProperties::new(false, false, false, false, true, 0, 1);
+    let func = TypeCheckedFunc {
+        name: String::from("main"),
+        id: 0,
+        args: vec![],
+        ret_type: Type::Uint,
+        code: vec![TypeCheckedStatement {
+            kind: TypeCheckedStatementKind::Return(expr),
+            debug_info,
+        }],
+        tipe: Type::Func(prop, vec![], Box::new(Type::Uint)),
+        public: false,
+        captures: BTreeSet::new(),
+        generics: vec![],
+        unique_id: Some(0),
+        properties: prop,
+        debug_info,
+    };
+    mavm_codegen_func(func, &StringTable::new(), &HashMap::new(), &HashMap::new())
+}
+
+#[test]
+fn test_tuple_bounds() {
+    let debug_info = DebugInfo::default();
+    let access = |offset: usize, width: usize| {
+        // a pair standing in for a wider tuple, as a transform substituting a value might leave
+        let pair = TypeCheckedExpr::new(
+            TypeCheckedExprKind::Const(
+                Value::new_tuple(vec![Value::from(3u64), Value::from(4u64)]),
+                Type::Tuple(vec![Type::Uint, Type::Uint]),
+            ),
+            debug_info,
+        );
+        let expr = TypeCheckedExpr::new(
+            TypeCheckedExprKind::TupleRef(Box::new(pair), offset, width, Type::Uint),
+            debug_info,
+        );
+        codegen_returning(expr)
+    };
+
+    assert!(access(1, 2).is_ok());
+    assert!(access(2, 3).is_err());
+    assert!(access(2, 2).is_err());
+}
+
+#[test]
+fn test_tuple_bounds_after_simplifying() {
+    use super::simplify;
+
+    let debug_info = DebugInfo::default();
+    let expr = |kind| TypeCheckedExpr::new(kind, debug_info);
+    let pair = expr(TypeCheckedExprKind::Const(
+        Value::new_tuple(vec![Value::from(3u64), Value::from(4u64)]),
+        Type::Tuple(vec![Type::Uint, Type::Uint]),
+    ));
+
+    // an unwrapped option claiming a triple, which simplifying replaces with the pair it wraps
+    let triple = Type::Tuple(vec![Type::Uint; 3]);
+    let unwrapped = expr(TypeCheckedExprKind::Try(
+        Box::new(expr(TypeCheckedExprKind::Variant(Box::new(pair)))),
+        triple,
+    ));
+    let mut access = expr(TypeCheckedExprKind::TupleRef(
+        Box::new(unwrapped),
+        2,
+        3,
+        Type::Uint,
+    ));
+    simplify::fold_redundant_options(&mut access);
+
+    match &access.kind {
+        TypeCheckedExprKind::TupleRef(tuple, ..) => match &tuple.kind {
+            TypeCheckedExprKind::Const(Value::Tuple(fields), _) => assert_eq!(fields.len(), 2),
+            other => panic!("option wasn't folded: {:?}", other),
+        },
+        other => panic!("tuple access was rewritten: {:?}", other),
+    }
+    assert!(codegen_returning(access).is_err());
+}
+
+#[test]
+fn test_small_fixed_array_access() {
+    let debug_info = DebugInfo::default();
+    let access = |index: TypeCheckedExprKind| {
+        let array_type = Type::FixedArray(Box::new(Type::Uint), 4);
+        let array = TypeCheckedExpr::new(
+            TypeCheckedExprKind::Const(
+                Value::new_tuple(vec![Value::from(7u64); TUPLE_SIZE]),
+                array_type.clone(),
+            ),
+            debug_info,
+        );
+        let expr = TypeCheckedExpr::new(
+            TypeCheckedExprKind::FixedArrayRef(
+                Box::new(array),
+                Box::new(TypeCheckedExpr::new(index, debug_info)),
+                4,
+                Type::Uint,
+            ),
+            debug_info,
+        );
+        let (code, ..) = codegen_returning(expr).unwrap();
+        code
+    };
+
+    let two = Value::from(2usize);
+    let known = access(TypeCheckedExprKind::Const(two.clone(), Type::Uint));
+    let unknown = access(TypeCheckedExprKind::Cast(
+        Box::new(TypeCheckedExpr::new(
+            TypeCheckedExprKind::Const(two.clone(), Type::Uint),
+            debug_info,
+        )),
+        Type::Uint,
+    ));
+    let out_of_bounds = access(TypeCheckedExprKind::Const(Value::from(4usize), Type::Uint));
+
+    let has = |code: &Vec<Instruction>, opcode: AVMOpcode| {
+        code.iter()
+            .any(|insn| insn.opcode == Opcode::AVMOpcode(opcode))
+    };
+    assert!(known.len() < unknown.len());
+    assert!(!has(&known, AVMOpcode::Error));
+    assert!(has(&unknown, AVMOpcode::Error));
+    assert!(has(&out_of_bounds, AVMOpcode::Error));
+    assert!(known
+        .iter()
+        .any(|insn| insn.opcode == Opcode::AVMOpcode(AVMOpcode::Tget)
+            && insn.immediate == Some(two.clone())));
+}
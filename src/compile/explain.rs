@@ -0,0 +1,87 @@
+/*
+ * Copyright 2021, Offchain Labs, Inc. All rights reserved.
+ */
+
+//! Extended explanations for the error codes attached to some `CompileError`s
+
+/// An expression's type differs from the type required where it's used.
+pub const TYPE_MISMATCH: &str = "E0001";
+/// A func with a return type can reach its end without returning a value.
+pub const MISSING_RETURN: &str = "E0002";
+/// A name doesn't refer to any local, global, func, or import in scope.
+pub const UNRECOGNIZED_IDENTIFIER: &str = "E0003";
+
+/// Each error code paired with the text printed by `mini compile --explain <code>`.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        TYPE_MISMATCH,
+        "\
+A value was used where a different type is required.
+
+Erroneous code example:
+
+    func main() -> uint {
+        let flag = true;
+        return flag;    // a bool is returned from a func that returns uint
+    }
+
+Every assignment, return, and if-else must produce a type assignable to the one
+expected at that position. The error lists both types along with the first
+place they disagree, which for tuples and structs names the differing field.
+
+To fix this, convert the value with a cast, e.g. `uint(flag)`, or change the
+declared type so the two agree.",
+    ),
+    (
+        MISSING_RETURN,
+        "\
+A func that declares a return type can reach its end without returning.
+
+Erroneous code example:
+
+    func double(x: uint) -> uint {
+        if x > 0 {
+            return 2 * x;
+        }
+    }
+
+Mini requires every path through a func with a return type to end in a
+`return`, since falling off the end would leave no value for the caller. An
+`if` counts only when it has an `else` and both of its branches return.
+
+To fix this, finish the func with `return` and the value to give back, or
+remove the return type if the func isn't meant to produce one.",
+    ),
+    (
+        UNRECOGNIZED_IDENTIFIER,
+        "\
+A name was used that isn't defined anywhere visible from this point.
+
+Erroneous code example:
+
+    func main() -> uint {
+        return total;    // no local, global, or func named total exists
+    }
+
+Names must be declared before use: locals with `let`, globals with `var`, and
+funcs or types from other modules with a `use` statement at the top of the file.
+
+To fix this, check the spelling, declare the name, or import it, e.g.
+`use std::queue::queue_new;`.",
+    ),
+];
+
+/// Returns the extended explanation for an error code such as `E0001`, if one exists.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, text)| *text)
+}
+
+#[test]
+fn test_explain() {
+    assert!(explain(TYPE_MISMATCH).unwrap().contains("cast"));
+    assert!(explain("e0002").is_some());
+    assert!(explain("E9999").is_none());
+}
@@ -0,0 +1,202 @@
+/*
+ * Copyright 2021, Offchain Labs, Inc. All rights reserved.
+ */
+
+//! Hoists loop-invariant computations out of loops
+
+use super::ast::{AssignRef, BinaryOp};
+use super::typecheck::{
+    AbstractSyntaxTree, TypeCheckedExpr, TypeCheckedExprKind, TypeCheckedNode,
+    TypeCheckedStatement, TypeCheckedStatementKind,
+};
+use crate::stringtable::{StringId, StringTable};
+use std::collections::BTreeSet;
+
+/// Moves computations inside each loop of `code` that don't depend on anything the loop
+/// changes into locals bound just before the loop, so they're evaluated once rather than on every
+/// iteration. Nested loops are handled outermost first. The `count` argument numbers the locals
+/// created, and is advanced for each.
+pub fn hoist_loop_invariants(
+    code: &mut Vec<TypeCheckedStatement>,
+    string_table: &mut StringTable,
+    count: &mut usize,
+) {
+    let mut hoisted_code = Vec::with_capacity(code.len());
+
+    for mut stat in code.drain(..) {
+        let is_loop = match &stat.kind {
+            TypeCheckedStatementKind::While(..) => true,
+            TypeCheckedStatementKind::Expression(expr) => {
+                matches!(expr.kind, TypeCheckedExprKind::Loop(..))
+            }
+            _ => false,
+        };
+        if is_loop {
+            let mut bound = BTreeSet::new();
+            bound_locals(&mut stat, &mut bound);
+
+            let mut hoisted = vec![];
+            extract_invariants(&mut stat, &bound, &mut hoisted, string_table, count);
+
+            for (id, expr) in hoisted {
+                let debug_info = expr.debug_info;
+                hoisted_code.push(TypeCheckedStatement {
+                    kind: TypeCheckedStatementKind::SetLocals(
+                        vec![AssignRef::new(id, true, debug_info)],
+                        expr,
+                    ),
+                    debug_info,
+                });
+            }
+        }
+        match &mut stat.kind {
+            TypeCheckedStatementKind::While(cond, block) => {
+                hoist_in_nested_blocks(cond, string_table, count);
+                hoist_loop_invariants(&mut block.body, string_table, count);
+                if let Some(expr) = &mut block.ret_expr {
+                    hoist_in_nested_blocks(&mut **expr, string_table, count);
+                }
+            }
+            _ => hoist_in_nested_blocks(&mut stat, string_table, count),
+        }
+        hoisted_code.push(stat);
+    }
+
+    *code = hoisted_code;
+}
+
+/// Finds every statement list nested beneath `node` and hoists the loop invariants within.
+fn hoist_in_nested_blocks<T: AbstractSyntaxTree>(
+    node: &mut T,
+    string_table: &mut StringTable,
+    count: &mut usize,
+) {
+    for child in node.child_nodes() {
+        match child {
+            TypeCheckedNode::Statement(stat) => hoist_in_nested_blocks(stat, string_table, count),
+            TypeCheckedNode::Expression(expr) => match &mut expr.kind {
+                TypeCheckedExprKind::CodeBlock(block) | TypeCheckedExprKind::Loop(block, _) => {
+                    hoist_loop_invariants(&mut block.body, string_table, count);
+                    if let Some(expr) = &mut block.ret_expr {
+                        hoist_in_nested_blocks(&mut **expr, string_table, count);
+                    }
+                }
+                TypeCheckedExprKind::If(cond, block, else_block, _)
+                | TypeCheckedExprKind::IfLet(_, cond, block, else_block, _) => {
+                    hoist_in_nested_blocks(&mut **cond, string_table, count);
+                    for block in std::iter::once(block).chain(else_block) {
+                        hoist_loop_invariants(&mut block.body, string_table, count);
+                        if let Some(expr) = &mut block.ret_expr {
+                            hoist_in_nested_blocks(&mut **expr, string_table, count);
+                        }
+                    }
+                }
+                _ => hoist_in_nested_blocks(expr, string_table, count),
+            },
+            TypeCheckedNode::Type(_) => {}
+        }
+    }
+}
+
+/// Collects every local that's bound or assigned anywhere beneath `node`.
+fn bound_locals<T: AbstractSyntaxTree>(node: &mut T, bound: &mut BTreeSet<StringId>) {
+    for mut child in node.child_nodes() {
+        match &child {
+            TypeCheckedNode::Statement(stat) => {
+                if let TypeCheckedStatementKind::SetLocals(assigned, _) = &stat.kind {
+                    bound.extend(assigned.iter().map(|assigned| assigned.id));
+                }
+            }
+            TypeCheckedNode::Expression(expr) => {
+                if let TypeCheckedExprKind::IfLet(id, ..) = &expr.kind {
+                    bound.insert(*id);
+                }
+            }
+            TypeCheckedNode::Type(_) => {}
+        }
+        bound_locals(&mut child, bound);
+    }
+}
+
+/// Replaces each maximal invariant computation beneath `node` with a reference to a new local,
+/// recording the local and the computation it stands for in `hoisted`. Identical computations
+/// share a local.
+fn extract_invariants<T: AbstractSyntaxTree>(
+    node: &mut T,
+    bound: &BTreeSet<StringId>,
+    hoisted: &mut Vec<(StringId, TypeCheckedExpr)>,
+    string_table: &mut StringTable,
+    count: &mut usize,
+) {
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &mut child {
+            if is_invariant(expr, bound) && !is_trivial(expr) {
+                let id = match hoisted.iter().find(|(_, prior)| prior.kind == expr.kind) {
+                    Some((id, _)) => *id,
+                    None => {
+                        let id = string_table.get(format!("_invariant#{}", count));
+                        *count += 1;
+                        hoisted.push((id, expr.clone()));
+                        id
+                    }
+                };
+                expr.kind = TypeCheckedExprKind::LocalVariableRef(id, expr.get_type());
+                continue;
+            }
+        }
+        extract_invariants(&mut child, bound, hoisted, string_table, count);
+    }
+}
+
+/// Determines whether `expr` computes the same value on every iteration of a loop that binds the
+/// locals in `bound`. Only computations without side effects that can never trap qualify, since a
+/// hoisted computation runs even when the loop body wouldn't have.
+fn is_invariant(expr: &TypeCheckedExpr, bound: &BTreeSet<StringId>) -> bool {
+    match &expr.kind {
+        TypeCheckedExprKind::LocalVariableRef(id, _) => !bound.contains(id),
+        TypeCheckedExprKind::Const(..)
+        | TypeCheckedExprKind::FuncRef(..)
+        | TypeCheckedExprKind::Quote(..)
+        | TypeCheckedExprKind::NewBuffer => true,
+        TypeCheckedExprKind::UnaryOp(_, inner, _)
+        | TypeCheckedExprKind::Variant(inner)
+        | TypeCheckedExprKind::Cast(inner, _)
+        | TypeCheckedExprKind::TupleRef(inner, ..)
+        | TypeCheckedExprKind::NewFixedArray(_, inner, _) => is_invariant(inner, bound),
+        TypeCheckedExprKind::Binary(op, left, right, _) => {
+            !matches!(
+                op,
+                BinaryOp::Div
+                    | BinaryOp::Mod
+                    | BinaryOp::Sdiv
+                    | BinaryOp::Smod
+                    | BinaryOp::GetBuffer8
+                    | BinaryOp::GetBuffer64
+                    | BinaryOp::GetBuffer256
+            ) && is_invariant(left, bound)
+                && is_invariant(right, bound)
+        }
+        TypeCheckedExprKind::ShortcutOr(left, right)
+        | TypeCheckedExprKind::ShortcutAnd(left, right)
+        | TypeCheckedExprKind::StructMod(left, _, _, right, _) => {
+            is_invariant(left, bound) && is_invariant(right, bound)
+        }
+        TypeCheckedExprKind::Tuple(fields, _) => {
+            fields.iter().all(|field| is_invariant(field, bound))
+        }
+        _ => false,
+    }
+}
+
+/// Determines whether `expr` is cheap enough that a local couldn't hold it for less.
+fn is_trivial(expr: &TypeCheckedExpr) -> bool {
+    match &expr.kind {
+        TypeCheckedExprKind::LocalVariableRef(..)
+        | TypeCheckedExprKind::Const(..)
+        | TypeCheckedExprKind::FuncRef(..)
+        | TypeCheckedExprKind::Quote(..)
+        | TypeCheckedExprKind::NewBuffer => true,
+        TypeCheckedExprKind::Cast(inner, _) => is_trivial(inner),
+        _ => false,
+    }
+}
@@ -25,6 +25,32 @@ pub struct ConstantsFile {
     parameters_hex: BTreeMap<String, String>,
 }
 
+/// Reads the constants file at `constants_path`.
+pub fn read_constants_file(constants_path: &Path) -> Result<ConstantsFile, CompileError> {
+    let mut file = File::open(constants_path).map_err(|_| {
+        CompileError::new(
+            String::from("Compile error"),
+            format!("Could not open constants file {:?}", constants_path),
+            vec![],
+        )
+    })?;
+    let mut consts_string = String::new();
+    file.read_to_string(&mut consts_string).map_err(|_| {
+        CompileError::new(
+            String::from("Compile error"),
+            format!("Could not read file {:?} to a string", constants_path),
+            vec![],
+        )
+    })?;
+    serde_json::from_str::<ConstantsFile>(&consts_string).map_err(|_| {
+        CompileError::new(
+            String::from("Compile error"),
+            format!("Could not parse {:?} as constants file", constants_path),
+            vec![],
+        )
+    })
+}
+
 /// Creates a fixed list of globally accessible constants.
 pub fn init_constant_table(
     constants_path: Option<&Path>,
@@ -32,28 +58,7 @@ pub fn init_constant_table(
     let mut ret = HashMap::new();
 
     let consts = if let Some(consts_file) = constants_path {
-        let mut file = File::open(consts_file).map_err(|_| {
-            CompileError::new(
-                String::from("Compile error"),
-                format!("Could not open constants file {:?}", consts_file),
-                vec![],
-            )
-        })?;
-        let mut consts_string = String::new();
-        file.read_to_string(&mut consts_string).map_err(|_| {
-            CompileError::new(
-                String::from("Compile error"),
-                format!("Could not read file {:?} to a string", consts_file),
-                vec![],
-            )
-        })?;
-        serde_json::from_str::<ConstantsFile>(&consts_string).map_err(|_| {
-            CompileError::new(
-                String::from("Compile error"),
-                format!("Could not parse {:?} as constants file", consts_file),
-                vec![],
-            )
-        })?
+        read_constants_file(consts_file)?
     } else {
         ConstantsFile::default()
     };
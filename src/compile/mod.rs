@@ -5,12 +5,15 @@
 //! Contains utilities for compiling mini source code.
 
 use crate::console::Color;
-use crate::link::{link, postlink_compile, Import, LinkedProgram};
+use crate::link::{
+    link, postlink_compile, Import, LinkOptions, LinkedProgram, PostlinkOptions,
+    SerializableTypeTree,
+};
 use crate::mavm::{Instruction, Label, LabelId};
 use crate::optimize::BasicGraph;
 use crate::pos::{BytePos, Location};
 use crate::stringtable::{StringId, StringTable};
-use ast::Func;
+use ast::{AssertLevel, Func, Lints};
 use clap::Clap;
 use lalrpop_util::lalrpop_mod;
 use lalrpop_util::ParseError;
@@ -19,25 +22,32 @@ use miniconstants::init_constant_table;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::{self, Read};
-use std::path::Path;
-use typecheck::TypeCheckedFunc;
+use std::path::{Path, PathBuf};
+use typecheck::{TypeCheckedExprKind, TypeCheckedFunc};
 
 pub use ast::{DebugInfo, FuncProperties, GlobalVar, StructField, TopLevelDecl, Type, TypeTree};
 pub use codegen::{FrameSize, SlotNum};
+pub use explain::explain;
 pub use source::Lines;
 pub use typecheck::{AbstractSyntaxTree, TypeCheckedNode};
+pub use xref::SymbolIndex;
 
 mod ast;
+mod cfg;
 mod codegen;
+mod explain;
+mod licm;
 pub mod miniconstants;
+mod simplify;
 mod source;
 mod translate;
 mod typecheck;
+mod xref;
 lalrpop_mod!(mini);
 
 /// Command line options for compile subcommand.
@@ -60,8 +70,52 @@ pub struct CompileStruct {
     pub must_use_global_consts: bool,
     #[clap(short, long)]
     pub release_build: bool,
+    #[clap(long)]
+    pub assert_level: Option<String>,
     #[clap(short, long)]
     pub no_builtins: bool,
+    #[clap(short, long, number_of_values = 1)]
+    pub entry_points: Vec<String>,
+    #[clap(short, long)]
+    pub verify: bool,
+    #[clap(short, long)]
+    pub globals_layout: bool,
+    #[clap(short, long)]
+    pub layout_by_usage: bool,
+    #[clap(long)]
+    pub merge_funcs: bool,
+    #[clap(long)]
+    pub explain: Option<String>,
+    #[clap(long)]
+    pub symbol_index: Option<String>,
+    #[clap(long)]
+    pub json_schema: Option<String>,
+    #[clap(long)]
+    pub call_graph: Option<String>,
+    #[clap(long)]
+    pub require_mut: bool,
+    #[clap(long)]
+    pub warn_unimplemented: bool,
+    #[clap(long)]
+    pub no_implicit_any: bool,
+    #[clap(long)]
+    pub check: bool,
+    #[clap(long, possible_values = &["deps", "mao"])]
+    pub emit: Option<String>,
+    #[clap(long)]
+    pub arbos_version: Option<u64>,
+    #[clap(long)]
+    pub schedule_pure: bool,
+    #[clap(long)]
+    pub peephole_passes: Option<usize>,
+    #[clap(long)]
+    pub optimizer_listings: Option<String>,
+    #[clap(long)]
+    pub max_tuple_depth: Option<usize>,
+    #[clap(long)]
+    pub max_include_size: Option<usize>,
+    #[clap(long)]
+    pub diagnostic_summary: Option<String>,
 }
 
 /// Represents the contents of a source file after parsing.
@@ -71,6 +125,8 @@ struct Module {
     funcs: Vec<Func>,
     /// Map from `StringId`s in this file to the `Type`s they represent.
     named_types: HashMap<StringId, Type>,
+    /// Map from the `StringId`s of the types declared in this file to where they're declared.
+    type_locations: HashMap<StringId, Location>,
     /// List of constants used in this file.
     constants: HashSet<String>,
     /// List of global variables defined within this file.
@@ -110,29 +166,255 @@ struct TypeCheckedModule {
 }
 
 impl CompileStruct {
+    /// Compiles the input files, returning the linked program when one could be produced along
+    /// with every error and warning found.
+    pub fn compile(&self) -> CompileOutcome {
+        self.compile_in_session(&mut CompileSession::default())
+    }
+
+    /// Compiles the input files as `compile` does, reusing the library modules `session` parsed
+    /// for earlier compilations and keeping those it parses now.
+    pub fn compile_in_session(&self, session: &mut CompileSession) -> CompileOutcome {
+        let mut error_system = self.error_system();
+        let mut file_info_chart = BTreeMap::new();
+
+        let linked_prog = match self.link_inputs(&mut error_system, &mut file_info_chart, session) {
+            Some(program) => program,
+            None => return CompileOutcome::new(None, error_system, file_info_chart),
+        };
+
+        let postlinked_prog = match postlink_compile(
+            linked_prog,
+            file_info_chart.clone(),
+            &mut error_system,
+            &self.postlink_options(),
+        ) {
+            Ok(idk) => idk,
+            Err(err) => {
+                error_system.errors.push(err);
+                return CompileOutcome::new(None, error_system, file_info_chart);
+            }
+        };
+
+        if error_system.warnings.len() > 0 && error_system.warnings_are_errors {
+            error_system.errors.push(CompileError::new(
+                "Compile Error",
+                "Found warning with -w on",
+                vec![],
+            ));
+        }
+
+        // errors the parser defers, like unreadable includes, don't halt compilation early
+        match error_system.errors.is_empty() {
+            true => CompileOutcome::new(Some(postlinked_prog), error_system, file_info_chart),
+            false => CompileOutcome::new(None, error_system, file_info_chart),
+        }
+    }
+
+    /// Compiles the input files as `compile` does, but with the errors and warnings kept in an
+    /// `ErrorSystem` that's returned on its own when no program was produced.
     pub fn invoke(&self) -> Result<(LinkedProgram, ErrorSystem), ErrorSystem> {
-        // Initialize rayon to use a large stack size. We do this here rather than
-        // main() so that tests are affected.
-        drop(
-            rayon::ThreadPoolBuilder::new()
-                .stack_size(4 * 8192 * 1024)
-                .build_global(),
+        let mut error_system = self.error_system();
+        let mut file_info_chart = BTreeMap::new();
+        let program = error_system.absorb(self.compile(), &mut file_info_chart);
+        error_system.file_info_chart = file_info_chart;
+
+        match program {
+            Some(program) => Ok((program, error_system)),
+            None => Err(error_system),
+        }
+    }
+
+    /// Compiles and links the input files, stopping short of the post-link steps. The program
+    /// returned is what `--emit=mao` writes, and can be finished later by giving its .mao file as
+    /// the only input.
+    pub fn link_only(&self) -> Result<(CompiledProgram, ErrorSystem), ErrorSystem> {
+        let mut error_system = self.error_system();
+        let mut file_info_chart = BTreeMap::new();
+
+        let linked_prog = self.link_inputs(
+            &mut error_system,
+            &mut file_info_chart,
+            &mut CompileSession::default(),
         );
+        error_system.file_info_chart = file_info_chart;
+        if error_system.warnings.len() > 0 && error_system.warnings_are_errors {
+            error_system.errors.push(CompileError::new(
+                "Compile Error",
+                "Found warning with -w on",
+                vec![],
+            ));
+        }
 
-        let mut error_system = ErrorSystem {
+        match linked_prog {
+            Some(program) if error_system.errors.is_empty() => Ok((program, error_system)),
+            _ => Err(error_system),
+        }
+    }
+
+    /// Checks the inputs for errors and warnings as `check_from_folder` does, with the options a
+    /// compile of them would use, and without linking or producing any output.
+    pub fn check_only(&self) -> Result<ErrorSystem, ErrorSystem> {
+        let mut error_system = self.error_system();
+        let mut file_info_chart = BTreeMap::new();
+
+        if let Some(options) = self.compile_options(&mut error_system) {
+            for input in &self.input {
+                match locate_main(Path::new(input)) {
+                    Ok((folder, _, main)) => check_from_folder(
+                        folder,
+                        main,
+                        self.consts_file.as_deref().map(Path::new),
+                        &mut file_info_chart,
+                        &mut error_system,
+                        &options,
+                    ),
+                    Err(error) => error_system.errors.push(error),
+                }
+            }
+        }
+        error_system.file_info_chart = file_info_chart;
+        if error_system.warnings.len() > 0 && error_system.warnings_are_errors {
+            error_system.errors.push(CompileError::new(
+                "Compile Error",
+                "Found warning with -w on",
+                vec![],
+            ));
+        }
+
+        match error_system.errors.is_empty() {
+            true => Ok(error_system),
+            false => Err(error_system),
+        }
+    }
+
+    /// Creates an empty `ErrorSystem` colored according to whether warnings are errors.
+    fn error_system(&self) -> ErrorSystem {
+        ErrorSystem {
             errors: vec![],
             warnings: vec![],
+            notes: vec![],
             warnings_are_errors: self.warnings_are_errors,
             warn_color: match self.warnings_are_errors {
                 true => Color::PINK,
                 false => Color::YELLOW,
             },
             file_info_chart: BTreeMap::new(),
-        };
+        }
+    }
+
+    /// The settings the modules of the inputs are checked and lowered with. Returns `None` if the
+    /// options given are invalid, which is recorded in `error_system`.
+    fn compile_options(&self, error_system: &mut ErrorSystem) -> Option<CompileOptions> {
+        Some(CompileOptions {
+            must_use_global_consts: self.must_use_global_consts,
+            asserts: match self.assert_level.as_deref() {
+                None if self.release_build => None,
+                None => Some(AssertLevel::Debug),
+                Some("none") => None,
+                Some(name) => match AssertLevel::from_name(name) {
+                    Some(level) => Some(level),
+                    None => {
+                        error_system.errors.push(CompileError::new(
+                            "Compile error",
+                            format!(
+                                "Unknown assert level {}, expected none, release, or debug",
+                                Color::red(name)
+                            ),
+                            vec![],
+                        ));
+                        return None;
+                    }
+                },
+            },
+            builtins: !self.no_builtins,
+            require_mut: self.require_mut,
+            warn_unimplemented: self.warn_unimplemented,
+            no_implicit_any: self.no_implicit_any,
+            max_include_size: self.max_include_size.unwrap_or(DEFAULT_MAX_INCLUDE_SIZE),
+            max_tuple_depth: self.max_tuple_depth,
+        })
+    }
+
+    /// The settings `postlink_compile` runs with.
+    fn postlink_options(&self) -> PostlinkOptions<'_> {
+        PostlinkOptions {
+            test_mode: self.test_mode,
+            debug: self.debug_mode,
+            verify: self.verify,
+            globals_layout: self.globals_layout,
+            peephole_passes: self.peephole_passes.unwrap_or(1),
+            schedule_pure: self.schedule_pure,
+            optimizer_listings: self.optimizer_listings.as_deref(),
+            max_tuple_depth: self.max_tuple_depth,
+            arbos_version: self.arbos_version,
+            constants_path: self.constants_file(),
+        }
+    }
+
+    /// The constants file the inputs are compiled with, which is `consts_file` when given and
+    /// otherwise the `constants.json` of the first input's folder, if it has one.
+    fn constants_file(&self) -> Option<PathBuf> {
+        match &self.consts_file {
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                let (folder, _, _) = locate_main(Path::new(self.input.first()?)).ok()?;
+                default_constants(folder, None)
+            }
+        }
+    }
+
+    /// Produces the linked program for the inputs, which are either mini sources or a lone .mao
+    /// file. Returns `None` if errors, which are recorded in `error_system`, stopped it from being
+    /// produced.
+    fn link_inputs(
+        &self,
+        error_system: &mut ErrorSystem,
+        file_info_chart: &mut BTreeMap<u64, FileInfo>,
+        session: &mut CompileSession,
+    ) -> Option<CompiledProgram> {
+        // Initialize rayon to use a large stack size. We do this here rather than
+        // main() so that tests are affected.
+        drop(
+            rayon::ThreadPoolBuilder::new()
+                .stack_size(4 * 8192 * 1024)
+                .build_global(),
+        );
+
+        let is_mao = |filename: &String| Path::new(filename).extension() == Some("mao".as_ref());
+        match self.input.as_slice() {
+            [filename] if is_mao(filename) => {
+                let read = File::open(filename)
+                    .map_err(|why| why.to_string())
+                    .and_then(|mut file| CompiledProgram::read_mao(&mut file));
+                return match read {
+                    Ok(program) => Some(program),
+                    Err(why) => {
+                        error_system.errors.push(CompileError::new(
+                            "Compile error",
+                            format!("Could not read {}: {}", Color::red(filename), why),
+                            vec![],
+                        ));
+                        None
+                    }
+                };
+            }
+            inputs if inputs.iter().any(is_mao) => {
+                error_system.errors.push(CompileError::new(
+                    "Compile error",
+                    "A .mao file must be the only input",
+                    vec![],
+                ));
+                return None;
+            }
+            _ => {}
+        }
+
+        let options = self.compile_options(error_system)?;
 
         let mut unlinked_progs = vec![];
-        let mut file_info_chart = BTreeMap::new();
         let mut globals = vec![];
+        let mut symbol_index = SymbolIndex::new();
 
         for filename in &self.input {
             let path = Path::new(filename);
@@ -140,69 +422,185 @@ impl CompileStruct {
                 Some(path) => Some(Path::new(path)),
                 None => None,
             };
-            let (progs, all_globals) = match compile_from_file(
+            let outcome = compile_from_file(
                 path,
-                &mut file_info_chart,
                 constants_path,
-                self.must_use_global_consts,
-                &mut error_system,
-                self.release_build,
-                !self.no_builtins,
-            ) {
-                Ok(idk) => idk,
-                Err(err) => {
-                    error_system.errors.push(err);
-                    error_system.file_info_chart = file_info_chart;
-                    return Err(error_system);
-                }
-            };
+                ErrorSystem {
+                    errors: vec![],
+                    warnings: vec![],
+                    notes: vec![],
+                    file_info_chart: BTreeMap::new(),
+                    ..*error_system
+                },
+                &options,
+                &mut symbol_index,
+                session,
+            );
+            let (progs, all_globals, _) = error_system.absorb(outcome, file_info_chart)?;
 
             globals = all_globals;
 
             unlinked_progs.extend(progs);
         }
 
-        // If this condition is true it means that __fixedLocationGlobal will not be at
-        // index [0], but rather [0][0] or [0][0][0] etc
-        if globals.len() >= 58 {
-            panic!("Too many globals defined in program, location of first global is not correct")
+        if let Some(path) = &self.symbol_index {
+            let written = File::create(path)
+                .map_err(|why| why.to_string())
+                .and_then(|file| {
+                    serde_json::to_writer(file, &symbol_index).map_err(|why| why.to_string())
+                });
+            if let Err(why) = written {
+                error_system.errors.push(CompileError::new(
+                    "Compile error",
+                    format!("Could not write symbol index to {}: {}", path, why),
+                    vec![],
+                ));
+            }
         }
 
-        let linked_prog = link(unlinked_progs, globals, &mut error_system, self.test_mode);
+        let prior_errors = error_system.errors.len();
+        let (program, schema) = link(
+            unlinked_progs,
+            globals,
+            error_system,
+            &LinkOptions {
+                test_mode: self.test_mode,
+                entry_points: &self.entry_points,
+                json_schema: self.json_schema.is_some(),
+                call_graph: self.call_graph.as_deref(),
+                weighted_layout: self.layout_by_usage,
+                merge_funcs: self.merge_funcs,
+            },
+        );
 
-        let postlinked_prog = match postlink_compile(
-            linked_prog,
-            file_info_chart.clone(),
-            self.test_mode,
-            self.debug_mode,
-        ) {
-            Ok(idk) => idk,
-            Err(err) => {
-                error_system.errors.push(err);
-                error_system.file_info_chart = file_info_chart;
-                return Err(error_system);
+        if let (Some(path), Some(schema)) = (&self.json_schema, schema) {
+            let written = File::create(path)
+                .map_err(|why| why.to_string())
+                .and_then(|file| {
+                    serde_json::to_writer_pretty(file, &schema).map_err(|why| why.to_string())
+                });
+            if let Err(why) = written {
+                error_system.errors.push(CompileError::new(
+                    "Compile error",
+                    format!("Could not write json schema to {}: {}", path, why),
+                    vec![],
+                ));
             }
-        };
+        }
+        match error_system.errors.len() == prior_errors {
+            true => Some(program),
+            false => None,
+        }
+    }
+}
 
-        error_system.file_info_chart = file_info_chart;
+/// The result of compiling a program, which carries every diagnostic even when compilation fails.
+/// The program is the linked one, except for `compile_from_folder` and `compile_from_file`, which
+/// stop at the modules' `CompiledModules`.
+pub struct CompileOutcome<T = LinkedProgram> {
+    /// The program, or `None` if errors stopped compilation before it was produced.
+    pub program: Option<T>,
+    /// All warnings followed by all errors.
+    pub diagnostics: Vec<CompileError>,
+    /// Remarks about what compilation did, as kept by `ErrorSystem`.
+    pub notes: Vec<String>,
+    /// The source files referenced by the diagnostics' locations.
+    pub file_info_chart: BTreeMap<u64, FileInfo>,
+}
 
-        if error_system.warnings.len() > 0 && error_system.warnings_are_errors {
-            error_system.errors.push(CompileError::new(
-                "Compile Error",
-                "Found warning with -w on",
-                vec![],
-            ));
-            Err(error_system)
-        } else {
-            Ok((postlinked_prog, error_system))
+impl<T> CompileOutcome<T> {
+    fn new(
+        program: Option<T>,
+        error_system: ErrorSystem,
+        file_info_chart: BTreeMap<u64, FileInfo>,
+    ) -> Self {
+        let mut diagnostics = error_system.warnings;
+        diagnostics.extend(error_system.errors);
+        Self {
+            program,
+            diagnostics,
+            notes: error_system.notes,
+            file_info_chart,
+        }
+    }
+}
+
+/// Settings that decide how the modules of a program are checked and lowered.
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    /// Whether every global constant must be used.
+    pub must_use_global_consts: bool,
+    /// The least essential asserts that are kept, or `None` if they're all stripped.
+    pub asserts: Option<AssertLevel>,
+    /// Whether the builtin modules are imported.
+    pub builtins: bool,
+    /// Whether locals that are reassigned must be declared `mut`.
+    pub require_mut: bool,
+    /// Whether to warn about funcs that are only stubs.
+    pub warn_unimplemented: bool,
+    /// Whether to warn when a type is implicitly widened to `any`.
+    pub no_implicit_any: bool,
+    /// The largest file `include_bytes` may embed.
+    pub max_include_size: usize,
+    /// Arrays created in funcs whose default values would nest tuples deeper than this are
+    /// rejected.
+    pub max_tuple_depth: Option<usize>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            must_use_global_consts: false,
+            asserts: Some(AssertLevel::Debug),
+            builtins: true,
+            require_mut: false,
+            warn_unimplemented: false,
+            no_implicit_any: false,
+            max_include_size: DEFAULT_MAX_INCLUDE_SIZE,
+            max_tuple_depth: None,
         }
     }
 }
 
+/// Library modules parsed by earlier compilations, which later compilations in the same session
+/// reuse rather than parsing them again. This speeds up compiling many programs in a row, like a
+/// test corpus. Only the modules of `core`, `std`, and `std2` are kept, since they rarely change
+/// between programs, and a session is meant to be used from one thread at a time. A module whose
+/// file or constants file has been edited since it was kept is parsed anew.
+///
+/// Only parsing is shared. Each compilation still reads and hashes the library files, then
+/// resolves, typechecks, and generates code for every module, libraries included, from scratch.
+#[derive(Default)]
+pub struct CompileSession {
+    /// Each module by the file it was read from, the constants file it used, and whether
+    /// builtins were imported.
+    modules: HashMap<(PathBuf, Option<PathBuf>, bool), CachedModule>,
+}
+
+impl CompileSession {
+    /// Returns how many library modules the session holds.
+    #[cfg(test)]
+    pub fn cached_len(&self) -> usize {
+        self.modules.len()
+    }
+}
+
+/// A library module as it was parsed, along with what parsing it produced besides the module.
+struct CachedModule {
+    /// A hash of the source the module was parsed from, to tell if its file has changed since.
+    source_hash: u64,
+    /// A hash of the constants file the module's constants were taken from.
+    constants_hash: u64,
+    module: Module,
+    file_info: FileInfo,
+    warnings: Vec<CompileError>,
+}
+
 impl Module {
     fn new(
         funcs: Vec<Func>,
         named_types: HashMap<usize, Type>,
+        type_locations: HashMap<usize, Location>,
         constants: HashSet<String>,
         global_vars: Vec<GlobalVar>,
         imports: Vec<Import>,
@@ -214,6 +612,7 @@ impl Module {
         Self {
             funcs,
             named_types,
+            type_locations,
             constants,
             global_vars,
             imports,
@@ -248,11 +647,98 @@ impl TypeCheckedModule {
         }
     }
 
-    /// Propagates inherited attributes down top-level decls.
-    fn propagate_attributes(&mut self) {
+    /// Removes the asserts of each func above the `kept` level, warning about those whose
+    /// conditions have side effects that still need to happen.
+    fn strip_asserts(
+        &mut self,
+        kept: Option<AssertLevel>,
+        type_tree: &TypeTree,
+        error_system: &mut ErrorSystem,
+    ) {
+        let discard = self.string_table.get("_".to_string());
+        for (_id, func) in &mut self.checked_funcs {
+            for location in simplify::strip_asserts(func, kept, discard, type_tree) {
+                error_system.warnings.push(CompileError::new_warning(
+                    "Compile warning",
+                    format!(
+                        "Stripped assert in func {} has side effects, which are kept",
+                        Color::color(error_system.warn_color, &func.name),
+                    ),
+                    vec![location],
+                ));
+            }
+        }
+    }
+
+    /// Rewrites constructs in each func that compute something simpler than they appear to,
+    /// such as options unwrapped immediately after being made.
+    fn simplify(&mut self) {
+        for (_id, func) in &mut self.checked_funcs {
+            simplify::fold_self_comparisons(func);
+            simplify::fold_redundant_options(func);
+            simplify::fold_bool_ifs(func);
+            simplify::fold_constant_loops(func);
+        }
+    }
+
+    /// Replaces each `include_bytes` in the module with the contents of the file it names, which
+    /// is found relative to the module's source file in `folder`. Files that can't be read or are
+    /// larger than `max_size` bytes are errors, and are replaced with nothing so that compilation
+    /// can go on to find other errors.
+    fn include_files(&mut self, folder: &Path, max_size: usize, error_system: &mut ErrorSystem) {
+        fn include(
+            node: &mut TypeCheckedNode,
+            folder: &Path,
+            max_size: usize,
+            error_system: &mut ErrorSystem,
+        ) {
+            if let TypeCheckedNode::Expression(expr) = node {
+                if let TypeCheckedExprKind::IncludeBytes(path) = &expr.kind {
+                    let path = folder.join(path);
+                    let included = match std::fs::read(&path) {
+                        Ok(bytes) if bytes.len() <= max_size => Ok(bytes),
+                        Ok(bytes) => Err(format!(
+                            "Included file {} is {} bytes, beyond the limit of {}",
+                            Color::red(path.display()),
+                            bytes.len(),
+                            max_size,
+                        )),
+                        Err(why) => Err(format!(
+                            "Could not include {}: {}",
+                            Color::red(path.display()),
+                            why
+                        )),
+                    };
+                    let bytes = included.unwrap_or_else(|failure| {
+                        error_system.errors.push(CompileError::new(
+                            "Compile error",
+                            failure,
+                            expr.debug_info.locs(),
+                        ));
+                        vec![]
+                    });
+                    expr.kind = TypeCheckedExprKind::Quote(bytes);
+                }
+            }
+            for mut child in node.child_nodes() {
+                include(&mut child, folder, max_size, error_system);
+            }
+        }
+
+        let source = folder.join(&self.name);
+        let folder = source.parent().unwrap_or(folder);
         for (_id, func) in &mut self.checked_funcs {
-            let attributes = func.debug_info.attributes.clone();
-            TypeCheckedNode::propagate_attributes(func.child_nodes(), &attributes);
+            for mut child in func.child_nodes() {
+                include(&mut child, folder, max_size, error_system);
+            }
+        }
+    }
+
+    /// Moves loop-invariant computations out of the loops in each func.
+    fn hoist_loop_invariants(&mut self) {
+        let mut count = 0;
+        for (_id, func) in &mut self.checked_funcs {
+            licm::hoist_loop_invariants(&mut func.code, &mut self.string_table, &mut count);
         }
     }
 
@@ -263,14 +749,17 @@ impl TypeCheckedModule {
         let mut imports: BTreeMap<StringId, Import> = BTreeMap::new();
 
         for import in self.imports.iter() {
-            let id = self.string_table.get_if_exists(&import.name).unwrap();
+            let id = self
+                .string_table
+                .get_if_exists(import.local_name())
+                .unwrap();
 
             if let Some(prior) = imports.get(&id) {
                 flow_warnings.push(CompileError::new_warning(
                     "Compile Warning",
                     format!(
                         "use statement {} is a duplicate",
-                        Color::color(error_system.warn_color, &import.name)
+                        Color::color(error_system.warn_color, import.local_name())
                     ),
                     prior
                         .location
@@ -310,7 +799,7 @@ impl TypeCheckedModule {
                 "Compile Warning",
                 format!(
                     "use statement {} is unnecessary",
-                    Color::color(error_system.warn_color, import.name)
+                    Color::color(error_system.warn_color, import.local_name())
                 ),
                 import.location.into_iter().collect(),
             ));
@@ -349,6 +838,10 @@ pub struct CompiledFunc {
     pub type_tree: TypeTree,
     /// This func's globally-unique identifier
     pub unique_id: LabelId,
+    /// Whether this func was declared public
+    pub public: bool,
+    /// This func's type, which gives its signature
+    pub tipe: Type,
     /// This func's debug info
     pub debug_info: DebugInfo,
 }
@@ -362,6 +855,8 @@ impl CompiledFunc {
         frame_size: FrameSize,
         globals: Vec<GlobalVar>,
         type_tree: TypeTree,
+        public: bool,
+        tipe: Type,
         debug_info: DebugInfo,
     ) -> Self {
         let unique_id = Import::unique_id(&path, &name);
@@ -374,13 +869,28 @@ impl CompiledFunc {
             globals,
             type_tree,
             unique_id,
+            public,
+            tipe,
             debug_info,
         }
     }
 }
 
+/// The version of the .mao layout, to be bumped whenever the fields of `CompiledProgram` change.
+pub const MAO_FORMAT_VERSION: u32 = 1;
+
+/// The contents of a .mao file. The type tree is stored apart from the program since its keys
+/// aren't strings, which json requires.
+#[derive(Serialize, Deserialize)]
+struct MaoFile {
+    mao_version: u32,
+    program: CompiledProgram,
+    type_tree: SerializableTypeTree,
+}
+
 /// Represents a mini program or module that has been compiled and possibly linked, but has not had
-/// post-link compilation steps applied. Is directly serialized to and from .mao files.
+/// post-link compilation steps applied. Is serialized to and from .mao files by `write_mao` and
+/// `read_mao`.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct CompiledProgram {
     /// Name of the program, usually the func from which it was derived
@@ -392,6 +902,7 @@ pub struct CompiledProgram {
     /// All globals used in this program
     pub globals: Vec<GlobalVar>,
     /// Tree of the types
+    #[serde(skip)]
     pub type_tree: TypeTree,
     /// A global id unique to the source (usually a func) from which this program was compiled
     pub unique_id: LabelId,
@@ -420,104 +931,138 @@ impl CompiledProgram {
         }
     }
 
-    /// Writes self to output in format "format".  Supported values are: "pretty", "json", or
-    /// "bincode" if None is specified, json is used, and if an invalid format is specified this
-    /// value appended by "invalid format: " will be written instead
-    pub fn _to_output(&self, output: &mut dyn io::Write, format: Option<&str>) {
+    /// Writes self to `output` as a .mao file, headed by the format version. The file is json if
+    /// `format` is `None` or "json", and bincode if it's "bincode".
+    pub fn write_mao(
+        &self,
+        output: &mut dyn io::Write,
+        format: Option<&str>,
+    ) -> Result<(), String> {
+        let mao = MaoFile {
+            mao_version: MAO_FORMAT_VERSION,
+            program: self.clone(),
+            type_tree: SerializableTypeTree::from_type_tree(self.type_tree.clone()),
+        };
+        match format {
+            None | Some("json") => serde_json::to_writer(output, &mao)
+                .map_err(|why| format!("could not write mao: {}", why)),
+            Some("bincode") => bincode::serialize_into(output, &mao)
+                .map_err(|why| format!("could not write mao: {}", why)),
+            Some(other) => Err(format!("mao can't be written as {}", Color::red(other))),
+        }
+    }
+
+    /// Reads a program from a .mao file written by `write_mao`, checking its format version before
+    /// anything else so that files written in an incompatible layout produce a clear error. Files
+    /// whose first non-whitespace byte opens a json object are read as json, and all others as
+    /// bincode.
+    pub fn read_mao(reader: &mut dyn io::Read) -> Result<Self, String> {
+        let mut input = vec![];
+        reader
+            .read_to_end(&mut input)
+            .map_err(|why| format!("could not read mao: {}", why))?;
+        let is_json = input.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{');
+
+        let check_version = |version: Option<u32>| match version {
+            Some(MAO_FORMAT_VERSION) => Ok(()),
+            Some(version) => Err(format!(
+                "mao has format version {} but only version {} can be read",
+                Color::red(version),
+                Color::red(MAO_FORMAT_VERSION),
+            )),
+            None => Err(String::from("mao is missing its format version")),
+        };
+
+        // the version heads the file, so it's checked before the rest is decoded
+        let (mut program, type_tree): (CompiledProgram, SerializableTypeTree) = match is_json {
+            true => {
+                let mao: serde_json::Value = serde_json::from_slice(&input)
+                    .map_err(|why| format!("mao is not valid json: {}", why))?;
+                let version = mao.get("mao_version").cloned();
+                check_version(version.and_then(|version| serde_json::from_value(version).ok()))?;
+                let mao: MaoFile =
+                    serde_json::from_value(mao).map_err(|why| format!("malformed mao: {}", why))?;
+                (mao.program, mao.type_tree)
+            }
+            false => {
+                let mut rest = input.as_slice();
+                check_version(bincode::deserialize_from(&mut rest).ok())?;
+                bincode::deserialize_from(&mut rest)
+                    .map_err(|why| format!("malformed mao: {}", why))?
+            }
+        };
+        program.type_tree = type_tree.into_type_tree();
+        Ok(program)
+    }
+
+    /// Writes self to output in format "format". Supported values are: "pretty", "json", or
+    /// "bincode", and if None is specified, json is used. The json and bincode outputs are .mao
+    /// files as written by `write_mao`, so they can be read back with `read_mao`.
+    pub fn to_output(
+        &self,
+        output: &mut dyn io::Write,
+        format: Option<&str>,
+    ) -> Result<(), String> {
         match format {
             Some("pretty") => {
                 for (idx, insn) in self.code.iter().enumerate() {
-                    writeln!(output, "{:04}:  {}", idx, insn).unwrap();
+                    writeln!(output, "{:04}:  {}", idx, insn)
+                        .map_err(|why| format!("could not write program: {}", why))?;
                 }
+                Ok(())
             }
-            None | Some("json") => match serde_json::to_string(self) {
-                Ok(prog_str) => {
-                    writeln!(output, "{}", prog_str).unwrap();
-                }
-                Err(e) => {
-                    writeln!(output, "json serialization error: {:?}", e).unwrap();
-                }
-            },
-            Some("bincode") => match bincode::serialize(self) {
-                Ok(encoded) => {
-                    if let Err(e) = output.write_all(&encoded) {
-                        writeln!(output, "bincode write error: {:?}", e).unwrap();
-                    }
-                }
-                Err(e) => {
-                    writeln!(output, "bincode serialization error: {:?}", e).unwrap();
-                }
-            },
-            Some(weird_value) => {
-                writeln!(output, "invalid format: {}", weird_value).unwrap();
-            }
+            format => self.write_mao(output, format),
         }
     }
 }
 
-/// Returns either a CompiledProgram generated from source code at path, otherwise returns a
-/// CompileError.
-///
-/// The file_id specified will be used as the file_id in locations originating from this source
-/// file, and if debug is set to true, then compiler internal debug information will be printed.
+/// The funcs of a program's modules after codegen, along with its globals and the `MergedStrings`
+/// their IDs refer to, ready to be linked.
+pub type CompiledModules = (Vec<CompiledFunc>, Vec<GlobalVar>, MergedStrings);
+
+/// Compiles the modules of the program whose main module is at `path`, as `compile_from_folder`
+/// does for the folder holding it.
 pub fn compile_from_file(
     path: &Path,
-    file_info_chart: &mut BTreeMap<u64, FileInfo>,
     constants_path: Option<&Path>,
-    must_use_global_consts: bool,
-    error_system: &mut ErrorSystem,
-    release_build: bool,
-    builtins: bool,
-) -> Result<(Vec<CompiledFunc>, Vec<GlobalVar>), CompileError> {
-    let library = path
-        .parent()
-        .map(|par| {
-            par.file_name()
-                .map(|lib| {
-                    let res = lib.to_str();
-                    if res == Some("builtin") {
-                        Some("core")
-                    } else if res == Some("stdlib") {
-                        Some("std")
-                    } else if res == Some("stdlib2") {
-                        Some("std2")
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or(None)
-        })
-        .unwrap_or(None);
-    if path.is_dir() {
-        compile_from_folder(
-            path,
+    mut error_system: ErrorSystem,
+    options: &CompileOptions,
+    symbol_index: &mut SymbolIndex,
+    session: &mut CompileSession,
+) -> CompileOutcome<CompiledModules> {
+    match locate_main(path) {
+        Ok((folder, library, main)) => compile_from_folder(
+            folder,
             library,
-            "main",
-            file_info_chart,
+            main,
             constants_path,
-            must_use_global_consts,
             error_system,
-            release_build,
-            builtins,
-        )
+            options,
+            symbol_index,
+            session,
+        ),
+        Err(error) => {
+            error_system.errors.push(error);
+            CompileOutcome::new(None, error_system, BTreeMap::new())
+        }
+    }
+}
+
+/// Splits an input `path` into the folder of modules it belongs to, that folder's library prefix,
+/// and the name of the main module. A folder's main module is named `main`.
+pub fn locate_main(path: &Path) -> Result<(&Path, Option<&'static str>, &str), CompileError> {
+    let library = path.parent().and_then(library_prefix);
+    if path.is_dir() {
+        Ok((path, library, "main"))
     } else if let (Some(parent), Some(file_name)) = (path.parent(), path.file_stem()) {
-        compile_from_folder(
-            parent,
-            library,
-            file_name.to_str().ok_or_else(|| {
-                CompileError::new(
-                    "Compile error",
-                    format!("File name {:?} must be UTF-8", file_name),
-                    vec![],
-                )
-            })?,
-            file_info_chart,
-            constants_path,
-            must_use_global_consts,
-            error_system,
-            release_build,
-            builtins,
-        )
+        let main = file_name.to_str().ok_or_else(|| {
+            CompileError::new(
+                "Compile error",
+                format!("File name {:?} must be UTF-8", file_name),
+                vec![],
+            )
+        })?;
+        Ok((parent, library, main))
     } else {
         Err(CompileError::new(
             "Compile error",
@@ -530,6 +1075,109 @@ pub fn compile_from_file(
     }
 }
 
+/// Returns the library prefix attached to the paths of modules in `folder`, if it's one of the
+/// folders holding a standard library.
+fn library_prefix(folder: &Path) -> Option<&'static str> {
+    match folder.file_name()?.to_str() {
+        Some("builtin") => Some("core"),
+        Some("stdlib") => Some("std"),
+        Some("stdlib2") => Some("std2"),
+        _ => None,
+    }
+}
+
+/// Checks the `main` module of `folder` and everything it imports for errors and warnings by
+/// running them through typechecking, flowchecking, and codegen with `options`, without linking or
+/// producing any output. This is meant for tools, like editors, that only want diagnostics.
+///
+/// The issues found are recorded in `error_system`, and the `file_info_chart` receives the files
+/// read, which is needed to print them.
+pub fn check_from_folder(
+    folder: &Path,
+    main: &str,
+    constants_path: Option<&Path>,
+    file_info_chart: &mut BTreeMap<u64, FileInfo>,
+    error_system: &mut ErrorSystem,
+    options: &CompileOptions,
+) {
+    if let Err(error) = compile_modules(
+        folder,
+        library_prefix(folder),
+        main,
+        file_info_chart,
+        constants_path,
+        error_system,
+        options,
+        &mut SymbolIndex::new(),
+        &mut CompileSession::default(),
+    ) {
+        error_system.errors.push(error);
+    }
+}
+
+/// Returns the constants file to compile the modules of `folder` with, which is `constants_path`
+/// when given and otherwise the folder's `constants.json`, if it has one.
+fn default_constants(folder: &Path, constants_path: Option<&Path>) -> Option<PathBuf> {
+    let constants_default = folder.join("constants.json");
+    match constants_path {
+        Some(path) => Some(path.to_path_buf()),
+        None => match constants_default.exists() {
+            true => Some(constants_default),
+            false => None,
+        },
+    }
+}
+
+/// Maps the source file of every module reachable from the `main` module of `folder`, including
+/// those of the standard libraries, to the source files of the modules it imports. Modules are
+/// only parsed, so this is much faster than compiling them, and is meant for build systems
+/// deciding what to rebuild.
+pub fn dependencies_from_folder(
+    folder: &Path,
+    library: Option<&str>,
+    main: &str,
+    constants_path: Option<&Path>,
+    builtins: bool,
+) -> Result<BTreeMap<String, BTreeSet<String>>, CompileError> {
+    let constants_path = default_constants(folder, constants_path);
+    let mut file_info_chart = BTreeMap::new();
+    let mut error_system = ErrorSystem {
+        errors: vec![],
+        warnings: vec![],
+        notes: vec![],
+        warnings_are_errors: false,
+        warn_color: Color::YELLOW,
+        file_info_chart: BTreeMap::new(),
+    };
+
+    let (_, import_map) = create_program_tree(
+        folder,
+        library,
+        main,
+        &mut file_info_chart,
+        constants_path.as_deref(),
+        &mut error_system,
+        &CompileOptions {
+            builtins,
+            ..CompileOptions::default()
+        },
+        &mut CompileSession::default(),
+    )?;
+
+    let file = |path: &Vec<String>| match file_info_chart.get(&module_file_id(path)) {
+        Some(info) => info.path.clone(),
+        None => path_display(path),
+    };
+
+    Ok(import_map
+        .iter()
+        .map(|(path, imports)| {
+            let depends = imports.iter().map(|import| file(&import.path)).collect();
+            (file(path), depends)
+        })
+        .collect())
+}
+
 /// Prints the AST nodes with indentation representing their depth, currently not used.
 fn _print_node(node: &mut TypeCheckedNode, state: &String, mut_state: &mut usize) -> bool {
     for _ in 0..*mut_state {
@@ -540,32 +1188,60 @@ fn _print_node(node: &mut TypeCheckedNode, state: &String, mut_state: &mut usize
     true
 }
 
-/// Compiles a `Vec<CompiledProgram>` from a folder along with it's `Vec<GlobalVar>`
-/// or generates a `CompileError` if a problem is encountered during compilation.
+/// Compiles the `main` module of `folder` and everything it imports into `CompiledModules`. The
+/// outcome carries every issue found, with those already in `error_system`, whose settings decide
+/// how the issues are colored.
 ///
-/// The `folder` argument gives the path to the folder, `library` optionally contains a library
-/// prefix attached to the front of all paths, `main` contains the name of the main file in the
-/// folder, `file_info_chart` contains a map from the `u64` hashes of file names to the `FileInfo`
-/// they represent, useful for formatting errors
+/// The `library` argument optionally contains a library prefix attached to the front of all paths,
+/// and `symbol_index` receives the definitions and uses of every symbol in each module compiled.
 pub fn compile_from_folder(
+    folder: &Path,
+    library: Option<&str>,
+    main: &str,
+    constants_path: Option<&Path>,
+    mut error_system: ErrorSystem,
+    options: &CompileOptions,
+    symbol_index: &mut SymbolIndex,
+    session: &mut CompileSession,
+) -> CompileOutcome<CompiledModules> {
+    let mut file_info_chart = BTreeMap::new();
+    let modules = compile_modules(
+        folder,
+        library,
+        main,
+        &mut file_info_chart,
+        constants_path,
+        &mut error_system,
+        options,
+        symbol_index,
+        session,
+    );
+    let modules = match modules {
+        Ok(modules) => Some(modules),
+        Err(error) => {
+            error_system.errors.push(error);
+            None
+        }
+    };
+    CompileOutcome::new(modules, error_system, file_info_chart)
+}
+
+/// Compiles the modules of `folder` as `compile_from_folder` does, recording issues in
+/// `error_system` and returning the one that stopped compilation, if any. The `file_info_chart`
+/// receives the files read, keyed by the `u64` hashes of their names.
+fn compile_modules(
     folder: &Path,
     library: Option<&str>,
     main: &str,
     file_info_chart: &mut BTreeMap<u64, FileInfo>,
     constants_path: Option<&Path>,
-    must_use_global_consts: bool,
     error_system: &mut ErrorSystem,
-    release_build: bool,
-    builtins: bool,
-) -> Result<(Vec<CompiledFunc>, Vec<GlobalVar>), CompileError> {
-    let constants_default = folder.join("constants.json");
-    let constants_path = match constants_path {
-        Some(path) => Some(path),
-        None => match constants_default.exists() {
-            true => Some(constants_default.as_path()),
-            false => None,
-        },
-    };
+    options: &CompileOptions,
+    symbol_index: &mut SymbolIndex,
+    session: &mut CompileSession,
+) -> Result<CompiledModules, CompileError> {
+    let constants_path = default_constants(folder, constants_path);
+    let constants_path = constants_path.as_deref();
 
     let (mut programs, mut import_map) = create_program_tree(
         folder,
@@ -574,13 +1250,15 @@ pub fn compile_from_folder(
         file_info_chart,
         constants_path,
         error_system,
-        builtins,
+        options,
+        session,
     )?;
 
     resolve_imports(&mut programs, &mut import_map, error_system)?;
 
     // Conversion of programs from `HashMap` to `Vec` for typechecking
     let type_tree = create_type_tree(&programs);
+    check_type_cycles(&type_tree, &programs)?;
     let mut modules = vec![programs
         .remove(&if let Some(lib) = library {
             vec![lib.to_string(), main.to_string()]
@@ -594,10 +1272,28 @@ pub fn compile_from_folder(
         out
     });
     let mut typechecked_modules =
-        typecheck_programs(&type_tree, modules, file_info_chart, error_system)?;
+        typecheck_programs(&type_tree, modules, file_info_chart, error_system, options)?;
+
+    if options.must_use_global_consts {
+        check_global_constants(&typechecked_modules, constants_path, error_system);
+    }
+
+    check_struct_fields(&mut typechecked_modules, &type_tree, error_system);
+
+    for module in &mut typechecked_modules {
+        symbol_index.insert(
+            path_display(&module.path),
+            xref::index_module(
+                &mut module.checked_funcs,
+                &module.global_vars,
+                &module.imports,
+                &module.string_table,
+            ),
+        );
+    }
 
-    if must_use_global_consts {
-        check_global_constants(&typechecked_modules, constants_path, error_system);
+    for module in &mut typechecked_modules {
+        module.include_files(folder, options.max_include_size, error_system);
     }
 
     // Control flow analysis stage
@@ -606,11 +1302,38 @@ pub fn compile_from_folder(
     }
 
     for module in &mut typechecked_modules {
-        module.propagate_attributes();
+        module.strip_asserts(options.asserts, &type_tree, error_system);
+        module.simplify();
+        module.hoist_loop_invariants();
     }
 
-    let (progs, globals) = codegen_modules(typechecked_modules, type_tree, release_build)?;
-    Ok((progs, globals))
+    let (mut progs, mut globals, strings) = codegen_modules(typechecked_modules, type_tree)?;
+    strings.renumber_globals(&mut progs, &mut globals)?;
+    Ok((progs, globals, strings))
+}
+
+/// Ensures every named type in `type_tree` resolves to a representation, rejecting aliases that
+/// are defined in terms of each other, like `type A = B; type B = A;`. The error points at the
+/// declaration of the alias the cycle returns to.
+fn check_type_cycles(
+    type_tree: &TypeTree,
+    programs: &HashMap<Vec<String>, Module>,
+) -> Result<(), CompileError> {
+    let locations = programs
+        .iter()
+        .flat_map(|(path, module)| {
+            module
+                .type_locations
+                .iter()
+                .map(move |(id, location)| ((path.clone(), *id), *location))
+        })
+        .collect();
+    let mut decls: Vec<_> = type_tree.iter().collect();
+    decls.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
+    for (_, (tipe, _)) in decls {
+        tipe.rep_located(type_tree, &locations)?;
+    }
+    Ok(())
 }
 
 /// Converts the `Vec<String>` used to identify a path into a single formatted string
@@ -626,6 +1349,13 @@ fn path_display(path: &Vec<String>) -> String {
     s
 }
 
+/// Returns the file id used in the locations of the module at `path`.
+fn module_file_id(path: &Vec<String>) -> u64 {
+    let mut file_hasher = DefaultHasher::new();
+    path.hash(&mut file_hasher);
+    file_hasher.finish()
+}
+
 /// Parsing stage of the compiler, creates a `HashMap` containing a list of modules and imports
 /// generated by interpreting the contents of `folder` as source code. Returns a `CompileError` if
 /// the contents of `folder` fail to parse.
@@ -636,7 +1366,8 @@ fn create_program_tree(
     file_info_chart: &mut BTreeMap<u64, FileInfo>,
     constants_path: Option<&Path>,
     error_system: &mut ErrorSystem,
-    builtins: bool,
+    options: &CompileOptions,
+    session: &mut CompileSession,
 ) -> Result<
     (
         HashMap<Vec<String>, Module>,
@@ -650,6 +1381,13 @@ fn create_program_tree(
         vec![vec![main.to_owned()]]
     };
 
+    // cached modules had their constants substituted, so they're stale once the constants change
+    let mut constants_hasher = DefaultHasher::new();
+    constants_path
+        .and_then(|path| std::fs::read(path).ok())
+        .hash(&mut constants_hasher);
+    let constants_hash = constants_hasher.finish();
+
     let mut programs = HashMap::new();
     let mut import_map = HashMap::new();
     let mut seen_paths = HashSet::new();
@@ -668,8 +1406,22 @@ fn create_program_tree(
         } else if path[0] == "core" {
             format!("../builtin/{}", path[1])
         } else {
-            path[0].clone()
+            // nested modules live in subfolders, so a::b::c is found at a/b/c.mini
+            path.join("/")
         } + ".mini";
+        let nested = path.len() > 1 && !["std", "std2", "core"].contains(&path[0].as_str());
+        if nested && !folder.join(&name).exists() {
+            return Err(CompileError::new(
+                "Compile error",
+                format!(
+                    "Module {} does not exist, looked for {}",
+                    Color::red(path_display(&path)),
+                    Color::red(folder.join(&name).display()),
+                ),
+                vec![],
+            ));
+        }
+
         let mut file = File::open(folder.join(name.clone())).map_err(|why| {
             CompileError::new(
                 "Compile error",
@@ -686,22 +1438,48 @@ fn create_program_tree(
                 vec![],
             )
         })?;
-        let mut file_hasher = DefaultHasher::new();
-        path.hash(&mut file_hasher);
-        let file_id = file_hasher.finish();
 
-        file_info_chart.insert(
-            file_id,
-            FileInfo {
-                name: path_display(&path),
-                path: folder.join(name.clone()).display().to_string(),
-                contents: source.split("\n").map(|x| x.to_string()).collect(),
-            },
+        let in_library = ["std", "std2", "core"].contains(&path[0].as_str());
+        let cache_key = match in_library {
+            true => Some((
+                folder.join(&name),
+                constants_path.map(Path::to_path_buf),
+                options.builtins,
+            )),
+            false => None,
+        };
+        let mut source_hasher = DefaultHasher::new();
+        source.hash(&mut source_hasher);
+        let source_hash = source_hasher.finish();
+        let cached = cache_key.as_ref().and_then(|key| session.modules.get(key));
+        let fresh = |cached: &&CachedModule| {
+            cached.source_hash == source_hash && cached.constants_hash == constants_hash
+        };
+        if let Some(cached) = cached.filter(fresh) {
+            let module = cached.module.clone();
+            file_info_chart.insert(module_file_id(&path), cached.file_info.clone());
+            error_system
+                .warnings
+                .extend(cached.warnings.iter().cloned());
+            paths.extend(module.imports.iter().map(|imp| imp.path.clone()));
+            import_map.insert(path.clone(), module.imports.clone());
+            programs.insert(path, module);
+            continue;
+        }
+
+        let file_id = module_file_id(&path);
+
+        let file_info = FileInfo::new(
+            path_display(&path),
+            folder.join(name.clone()).display().to_string(),
+            &source,
         );
+        file_info_chart.insert(file_id, file_info.clone());
 
+        let warnings_before = error_system.warnings.len();
         let mut string_table = StringTable::new();
         let mut used_constants = HashSet::new();
-        let (imports, funcs, named_types, global_vars, func_table) =
+        let (imports, funcs, named_types, global_vars, func_table, type_locations) =
             typecheck::sort_top_level_decls(
                 parse_from_source(
                     source,
@@ -714,24 +1492,35 @@ fn create_program_tree(
                 )?,
                 path.clone(),
                 &mut string_table,
-                builtins,
+                options.builtins,
             );
         paths.append(&mut imports.iter().map(|imp| imp.path.clone()).collect());
         import_map.insert(path.clone(), imports.clone());
-        programs.insert(
+        let module = Module::new(
+            funcs,
+            named_types,
+            type_locations,
+            used_constants,
+            global_vars,
+            imports,
+            string_table,
+            func_table,
             path.clone(),
-            Module::new(
-                funcs,
-                named_types,
-                used_constants,
-                global_vars,
-                imports,
-                string_table,
-                func_table,
-                path,
-                name,
-            ),
+            name,
         );
+        if let Some(key) = cache_key {
+            session.modules.insert(
+                key,
+                CachedModule {
+                    source_hash,
+                    constants_hash,
+                    module: module.clone(),
+                    file_info,
+                    warnings: error_system.warnings[warnings_before..].to_vec(),
+                },
+            );
+        }
+        programs.insert(path, module);
     }
     Ok((programs, import_map))
 }
@@ -786,12 +1575,15 @@ fn resolve_imports(
                 )
             })?;
 
-            let string_id = match origin_module.string_table.get_if_exists(&import.name) {
+            let string_id = match origin_module
+                .string_table
+                .get_if_exists(import.local_name())
+            {
                 Some(string_id) => string_id,
                 None => {
                     return Err(CompileError::new(
                         "Internal error",
-                        format!("Import {} has no string id", import.name),
+                        format!("Import {} has no string id", import.local_name()),
                         import.loc(),
                     ))
                 }
@@ -870,13 +1662,15 @@ fn typecheck_programs(
     modules: Vec<Module>,
     _file_info_chart: &mut BTreeMap<u64, FileInfo>,
     error_system: &mut ErrorSystem,
+    options: &CompileOptions,
 ) -> Result<Vec<TypeCheckedModule>, CompileError> {
-    let (typechecked_modules, module_issues) = modules
+    let (checked, failed): (Vec<_>, Vec<_>) = modules
         .into_par_iter()
         .map(
             |Module {
                  funcs,
                  named_types,
+                 type_locations: _,
                  constants,
                  global_vars,
                  imports,
@@ -898,34 +1692,104 @@ fn typecheck_programs(
                         &path,
                     )?;
 
+                let global_types: HashMap<_, _> = global_vars
+                    .iter()
+                    .map(|global| (global.id, global.tipe.clone()))
+                    .collect();
+
                 checked_funcs.iter_mut().for_each(|(id, func)| {
+                    // inherited attributes must be in place before any lints run
+                    let attributes = func.debug_info.attributes.clone();
+                    TypeCheckedNode::propagate_attributes(func.child_nodes(), &attributes);
+
                     let detected_view = func.is_view(type_tree);
                     let detected_write = func.is_write(type_tree);
 
                     let name = string_table.name_from_id(*id);
 
-                    if detected_view && !func.properties.view {
-                        typecheck_issues.push(CompileError::new_type_error(
-                            format!(
-                                "Func {} is {} but was not declared so",
-                                Color::red(name),
-                                Color::red("view")
-                            ),
-                            func.debug_info.locs(),
+                    let allowed = func.debug_info.attributes.allow.contains(Lints::IMPURE);
+                    typecheck_issues.extend(typecheck::flowcheck_impure_positions(
+                        func,
+                        allowed,
+                        type_tree,
+                        &string_table,
+                        error_system.warn_color,
+                    ));
+
+                    typecheck_issues.extend(typecheck::flowcheck_lossy_constants(
+                        func,
+                        error_system.warn_color,
+                    ));
+
+                    typecheck_issues.extend(typecheck::flowcheck_constant_scrutinees(
+                        func,
+                        error_system.warn_color,
+                    ));
+
+                    typecheck_issues.extend(typecheck::flowcheck_self_comparisons(
+                        func,
+                        error_system.warn_color,
+                    ));
+
+                    typecheck_issues
+                        .extend(typecheck::flowcheck_bool_ifs(func, error_system.warn_color));
+
+                    typecheck_issues.extend(typecheck::flowcheck_non_copy_duplicates(
+                        func,
+                        type_tree,
+                        &string_table,
+                        error_system.warn_color,
+                    ));
+
+                    if let Some(max_depth) = options.max_tuple_depth {
+                        typecheck_issues.extend(typecheck::flowcheck_tuple_depths(
+                            func,
+                            max_depth,
+                            type_tree,
+                            &string_table,
                         ));
                     }
 
-                    if detected_write && !func.properties.write {
-                        typecheck_issues.push(CompileError::new_type_error(
-                            format!(
-                                "Func {} is {} but was not declared so",
-                                Color::red(name),
-                                Color::red("write")
-                            ),
-                            func.debug_info.locs(),
+                    let library = ["core", "std", "std2"].contains(&path[0].as_str());
+                    if options.require_mut && !library {
+                        typecheck_issues.extend(typecheck::flowcheck_mutability(
+                            func,
+                            &mut HashMap::new(),
+                            &string_table,
+                        ));
+                    }
+
+                    if options.warn_unimplemented && !library {
+                        typecheck_issues.extend(typecheck::flowcheck_unimplemented(
+                            func,
+                            name,
+                            error_system.warn_color,
+                        ));
+                    }
+
+                    if options.no_implicit_any && !library {
+                        let ret_type = func.ret_type.clone();
+                        typecheck_issues.extend(typecheck::flowcheck_implicit_any(
+                            func,
+                            &ret_type,
+                            &global_types,
+                            type_tree,
+                            error_system.warn_color,
                         ));
                     }
 
+                    if detected_view && !func.properties.view {
+                        let asm = typecheck::impure_asm_insns(func, type_tree, false);
+                        let gas = typecheck::gas_accesses(func, false);
+                        typecheck_issues.push(undeclared_purity(name, "view", func, &asm, &gas));
+                    }
+
+                    if detected_write && !func.properties.write {
+                        let asm = typecheck::impure_asm_insns(func, type_tree, true);
+                        let gas = typecheck::gas_accesses(func, true);
+                        typecheck_issues.push(undeclared_purity(name, "write", func, &asm, &gas));
+                    }
+
                     if !detected_view && func.properties.view {
                         typecheck_issues.push(CompileError::new_warning(
                             String::from("Typecheck warning"),
@@ -965,7 +1829,11 @@ fn typecheck_programs(
                 ))
             },
         )
-        .collect::<Result<(Vec<TypeCheckedModule>, Vec<Vec<CompileError>>), CompileError>>()?;
+        .collect::<Vec<Result<_, Vec<CompileError>>>>()
+        .into_iter()
+        .partition(Result::is_ok);
+    let (typechecked_modules, module_issues): (Vec<_>, Vec<_>) =
+        checked.into_iter().map(Result::unwrap).unzip();
 
     for issue in module_issues.into_iter().flatten() {
         match issue.is_warning {
@@ -974,7 +1842,61 @@ fn typecheck_programs(
         }
     }
 
-    Ok(typechecked_modules)
+    // every module's errors are reported, and the last is returned to halt compilation
+    let mut errors: Vec<_> = failed.into_iter().flat_map(Result::unwrap_err).collect();
+    match errors.pop() {
+        Some(last) => {
+            error_system.errors.extend(errors);
+            Err(last)
+        }
+        None => Ok(typechecked_modules),
+    }
+}
+
+/// Reports that the func `name` is `kind` without having been declared so. When instructions
+/// within its `asm` blocks or its uses of `getGas` or `setGas` are responsible, they're named and
+/// located too.
+fn undeclared_purity(
+    name: &str,
+    kind: &str,
+    func: &TypeCheckedFunc,
+    asm: &[Instruction],
+    gas: &[Location],
+) -> CompileError {
+    let mut locations = func.debug_info.locs();
+    locations.extend(gas);
+    locations.extend(asm.iter().filter_map(|insn| insn.debug_info.location));
+
+    let mut reasons = vec![];
+    if !gas.is_empty() {
+        let builtin = match kind {
+            "write" => "setGas",
+            _ => "getGas",
+        };
+        reasons.push(format!("it uses {}", Color::red(builtin)));
+    }
+    if !asm.is_empty() {
+        reasons.push(format!(
+            "its asm uses {}",
+            asm.iter()
+                .map(|insn| Color::red(&insn.opcode))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let because = match reasons.is_empty() {
+        true => String::new(),
+        false => format!(", as {}", reasons.join(" and ")),
+    };
+    CompileError::new_type_error(
+        format!(
+            "Func {} is {} but was not declared so{}",
+            Color::red(name),
+            Color::red(kind),
+            because,
+        ),
+        locations,
+    )
 }
 
 fn check_global_constants(
@@ -1003,15 +1925,134 @@ fn check_global_constants(
     }
 }
 
+/// Warns about fields of the structs declared outside the libraries that are never read anywhere
+/// in the program, noting those that are still replaced by struct modifiers. Fields whose names
+/// start with an underscore or that are marked `allow(unused)` are skipped.
+fn check_struct_fields(
+    modules: &mut Vec<TypeCheckedModule>,
+    type_tree: &TypeTree,
+    error_system: &mut ErrorSystem,
+) {
+    let mut reads = BTreeSet::new();
+    let mut writes = BTreeSet::new();
+    for module in modules {
+        for func in module.checked_funcs.values_mut() {
+            typecheck::struct_field_accesses(func, type_tree, &mut reads, &mut writes);
+        }
+    }
+
+    let declared: BTreeMap<_, _> = type_tree
+        .iter()
+        .filter(|((path, _), _)| match path.first() {
+            Some(root) => !["core", "std", "std2"].contains(&root.as_str()),
+            None => true,
+        })
+        .collect();
+
+    for (tipe, name) in declared.into_values() {
+        let fields = match tipe {
+            Type::Struct(fields) => fields,
+            _ => continue,
+        };
+        for (slot, field) in fields.iter().enumerate() {
+            let access = (field.name.clone(), slot, fields.len());
+            let allowed = field.debug_info.attributes.allow.contains(Lints::UNUSED);
+            if reads.contains(&access) || allowed || field.name.starts_with('_') {
+                continue;
+            }
+            error_system.warnings.push(CompileError::new_warning(
+                "Compile Warning",
+                format!(
+                    "field {} of {} is {}",
+                    Color::color(error_system.warn_color, &field.name),
+                    Color::color(error_system.warn_color, name),
+                    match writes.contains(&access) {
+                        true => "written but never read",
+                        false => "never read",
+                    },
+                ),
+                field.debug_info.locs(),
+            ));
+        }
+    }
+}
+
+/// The strings of every module compiled together, merged into one table. Each module numbers its
+/// strings independently, so `remaps` gives, for each module's path, the merged ID of each of
+/// that module's IDs.
+#[derive(Clone, Debug)]
+pub struct MergedStrings {
+    /// The names of every module, each qualified by its module's path
+    pub table: StringTable,
+    /// Map from each module's path to the merged ID of each of that module's IDs
+    pub remaps: HashMap<Vec<String>, Vec<StringId>>,
+}
+
+impl MergedStrings {
+    fn new() -> Self {
+        MergedStrings {
+            table: StringTable::new(),
+            remaps: HashMap::new(),
+        }
+    }
+
+    /// Merges the `string_table` of the module at `path` into the table.
+    fn merge(&mut self, path: &[String], string_table: &StringTable) {
+        let remap = self.table.merge(string_table, path);
+        self.remaps.insert(path.to_vec(), remap);
+    }
+
+    /// Renumbers the globals each of `funcs` can access, as well as the linked `globals` holding
+    /// the same slots, from the IDs of the module they're declared in to the merged IDs. Since
+    /// merged names are qualified by module, globals of the same name in different modules end up
+    /// with different IDs.
+    fn renumber_globals(
+        &self,
+        funcs: &mut [CompiledFunc],
+        globals: &mut [GlobalVar],
+    ) -> Result<(), CompileError> {
+        let mut merged_ids = HashMap::new(); // global offsets to merged StringIds
+        for func in funcs {
+            let remap = self.remaps.get(&func.path).ok_or_else(|| {
+                CompileError::new(
+                    "Internal error",
+                    format!(
+                        "Func {} is in module {}, whose strings weren't merged",
+                        Color::red(&func.name),
+                        Color::red(path_display(&func.path)),
+                    ),
+                    func.debug_info.locs(),
+                )
+            })?;
+            for global in &mut func.globals {
+                global.id = remap[global.id];
+                debug_assert!(self.table.name_from_id(global.id).ends_with(&global.name));
+                merged_ids.insert(global.offset, global.id);
+            }
+        }
+        for global in globals {
+            if let Some(id) = merged_ids.get(&global.offset) {
+                global.id = *id;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generates the code of every func in `typechecked_modules`, returning the compiled funcs, the
+/// globals of all modules laid out side by side, and the merged strings of the modules, whose IDs
+/// the funcs and globals still use until `MergedStrings::renumber_globals` is applied to them.
 fn codegen_modules(
     typechecked_modules: Vec<TypeCheckedModule>,
     type_tree: TypeTree,
-    release_build: bool,
-) -> Result<(Vec<CompiledFunc>, Vec<GlobalVar>), CompileError> {
+) -> Result<(Vec<CompiledFunc>, Vec<GlobalVar>, MergedStrings), CompileError> {
     let mut work_list = vec![];
     let mut globals_so_far = 0;
+    let mut strings = MergedStrings::new();
 
     for mut module in typechecked_modules {
+        strings.merge(&module.path, &module.string_table);
+
         // assign globals to the right of all prior
         let mut global_vars = HashMap::new();
         for mut global in module.global_vars {
@@ -1055,15 +2096,12 @@ fn codegen_modules(
         .into_par_iter()
         .map(|(func, func_labels, string_table, globals, module_path)| {
             let func_name = func.name.clone();
+            let public = func.public;
+            let tipe = func.tipe.clone();
             let debug_info = func.debug_info;
 
-            let (code, mut label_gen, frame_size) = codegen::mavm_codegen_func(
-                func,
-                &string_table,
-                &globals,
-                &func_labels,
-                release_build,
-            )?;
+            let (code, mut label_gen, frame_size) =
+                codegen::mavm_codegen_func(func, &string_table, &globals, &func_labels)?;
 
             let mut graph = BasicGraph::new(code);
 
@@ -1087,6 +2125,8 @@ fn codegen_modules(
                 frame_size,
                 globals,
                 type_tree.clone(),
+                public,
+                tipe,
                 debug_info,
             );
 
@@ -1105,12 +2145,7 @@ fn codegen_modules(
         func.code = translate::pack_closures(&func.code, &capture_map, &frame_sizes);
     }
 
-    let mut globals = BTreeMap::new();
-    for func in &funcs {
-        for global in func.globals.iter() {
-            globals.insert(global.offset, global.clone()); // ensure duplicates aren't present
-        }
-    }
+    let globals = collect_global_slots(funcs.iter().flat_map(|func| &func.globals))?;
 
     let mut globals: Vec<_> = globals.into_iter().map(|x| x.1).collect();
     globals.push(GlobalVar::new(
@@ -1120,7 +2155,58 @@ fn codegen_modules(
         DebugInfo::default(),
     ));
 
-    Ok((funcs, globals))
+    Ok((funcs, globals, strings))
+}
+
+/// The number of slots available to declared globals. Beyond this the globals tuple must nest,
+/// and `__fixedLocationGlobal` would no longer be found at index [0], but rather [0][0] etc.
+const GLOBAL_SLOT_LIMIT: usize = 56;
+
+/// The largest file `include_bytes` may embed when `--max-include-size` isn't given.
+pub const DEFAULT_MAX_INCLUDE_SIZE: usize = 1 << 16;
+
+/// Gathers globals by the slot each was assigned, folding together the copies of a global that
+/// every function of its module carries. Since the globals tuple holds one value per slot, two
+/// distinct globals claiming the same slot, or a slot beyond `GLOBAL_SLOT_LIMIT`, is an error.
+fn collect_global_slots<'a>(
+    globals: impl IntoIterator<Item = &'a GlobalVar>,
+) -> Result<BTreeMap<Option<usize>, GlobalVar>, CompileError> {
+    let mut slots = BTreeMap::<Option<usize>, GlobalVar>::new();
+    for global in globals {
+        if let Some(offset) = global.offset.filter(|offset| *offset >= GLOBAL_SLOT_LIMIT) {
+            return Err(CompileError::new(
+                "Compile error",
+                format!(
+                    "Global {} was assigned slot {}, but only {} globals are supported",
+                    Color::red(&global.name),
+                    offset,
+                    GLOBAL_SLOT_LIMIT,
+                ),
+                global.debug_info.locs(),
+            ));
+        }
+        match slots.get(&global.offset) {
+            Some(prior) if prior != global => {
+                return Err(CompileError::new(
+                    "Compile error",
+                    format!(
+                        "Globals {} and {} were both assigned slot {}",
+                        Color::red(&prior.name),
+                        Color::red(&global.name),
+                        global.offset.unwrap_or_default(),
+                    ),
+                    prior
+                        .debug_info
+                        .locs()
+                        .into_iter()
+                        .chain(global.debug_info.locs())
+                        .collect(),
+                ));
+            }
+            _ => drop(slots.insert(global.offset, global.clone())),
+        }
+    }
+    Ok(slots)
 }
 
 pub fn comma_list(input: &[String]) -> String {
@@ -1232,11 +2318,67 @@ pub struct CompileError {
     pub locations: Vec<Location>,
     /// Whether the error should not stop compilation
     pub is_warning: bool,
+    /// A mechanical fix for the issue, if one exists.
+    pub suggestion: Option<Suggestion>,
+    /// A stable code identifying the kind of issue, which `--explain` describes in detail.
+    pub code: Option<&'static str>,
+}
+
+/// A fix that can be applied automatically by replacing a span of source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// Where the replaced text begins.
+    pub start: Location,
+    /// Where the replaced text ends, exclusive.
+    pub end: Location,
+    /// The text that should be written in place of the span.
+    pub replacement: String,
+}
+
+impl Suggestion {
+    /// Creates a suggestion replacing the `text` found at `start` with `replacement`.
+    pub fn replace(start: Location, text: &str, replacement: String) -> Self {
+        Suggestion {
+            start,
+            end: text.chars().fold(start, Location::shift),
+            replacement,
+        }
+    }
+
+    /// Recovers the source text this suggestion replaces, provided it lies on a single line.
+    fn original<'a>(&self, file_info_chart: &'a BTreeMap<u64, FileInfo>) -> Option<&'a str> {
+        if self.start.file_id != self.end.file_id || self.start.line != self.end.line {
+            return None;
+        }
+        let line = file_info_chart
+            .get(&self.start.file_id)?
+            .contents
+            .get(self.start.line.to_usize())?;
+        let mut offsets = line
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(Some(line.len()));
+        let first = offsets.nth(self.start.column.to_usize())?;
+        let width = self.end.column.to_usize() - self.start.column.to_usize();
+        let last = match width {
+            0 => first,
+            _ => offsets.nth(width - 1)?,
+        };
+        line.get(first..last)
+    }
 }
 
 impl Display for CompileError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.description)
+        write!(f, "{}", self.description)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(
+                f,
+                "\nhelp: replace {} through {} with \"{}\"",
+                suggestion.start, suggestion.end, suggestion.replacement
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -1257,6 +2399,8 @@ impl CompileError {
             description: description.to_string(),
             locations,
             is_warning: false,
+            suggestion: None,
+            code: None,
         }
     }
 
@@ -1270,6 +2414,8 @@ impl CompileError {
             description: description.to_string(),
             locations,
             is_warning: true,
+            suggestion: None,
+            code: None,
         }
     }
 
@@ -1282,6 +2428,8 @@ impl CompileError {
             description: description.to_string(),
             locations,
             is_warning: false,
+            suggestion: None,
+            code: None,
         }
     }
 
@@ -1294,9 +2442,23 @@ impl CompileError {
             description: description.to_string(),
             locations: location.into_iter().collect(),
             is_warning: false,
+            suggestion: None,
+            code: None,
         }
     }
 
+    /// Attaches a mechanical fix to this issue.
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.suggestion = Some(suggestion);
+        self
+    }
+
+    /// Tags this issue with an error code that has an extended explanation.
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
     pub fn pretty_fmt(
         &self,
         file_info_chart: &BTreeMap<u64, FileInfo>,
@@ -1315,9 +2477,14 @@ impl CompileError {
 
         let last_line = &self.locations.last();
 
+        let title = match self.code {
+            Some(code) => format!("{}[{}]", self.title, code),
+            None => self.title.clone(),
+        };
+
         let mut pretty = format!(
             "{}: {}\n{}    --> {}{}\n",
-            Color::color(err_color, &self.title),
+            Color::color(err_color, title),
             self.description,
             blue,
             match last_line {
@@ -1361,15 +2528,49 @@ impl CompileError {
             .into_iter()
             .map(|x| {
                 format!(
-                    "     {}{:0space$}{}\n",
+                    "     {} {}{}\n",
                     Color::blue("|"),
-                    " ",
+                    caret_padding(
+                        file_info_chart
+                            .get(&x.file_id)
+                            .and_then(|info| info.contents.get(x.line.to_usize()))
+                            .map(|line| line.as_str())
+                            .unwrap_or(""),
+                        x.column.to_usize()
+                    ),
                     Color::color(err_color, "^"),
-                    space = x.column.to_usize() + 1
                 )
             })
             .collect::<String>();
 
+        if let Some(suggestion) = &self.suggestion {
+            pretty += &format!(
+                "     {} {}\n",
+                Color::blue("="),
+                match suggestion.original(file_info_chart) {
+                    Some(original) => format!(
+                        "help: replace {} with {}",
+                        Color::color(err_color, original),
+                        Color::color(err_color, &suggestion.replacement)
+                    ),
+                    None => format!(
+                        "help: replace {} through {} with {}",
+                        suggestion.start,
+                        suggestion.end,
+                        Color::color(err_color, &suggestion.replacement)
+                    ),
+                }
+            );
+        }
+
+        if let Some(code) = self.code {
+            pretty += &format!(
+                "     {} note: run `mini compile --explain {}` for more information\n",
+                Color::blue("="),
+                code,
+            );
+        }
+
         pretty
     }
 
@@ -1378,12 +2579,26 @@ impl CompileError {
     }
 }
 
+/// Produces the whitespace needed to place a caret under `column` of `line`, reusing any tabs so
+/// the caret lines up no matter how wide the terminal renders them.
+fn caret_padding(line: &str, column: usize) -> String {
+    let mut chars = line.chars();
+    (0..column)
+        .map(|_| match chars.next() {
+            Some('\t') => '\t',
+            _ => ' ',
+        })
+        .collect()
+}
+
 /// A collection of all compiler warnings encountered and the mechanism to handle them.
 pub struct ErrorSystem {
     /// All compilation errors
     pub errors: Vec<CompileError>,
     /// All compilation warnings
     pub warnings: Vec<CompileError>,
+    /// Remarks about what compilation did, like how much an optimization saved
+    pub notes: Vec<String>,
     /// Whether these should halt compilation
     pub warnings_are_errors: bool,
     /// The color to use when highlighting parts of the body text
@@ -1394,6 +2609,9 @@ pub struct ErrorSystem {
 
 impl ErrorSystem {
     pub fn print(&self) {
+        for note in &self.notes {
+            eprintln!("{}: {}", Color::grey("note"), note);
+        }
         for warning in &self.warnings {
             warning.print(&self.file_info_chart, self.warnings_are_errors);
         }
@@ -1401,6 +2619,51 @@ impl ErrorSystem {
             error.print(&self.file_info_chart, self.warnings_are_errors);
         }
     }
+
+    /// Takes in the issues and notes of `outcome`, adding the files they refer to to
+    /// `file_info_chart`, and returns its program.
+    fn absorb<T>(
+        &mut self,
+        outcome: CompileOutcome<T>,
+        file_info_chart: &mut BTreeMap<u64, FileInfo>,
+    ) -> Option<T> {
+        let (warnings, errors): (Vec<_>, Vec<_>) = outcome
+            .diagnostics
+            .into_iter()
+            .partition(|issue| issue.is_warning);
+        self.warnings.extend(warnings);
+        self.errors.extend(errors);
+        self.notes.extend(outcome.notes);
+        file_info_chart.extend(outcome.file_info_chart);
+        outcome.program
+    }
+
+    /// Tallies the issues encountered so far by severity and by code.
+    pub fn summary(&self) -> DiagnosticSummary {
+        let mut codes = BTreeMap::new();
+        for issue in self.errors.iter().chain(&self.warnings) {
+            if let Some(code) = issue.code {
+                *codes.entry(code).or_insert(0) += 1;
+            }
+        }
+        DiagnosticSummary {
+            errors: self.errors.len(),
+            warnings: self.warnings.len(),
+            codes,
+        }
+    }
+}
+
+/// Counts of the issues an `ErrorSystem` encountered, as written by `--diagnostic-summary` so that
+/// trends can be tracked across builds.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DiagnosticSummary {
+    /// The number of errors
+    pub errors: usize,
+    /// The number of warnings
+    pub warnings: usize,
+    /// How many errors and warnings have each code. Issues without a code are only counted above.
+    pub codes: BTreeMap<&'static str, usize>,
 }
 
 /// Lists the offset of each source file contained by a CompiledProgram in offsets, and the
@@ -1413,6 +2676,27 @@ pub struct FileInfo {
     pub path: String,
     #[serde(skip)]
     pub contents: Vec<String>,
+    /// Where each line of the source begins, which is lost when the file info is serialized.
+    #[serde(skip)]
+    lines: Lines,
+}
+
+impl FileInfo {
+    pub fn new(name: String, path: String, source: &str) -> Self {
+        FileInfo {
+            name,
+            path,
+            contents: source.split("\n").map(|x| x.to_string()).collect(),
+            lines: Lines::new(source.bytes()),
+        }
+    }
+
+    /// Finds the line and column of the byte at `byte_offset` in this file, which is identified by
+    /// `file_id` in the file info chart. Returns `None` for offsets past the end of the source, and
+    /// for every offset once the file info has been deserialized.
+    pub fn offset_to_location(&self, byte_offset: BytePos, file_id: u64) -> Option<Location> {
+        self.lines.location(byte_offset, file_id)
+    }
 }
 
 impl Debug for FileInfo {
@@ -1430,3 +2714,372 @@ impl Display for FileInfo {
         write!(f, "{}", self.name)
     }
 }
+
+#[test]
+fn test_caret_padding() {
+    let source = "func main() {\n\t\tlet \u{fc}n\u{ef}code = 3;\n}\n";
+    let lines = Lines::new(source.bytes());
+    let byte = source.find("= 3").unwrap();
+    let location = lines.location(BytePos::from(byte), 0).unwrap();
+    let line = source.lines().nth(location.line.to_usize()).unwrap();
+
+    let padding = caret_padding(line, location.column.to_usize());
+    assert_eq!(padding, format!("\t\t{}", " ".repeat(12)));
+}
+
+#[test]
+fn test_offset_to_location() {
+    let source = "func main() {\n    let \u{e9}t\u{e9} = 3;\n}\n";
+    let file_info = FileInfo::new("test".to_string(), "test.mini".to_string(), source);
+
+    let locate = |pattern: &str| {
+        let byte = BytePos::from(source.find(pattern).unwrap());
+        let location = file_info.offset_to_location(byte, 4).unwrap();
+        assert_eq!(location.file_id, 4);
+        assert_eq!(location.absolute, byte);
+        (location.line.to_usize(), location.column.to_usize())
+    };
+
+    assert_eq!(locate("main"), (0, 5));
+    assert_eq!(locate("= 3"), (1, 12));
+    assert_eq!(locate("}"), (2, 0));
+    assert_eq!(
+        file_info.offset_to_location(BytePos::from(source.len() + 1), 4),
+        None
+    );
+}
+
+#[test]
+fn test_suggestion_original() {
+    let source = "func f(\u{fc}nused: uint) {}\n";
+    let lines = Lines::new(source.bytes());
+    let byte = source.find('\u{fc}').unwrap();
+    let start = lines.location(BytePos::from(byte), 0).unwrap();
+    let suggestion = Suggestion::replace(start, "\u{fc}nused", "_\u{fc}nused".to_string());
+
+    let mut file_info_chart = BTreeMap::new();
+    file_info_chart.insert(
+        0,
+        FileInfo::new("test".to_string(), "test.mini".to_string(), source),
+    );
+    assert_eq!(suggestion.original(&file_info_chart), Some("\u{fc}nused"));
+}
+
+/// An `ErrorSystem` for tests, which collects issues without printing them.
+#[cfg(test)]
+fn test_error_system() -> ErrorSystem {
+    ErrorSystem {
+        errors: vec![],
+        warnings: vec![],
+        notes: vec![],
+        warnings_are_errors: false,
+        warn_color: Color::YELLOW,
+        file_info_chart: BTreeMap::new(),
+    }
+}
+
+/// Parses the type declarations in `source` as the module `test`, returning the tree they're
+/// declared in along with a nominal type naming each, in order.
+#[cfg(test)]
+fn parse_test_types(source: &str, string_table: &mut StringTable) -> (TypeTree, Vec<Type>) {
+    let path = vec!["test".to_string()];
+    let (decls, _) = parse_from_source(
+        source.to_string(),
+        0,
+        &path,
+        string_table,
+        None,
+        &mut HashSet::new(),
+        &mut test_error_system(),
+    )
+    .unwrap();
+
+    let mut type_tree = TypeTree::new();
+    let mut named = vec![];
+    for decl in decls {
+        if let TopLevelDecl::TypeDecl(decl) = decl {
+            let name = string_table.name_from_id(decl.name).clone();
+            type_tree.insert((path.clone(), decl.name), (decl.tipe, name));
+            named.push(Type::Nominal(path.clone(), decl.name, vec![]));
+        }
+    }
+    (type_tree, named)
+}
+
+#[test]
+fn test_display_source() {
+    let source = "
+        type point = struct { x: uint, y: option<int> };
+        type box = (point,);
+        type many = ([]point, [4]bytes32, map<address, buffer>, union<uint, bool>, any, ());
+        type callback = view write func(uint, option<box>) -> every;
+        type maker = func() -> map<uint, []func(bool)>;
+    ";
+
+    let mut string_table = StringTable::new();
+    let (type_tree, named) = parse_test_types(source, &mut string_table);
+
+    for tipe in &named {
+        let declared = tipe.rep(&type_tree).unwrap();
+        let displayed = declared.display_source(&type_tree).unwrap();
+        assert!(!displayed.ends_with(' '));
+
+        let source = format!("type again = {};", displayed);
+        let (again_tree, again) = parse_test_types(&source, &mut string_table);
+        assert_eq!(
+            again[0].rep(&again_tree).unwrap(),
+            declared,
+            "{}",
+            displayed
+        );
+    }
+
+    assert_eq!(Type::GenericSlot(0).display_source(&type_tree), None);
+    assert_eq!(
+        Type::Option(Box::new(Type::Uint)).display(),
+        "option<uint>".to_string()
+    );
+}
+
+#[test]
+fn test_type_aliases_are_transparent() {
+    let mut string_table = StringTable::new();
+    let (type_tree, aliases) = parse_test_types(
+        "type offset = int; type pair = (offset, uint);",
+        &mut string_table,
+    );
+    let mutual = |left: &Type, right: &Type| {
+        left.assignable(right, &type_tree, HashSet::new())
+            && right.assignable(left, &type_tree, HashSet::new())
+    };
+
+    let (offset, pair) = (&aliases[0], &aliases[1]);
+    assert!(mutual(offset, &Type::Int));
+    assert!(mutual(pair, &Type::Tuple(vec![Type::Int, Type::Uint])));
+    assert!(!mutual(offset, &Type::Uint));
+    assert_eq!(offset.print(&type_tree), "offset");
+}
+
+#[test]
+fn test_json_schema() {
+    use serde_json::json;
+
+    let mut string_table = StringTable::new();
+    let (type_tree, named) = parse_test_types(
+        "
+        type point = struct { x: uint, tag: option<bytes32> };
+        type chain = option<(point, chain)>;
+        type callback = func(uint);
+        type pair<T, U> = (T, U);
+        ",
+        &mut string_table,
+    );
+
+    let mut defs = BTreeMap::new();
+    let chain = named[1].json_schema(&type_tree, &mut defs).unwrap();
+    assert_eq!(chain, json!({ "$ref": "#/$defs/test::chain" }));
+    assert_eq!(
+        defs["test::point"],
+        json!({
+            "type": "object",
+            "properties": {
+                "x": { "type": "string", "pattern": "^[0-9]+$" },
+                "tag": {
+                    "anyOf": [
+                        { "type": "null" },
+                        { "type": "string", "pattern": "^0x[0-9a-fA-F]{64}$" },
+                    ]
+                },
+            },
+            "required": ["x", "tag"],
+            "additionalProperties": false,
+        })
+    );
+    let link = &defs["test::chain"]["anyOf"][1];
+    assert_eq!(
+        link["prefixItems"][0],
+        json!({ "$ref": "#/$defs/test::point" })
+    );
+    assert_eq!(
+        link["prefixItems"][1],
+        json!({ "$ref": "#/$defs/test::chain" })
+    );
+    assert_eq!(link["maxItems"], json!(2));
+
+    let error = named[2].json_schema(&type_tree, &mut defs).unwrap_err();
+    assert!(error.contains("no JSON representation"));
+    assert!(!defs.contains_key("test::callback"));
+
+    // the name of a specialized generic is escaped where it's referenced
+    let pair = match &named[3] {
+        Type::Nominal(path, id, _) => {
+            Type::Nominal(path.clone(), *id, vec![Type::Uint, Type::Bool])
+        }
+        _ => unreachable!(),
+    };
+    let reference = pair.json_schema(&type_tree, &mut defs).unwrap();
+    let name = defs
+        .keys()
+        .find(|name| name.starts_with("test::pair"))
+        .unwrap()
+        .clone();
+    assert_eq!(name, "test::pair<uint, bool>");
+    assert_eq!(
+        reference,
+        json!({ "$ref": "#/$defs/test::pair%3Cuint%2C%20bool%3E" })
+    );
+    assert_eq!(defs[&name]["maxItems"], json!(2));
+}
+
+#[test]
+fn test_diagnostic_summary() {
+    let issue = |code: Option<&'static str>| CompileError {
+        code,
+        ..CompileError::new("Typecheck error", "", vec![])
+    };
+    let error_system = ErrorSystem {
+        errors: vec![
+            issue(Some(explain::TYPE_MISMATCH)),
+            issue(Some(explain::TYPE_MISMATCH)),
+            issue(None),
+        ],
+        warnings: vec![issue(Some(explain::UNRECOGNIZED_IDENTIFIER)), issue(None)],
+        ..test_error_system()
+    };
+
+    let summary = error_system.summary();
+    assert_eq!(summary.errors, 3);
+    assert_eq!(summary.warnings, 2);
+    assert_eq!(summary.codes[explain::TYPE_MISMATCH], 2);
+    assert_eq!(summary.codes[explain::UNRECOGNIZED_IDENTIFIER], 1);
+    assert_eq!(summary.codes.len(), 2);
+
+    let json = serde_json::to_value(&summary).unwrap();
+    assert_eq!(json["errors"], 3);
+    assert_eq!(json["codes"][explain::TYPE_MISMATCH], 2);
+}
+
+#[test]
+fn test_mao_format_version() {
+    let program = CompiledProgram::new(
+        String::from("main"),
+        vec![String::from("main")],
+        vec![],
+        vec![],
+        TypeTree::new(),
+        DebugInfo::default(),
+    );
+    let mut written = vec![];
+    program.write_mao(&mut written, None).unwrap();
+    let read = CompiledProgram::read_mao(&mut written.as_slice()).unwrap();
+    assert_eq!(read.name, "main");
+    assert_eq!(read.unique_id, program.unique_id);
+
+    let written = String::from_utf8(written).unwrap();
+    let future = written.replacen(r#""mao_version":1"#, r#""mao_version":2"#, 1);
+    let error = CompiledProgram::read_mao(&mut future.as_bytes())
+        .err()
+        .unwrap();
+    assert!(error.contains("format version"));
+
+    let error = CompiledProgram::read_mao(&mut "{}".as_bytes())
+        .err()
+        .unwrap();
+    assert!(error.contains("missing"));
+
+    let truncated = &written[..written.len() / 2];
+    let error = CompiledProgram::read_mao(&mut truncated.as_bytes())
+        .err()
+        .unwrap();
+    assert!(error.contains("not valid json"));
+
+    let mut written = vec![];
+    program.write_mao(&mut written, Some("bincode")).unwrap();
+    let read = CompiledProgram::read_mao(&mut written.as_slice()).unwrap();
+    assert_eq!(read.unique_id, program.unique_id);
+
+    written[0] = 2;
+    let error = CompiledProgram::read_mao(&mut written.as_slice())
+        .err()
+        .unwrap();
+    assert!(error.contains("format version"));
+
+    let error = CompiledProgram::read_mao(&mut &[1u8][..]).err().unwrap();
+    assert!(error.contains("missing"));
+}
+
+#[test]
+fn test_struct_layout_follows_field_count() {
+    use crate::mavm::Value;
+    use ast::StructField;
+
+    let field = |name: &str, tipe| StructField::new(name.to_string(), tipe, DebugInfo::default());
+    let mixed = Type::Struct(vec![
+        field("flag", Type::Bool),
+        field("triple", Type::Tuple(vec![Type::Uint; 3])),
+        field("wide", Type::FixedArray(Box::new(Type::Uint), 20)),
+    ]);
+    let plain = Type::Struct(vec![
+        field("a", Type::Uint),
+        field("b", Type::Uint),
+        field("c", Type::Uint),
+    ]);
+
+    let type_tree = TypeTree::new();
+    assert_eq!(mixed.size_in_slots(&type_tree), Ok(3));
+    assert_eq!(plain.size_in_slots(&type_tree), Ok(3));
+    match mixed.default_value(&type_tree) {
+        Value::Tuple(slots) => assert_eq!(slots.len(), 3),
+        other => panic!("struct default isn't a tuple: {}", other),
+    }
+}
+
+#[test]
+fn test_global_slot_collisions() {
+    let global = |name: &str, offset: usize| GlobalVar {
+        offset: Some(offset),
+        ..GlobalVar::new(0, name.to_string(), Type::Uint, DebugInfo::default())
+    };
+    let (first, second) = (global("first", 0), global("second", 1));
+
+    let slots = collect_global_slots(&[first.clone(), second.clone(), first.clone()]).unwrap();
+    assert_eq!(slots.len(), 2);
+
+    let clash = collect_global_slots(&[first, second, global("third", 1)]).unwrap_err();
+    assert!(clash.description.contains("second"));
+    assert!(clash.description.contains("third"));
+
+    let overflow = collect_global_slots(&[global("distant", GLOBAL_SLOT_LIMIT)]).unwrap_err();
+    assert!(overflow.description.contains("distant"));
+}
+
+#[test]
+fn test_merged_strings() {
+    let (mut funcs, globals, strings) = compile_from_folder(
+        Path::new("minitests/shared-globals"),
+        None,
+        "main",
+        Some(Path::new("arb_os/constants.json")),
+        test_error_system(),
+        &CompileOptions::default(),
+        &mut SymbolIndex::new(),
+        &mut CompileSession::default(),
+    )
+    .program
+    .unwrap();
+
+    let mut names: Vec<_> = globals
+        .iter()
+        .filter(|global| global.name == "counter")
+        .map(|global| strings.table.name_from_id(global.id).as_str())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["main::counter", "other::counter"]);
+
+    let bump = funcs.iter_mut().find(|func| func.name == "bump").unwrap();
+    bump.path = vec!["missing".to_string()];
+    let error = strings
+        .renumber_globals(&mut funcs, &mut vec![])
+        .unwrap_err();
+    assert!(error.description.contains("missing"));
+}
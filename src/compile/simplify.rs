@@ -0,0 +1,349 @@
+/*
+ * Copyright 2021, Offchain Labs, Inc. All rights reserved.
+ */
+
+//! Rewrites typechecked code into simpler code that computes the same thing
+
+use super::ast::{AssertLevel, AssignRef, Type, TypeTree, UnaryOp};
+use super::typecheck::{
+    bool_if, self_comparison, typecheck_unary_op, AbstractSyntaxTree, TypeCheckedCodeBlock,
+    TypeCheckedExpr, TypeCheckedExprKind, TypeCheckedNode, TypeCheckedStatement,
+    TypeCheckedStatementKind,
+};
+use crate::mavm::Value;
+use crate::pos::Location;
+use crate::stringtable::StringId;
+use crate::uint256::Uint256;
+
+/// Removes options that are unwrapped right after being made. A `?` applied to a `Some` becomes
+/// the wrapped value, and an `if let` whose scrutinee is a `Some` becomes its then branch with the
+/// wrapped value bound beforehand. Nested expressions are simplified first.
+pub fn fold_redundant_options<T: AbstractSyntaxTree>(node: &mut T) {
+    for mut child in node.child_nodes() {
+        fold_redundant_options(&mut child);
+
+        if let TypeCheckedNode::Expression(expr) = child {
+            let folded = match &expr.kind {
+                TypeCheckedExprKind::Try(inner, _) => some_contents(inner).map(|value| value.kind),
+                TypeCheckedExprKind::IfLet(id, cond, block, _, _) => {
+                    some_contents(cond).map(|value| {
+                        let debug_info = value.debug_info;
+                        let bind = TypeCheckedStatement {
+                            kind: TypeCheckedStatementKind::SetLocals(
+                                vec![AssignRef::new(*id, true, debug_info)],
+                                value,
+                            ),
+                            debug_info,
+                        };
+                        let mut body = vec![bind];
+                        body.extend(block.body.iter().cloned());
+
+                        TypeCheckedExprKind::CodeBlock(TypeCheckedCodeBlock {
+                            body,
+                            ret_expr: block.ret_expr.clone(),
+                            scope: block.scope.clone(),
+                        })
+                    })
+                }
+                _ => None,
+            };
+            if let Some(kind) = folded {
+                expr.kind = kind;
+            }
+        }
+    }
+}
+
+/// Replaces comparisons of a pure value against itself with their result.
+pub fn fold_self_comparisons<T: AbstractSyntaxTree>(node: &mut T) {
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &mut child {
+            if let Some(result) = self_comparison(expr) {
+                let value = Value::Int(Uint256::from_bool(result));
+                expr.kind = TypeCheckedExprKind::Const(value, Type::Bool);
+                continue;
+            }
+        }
+        fold_self_comparisons(&mut child);
+    }
+}
+
+/// Replaces ifs that just yield `true` or `false` with their condition, negating it where the
+/// constants are inverted. Nested expressions are simplified first.
+pub fn fold_bool_ifs<T: AbstractSyntaxTree>(node: &mut T) {
+    for mut child in node.child_nodes() {
+        fold_bool_ifs(&mut child);
+
+        if let TypeCheckedNode::Expression(expr) = child {
+            if let (Some(negated), TypeCheckedExprKind::If(cond, ..)) = (bool_if(expr), &expr.kind)
+            {
+                let cond = (**cond).clone();
+                expr.kind = match negated {
+                    false => cond.kind,
+                    true => {
+                        // an if's condition is always a plain bool, so negating it can't fail
+                        typecheck_unary_op(UnaryOp::Not, cond, None, &TypeTree::new())
+                            .expect("if condition isn't a bool")
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Replaces `while true` loops with plain loops, which don't test a condition on each iteration,
+/// and drops `while false` loops, whose bodies never run.
+pub fn fold_constant_loops<T: AbstractSyntaxTree>(node: &mut T) {
+    for mut child in node.child_nodes() {
+        fold_constant_loops(&mut child);
+
+        if let TypeCheckedNode::Statement(stat) = child {
+            let folded = match &stat.kind {
+                TypeCheckedStatementKind::While(
+                    TypeCheckedExpr {
+                        kind: TypeCheckedExprKind::Const(Value::Int(cond), Type::Bool),
+                        ..
+                    },
+                    body,
+                ) => Some(match cond.is_zero() {
+                    true => TypeCheckedExprKind::CodeBlock(TypeCheckedCodeBlock {
+                        body: vec![],
+                        ret_expr: None,
+                        scope: None,
+                    }),
+                    false => TypeCheckedExprKind::Loop(body.clone(), Type::Every),
+                }),
+                _ => None,
+            };
+            if let Some(kind) = folded {
+                let expr = TypeCheckedExpr::new(kind, stat.debug_info);
+                stat.kind = TypeCheckedStatementKind::Expression(expr);
+            }
+        }
+    }
+}
+
+/// Removes the asserts above the `kept` level, or every assert when `kept` is `None`. An
+/// `assert_eq` counts as a release-level assert. Conditions that write state are still evaluated,
+/// binding their results to `discard`, and the locations of the asserts they belong to are
+/// returned so callers can warn about them.
+pub fn strip_asserts<T: AbstractSyntaxTree>(
+    node: &mut T,
+    kept: Option<AssertLevel>,
+    discard: StringId,
+    type_tree: &TypeTree,
+) -> Vec<Location> {
+    let mut impure = vec![];
+    for mut child in node.child_nodes() {
+        impure.extend(strip_asserts(&mut child, kept, discard, type_tree));
+
+        if let TypeCheckedNode::Statement(stat) = child {
+            let (level, conditions) = match &mut stat.kind {
+                TypeCheckedStatementKind::Assert(cond, level) => (*level, vec![cond]),
+                TypeCheckedStatementKind::AssertEq(left, right, ..) => {
+                    (AssertLevel::Release, vec![left, right])
+                }
+                _ => continue,
+            };
+            if kept.map_or(false, |kept| level <= kept) {
+                continue;
+            }
+
+            let mut body = vec![];
+            for cond in conditions {
+                if cond.is_write(type_tree) {
+                    body.push(TypeCheckedStatement {
+                        kind: TypeCheckedStatementKind::SetLocals(
+                            vec![AssignRef::new(discard, true, cond.debug_info)],
+                            cond.clone(),
+                        ),
+                        debug_info: cond.debug_info,
+                    });
+                }
+            }
+            if !body.is_empty() {
+                impure.extend(stat.debug_info.location);
+            }
+
+            let block = TypeCheckedExprKind::CodeBlock(TypeCheckedCodeBlock {
+                body,
+                ret_expr: None,
+                scope: None,
+            });
+            let expr = TypeCheckedExpr::new(block, stat.debug_info);
+            stat.kind = TypeCheckedStatementKind::Expression(expr);
+        }
+    }
+    impure
+}
+
+/// Returns the value an option expression wraps, if the option is known to be `Some`.
+fn some_contents(expr: &TypeCheckedExpr) -> Option<TypeCheckedExpr> {
+    match &expr.kind {
+        TypeCheckedExprKind::Variant(inner) => Some((**inner).clone()),
+        TypeCheckedExprKind::Const(Value::Tuple(fields), Type::Option(tipe)) => {
+            match fields.as_slice() {
+                [Value::Int(tag), value] if *tag == Uint256::one() => Some(TypeCheckedExpr::new(
+                    TypeCheckedExprKind::Const(value.clone(), (**tipe).clone()),
+                    expr.debug_info,
+                )),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_fold_redundant_options() {
+    use super::ast::DebugInfo;
+
+    let debug_info = DebugInfo::default();
+    let expr = |kind| TypeCheckedExpr::new(kind, debug_info);
+    let local = expr(TypeCheckedExprKind::LocalVariableRef(0, Type::Uint));
+    let wrapped = expr(TypeCheckedExprKind::Variant(Box::new(local.clone())));
+
+    let if_let = expr(TypeCheckedExprKind::IfLet(
+        1,
+        Box::new(wrapped.clone()),
+        TypeCheckedCodeBlock {
+            body: vec![],
+            ret_expr: None,
+            scope: None,
+        },
+        None,
+        Type::Void,
+    ));
+    let mut block = TypeCheckedCodeBlock {
+        body: vec![TypeCheckedStatement {
+            kind: TypeCheckedStatementKind::Expression(if_let),
+            debug_info,
+        }],
+        ret_expr: Some(Box::new(expr(TypeCheckedExprKind::Try(
+            Box::new(wrapped),
+            Type::Uint,
+        )))),
+        scope: None,
+    };
+
+    fold_redundant_options(&mut block);
+
+    assert_eq!(block.ret_expr, Some(Box::new(local.clone())));
+    match &block.body[0].kind {
+        TypeCheckedStatementKind::Expression(TypeCheckedExpr {
+            kind: TypeCheckedExprKind::CodeBlock(folded),
+            ..
+        }) => match &folded.body[..] {
+            [TypeCheckedStatement {
+                kind: TypeCheckedStatementKind::SetLocals(assigned, value),
+                ..
+            }] => {
+                assert_eq!(assigned[0].id, 1);
+                assert_eq!(*value, local);
+            }
+            other => panic!("if let wasn't replaced by a binding: {:?}", other),
+        },
+        other => panic!("if let wasn't folded: {:?}", other),
+    }
+}
+
+#[test]
+fn test_fold_bool_ifs() {
+    use super::ast::DebugInfo;
+
+    let debug_info = DebugInfo::default();
+    let expr = |kind| TypeCheckedExpr::new(kind, debug_info);
+    let yields = |value| TypeCheckedCodeBlock {
+        body: vec![],
+        ret_expr: Some(Box::new(expr(TypeCheckedExprKind::Const(
+            Value::Int(Uint256::from_bool(value)),
+            Type::Bool,
+        )))),
+        scope: None,
+    };
+    let cond = expr(TypeCheckedExprKind::LocalVariableRef(0, Type::Bool));
+    let bool_if = |then, otherwise| {
+        expr(TypeCheckedExprKind::If(
+            Box::new(cond.clone()),
+            yields(then),
+            Some(yields(otherwise)),
+            Type::Bool,
+        ))
+    };
+
+    let mut block = TypeCheckedCodeBlock {
+        body: vec![TypeCheckedStatement {
+            kind: TypeCheckedStatementKind::Expression(bool_if(false, true)),
+            debug_info,
+        }],
+        ret_expr: Some(Box::new(bool_if(true, false))),
+        scope: None,
+    };
+    let mut same = TypeCheckedCodeBlock {
+        body: vec![],
+        ret_expr: Some(Box::new(bool_if(true, true))),
+        scope: None,
+    };
+
+    fold_bool_ifs(&mut block);
+    fold_bool_ifs(&mut same);
+
+    assert_eq!(block.ret_expr, Some(Box::new(cond.clone())));
+    match &block.body[0].kind {
+        TypeCheckedStatementKind::Expression(TypeCheckedExpr {
+            kind: TypeCheckedExprKind::UnaryOp(UnaryOp::Not, negated, Type::Bool),
+            ..
+        }) => assert_eq!(**negated, cond),
+        other => panic!("inverted if wasn't folded: {:?}", other),
+    }
+    assert_eq!(same.ret_expr, Some(Box::new(bool_if(true, true))));
+}
+
+#[test]
+fn test_fold_constant_loops() {
+    use super::ast::DebugInfo;
+
+    let debug_info = DebugInfo::default();
+    let stat = |kind| TypeCheckedStatement { kind, debug_info };
+    let body = TypeCheckedCodeBlock {
+        body: vec![stat(TypeCheckedStatementKind::Expression(
+            TypeCheckedExpr::new(TypeCheckedExprKind::Error, debug_info),
+        ))],
+        ret_expr: None,
+        scope: None,
+    };
+    let while_loop = |cond: bool| {
+        let cond = TypeCheckedExprKind::Const(Value::Int(Uint256::from_bool(cond)), Type::Bool);
+        stat(TypeCheckedStatementKind::While(
+            TypeCheckedExpr::new(cond, debug_info),
+            body.clone(),
+        ))
+    };
+
+    let mut block = TypeCheckedCodeBlock {
+        body: vec![while_loop(true), while_loop(false)],
+        ret_expr: None,
+        scope: None,
+    };
+    fold_constant_loops(&mut block);
+
+    match &block.body[..] {
+        [TypeCheckedStatement {
+            kind: TypeCheckedStatementKind::Expression(forever),
+            ..
+        }, TypeCheckedStatement {
+            kind: TypeCheckedStatementKind::Expression(never),
+            ..
+        }] => {
+            assert_eq!(
+                forever.kind,
+                TypeCheckedExprKind::Loop(body.clone(), Type::Every)
+            );
+            match &never.kind {
+                TypeCheckedExprKind::CodeBlock(empty) => assert!(empty.body.is_empty()),
+                other => panic!("while false wasn't dropped: {:?}", other),
+            }
+        }
+        other => panic!("loops weren't folded: {:?}", other),
+    }
+}
@@ -7,11 +7,14 @@
 use crate::pos::{BytePos, Column, Line, Location};
 
 /// Type which provides a bidirectional mapping between byte offsets and line and column locations
-/// for some source file
-#[derive(Clone, Debug)]
+/// for some source file.
+///
+/// Columns count characters rather than bytes, so multi-byte characters occupy a single column and
+/// tabs count as one column regardless of how wide they are displayed.
+#[derive(Clone, Debug, Default)]
 pub struct Lines {
     starting_bytes: Vec<BytePos>,
-    end: usize,
+    source: Vec<u8>,
 }
 
 impl Lines {
@@ -22,20 +25,19 @@ impl Lines {
     {
         use std::iter;
 
-        let mut len = 0;
+        let source: Vec<u8> = src.into_iter().collect();
         let starting_bytes = {
-            let input_indices = src
-                .into_iter()
-                .inspect(|_| len += 1)
+            let input_indices = source
+                .iter()
                 .enumerate()
-                .filter(|&(_, b)| b == b'\n')
+                .filter(|&(_, b)| *b == b'\n')
                 .map(|(i, _)| BytePos::from(i + 1)); // index of first char in the line
 
             iter::once(BytePos::from(0)).chain(input_indices).collect()
         };
         Lines {
             starting_bytes,
-            end: len,
+            source,
         }
     }
 
@@ -47,12 +49,18 @@ impl Lines {
 
     /// Returns the line and column location of `byte`
     pub fn location(&self, byte: BytePos, file_id: u64) -> Option<Location> {
-        if byte.to_usize() <= self.end {
+        if byte.to_usize() <= self.source.len() {
             let line_index = self.line_number_at_byte(byte);
 
             self.line(line_index).map(|line_byte| Location {
                 line: line_index,
-                column: Column::from((byte - line_byte).to_usize()),
+                column: Column::from(
+                    // count every byte that doesn't continue a multi-byte character
+                    self.source[line_byte.to_usize()..byte.to_usize()]
+                        .iter()
+                        .filter(|b| *b & 0xc0 != 0x80)
+                        .count(),
+                ),
                 absolute: byte,
                 file_id,
             })
@@ -74,3 +82,22 @@ impl Lines {
         )
     }
 }
+
+#[test]
+fn test_columns_count_characters() {
+    let source = "func main() {\n\tlet x = 2;\n    let \u{e9}t\u{e9} = \"\u{1f600}\"; x\n}\n";
+    let lines = Lines::new(source.bytes());
+
+    let locate = |pattern: &str| {
+        let byte = BytePos::from(source.find(pattern).unwrap());
+        let location = lines.location(byte, 0).unwrap();
+        (location.line.to_usize(), location.column.to_usize())
+    };
+
+    assert_eq!(locate("main"), (0, 5));
+    assert_eq!(locate("let x"), (1, 1)); // a tab is one column
+    assert_eq!(locate("\u{e9}t"), (2, 8));
+    assert_eq!(locate("= \""), (2, 12)); // each accented letter is one column
+    assert_eq!(locate("; x"), (2, 17)); // so is the emoji
+    assert_eq!(locate("}\n"), (3, 0));
+}
@@ -5,12 +5,13 @@
 //! Converts non-type checked ast nodes to type checked versions, and other related utilities.
 
 use super::ast::{
-    AssignRef, Attributes, BinaryOp, CodeBlock, Constant, DebugInfo, Expr, ExprKind, Func,
-    GlobalVar, Statement, StatementKind, StructField, TopLevelDecl, TrinaryOp, Type, TypeTree,
-    UnaryOp,
+    AssertLevel, AssignRef, Attributes, BinaryOp, CodeBlock, Constant, DebugInfo, Expr, ExprKind,
+    Func, GlobalVar, Lints, Statement, StatementKind, StructField, TopLevelDecl, TrinaryOp, Type,
+    TypeTree, UnaryOp,
 };
+use super::cfg::ControlFlowGraph;
 use crate::compile::ast::{FieldInitializer, FuncProperties};
-use crate::compile::{CompileError, ErrorSystem};
+use crate::compile::{explain, CompileError, ErrorSystem, Suggestion};
 use crate::console::{human_readable_index, Color};
 use crate::link::Import;
 use crate::mavm::{Instruction, Value};
@@ -88,12 +89,14 @@ impl<'a> TypeCheckedNode<'a> {
                 TypeCheckedNode::Statement(stat) => {
                     stat.debug_info.attributes.codegen_print =
                         stat.debug_info.attributes.codegen_print || attributes.codegen_print;
+                    stat.debug_info.attributes.allow.insert(attributes.allow);
                     let child_attributes = stat.debug_info.attributes.clone();
                     TypeCheckedNode::propagate_attributes(stat.child_nodes(), &child_attributes);
                 }
                 TypeCheckedNode::Expression(expr) => {
                     expr.debug_info.attributes.codegen_print =
                         expr.debug_info.attributes.codegen_print || attributes.codegen_print;
+                    expr.debug_info.attributes.allow.insert(attributes.allow);
                     let child_attributes = expr.debug_info.attributes.clone();
                     TypeCheckedNode::propagate_attributes(expr.child_nodes(), &child_attributes);
                     if let TypeCheckedExprKind::Asm(_, ref mut vec, _) = expr.kind {
@@ -192,7 +195,17 @@ fn flowcheck_reachability<T: AbstractSyntaxTree>(node: &mut T) -> Vec<CompileErr
     }
 
     match child_iter.next() {
-        Some(TypeCheckedNode::Statement(issue)) => locations.extend(issue.debug_info.location),
+        Some(TypeCheckedNode::Statement(issue)) => {
+            if issue
+                .debug_info
+                .attributes
+                .allow
+                .contains(Lints::UNREACHABLE)
+            {
+                return warnings;
+            }
+            locations.extend(issue.debug_info.location)
+        }
         _ => {}
     };
 
@@ -218,6 +231,731 @@ fn flowcheck_reachability<T: AbstractSyntaxTree>(node: &mut T) -> Vec<CompileErr
     warnings
 }
 
+/// Discovers expressions with side effects in positions whose evaluation should be pure, namely
+/// the condition of an `if` and the size of a `newarray`. Nodes marked `allow(impure)`
+/// are skipped.
+pub fn flowcheck_impure_positions<T: AbstractSyntaxTree>(
+    node: &mut T,
+    allowed: bool,
+    type_tree: &TypeTree,
+    string_table: &StringTable,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    for mut child in node.child_nodes() {
+        let allowed = allowed
+            || match &child {
+                TypeCheckedNode::Statement(stat) => {
+                    stat.debug_info.attributes.allow.contains(Lints::IMPURE)
+                }
+                TypeCheckedNode::Expression(expr) => {
+                    expr.debug_info.attributes.allow.contains(Lints::IMPURE)
+                }
+                TypeCheckedNode::Type(_) => false,
+            };
+
+        if let (TypeCheckedNode::Expression(expr), false) = (&mut child, allowed) {
+            let (position, inner) = match &mut expr.kind {
+                TypeCheckedExprKind::If(cond, ..) => ("condition of if", Some(&mut **cond)),
+                TypeCheckedExprKind::FunctionCall(func, args, ..) => match &func.kind {
+                    TypeCheckedExprKind::FuncRef(id, _)
+                        if string_table.name_from_id(*id) == "builtin_arrayNew" =>
+                    {
+                        ("size of newarray", args.first_mut())
+                    }
+                    _ => ("", None),
+                },
+                _ => ("", None),
+            };
+
+            if let Some(inner) = inner {
+                if inner.is_write(type_tree) {
+                    warnings.push(CompileError::new_warning(
+                        String::from("Compile warning"),
+                        format!(
+                            "the {} has side effects, consider binding it to a local first",
+                            Color::color(warn_color, position),
+                        ),
+                        inner.debug_info.location.into_iter().collect(),
+                    ));
+                }
+            }
+        }
+
+        warnings.extend(flowcheck_impure_positions(
+            &mut child,
+            allowed,
+            type_tree,
+            string_table,
+            warn_color,
+        ));
+    }
+
+    warnings
+}
+
+/// Discovers locally created arrays whose default layout nests tuples more than `max_depth` deep,
+/// namely those of a `newfixedarray`, an array literal, or a `newarray`. A `newarray` is measured
+/// at its smallest, since its size is only known when it runs.
+pub fn flowcheck_tuple_depths<T: AbstractSyntaxTree>(
+    node: &mut T,
+    max_depth: usize,
+    type_tree: &TypeTree,
+    string_table: &StringTable,
+) -> Vec<CompileError> {
+    let mut errors = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            let created = match &expr.kind {
+                TypeCheckedExprKind::NewFixedArray(..) => Some("newfixedarray"),
+                TypeCheckedExprKind::FunctionCall(func, ..) => match &func.kind {
+                    TypeCheckedExprKind::FuncRef(id, _)
+                        if string_table.name_from_id(*id) == "builtin_arrayNew" =>
+                    {
+                        Some("newarray")
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(created) = created {
+                let tipe = expr.get_type();
+                let (_, depth) = tipe.default_layout(type_tree);
+                if depth > max_depth {
+                    errors.push(CompileError::new_type_error(
+                        format!(
+                            "{} of type {} nests tuples {} deep, beyond the limit of {}",
+                            created,
+                            Color::red(tipe.print(type_tree)),
+                            depth,
+                            max_depth,
+                        ),
+                        expr.debug_info.locs(),
+                    ));
+                }
+            }
+        }
+        errors.extend(flowcheck_tuple_depths(
+            &mut child,
+            max_depth,
+            type_tree,
+            string_table,
+        ));
+    }
+
+    errors
+}
+
+/// Determines whether shifting `value` left by `amount` pushes any set bits past bit 255.
+fn shift_discards_bits(amount: &Uint256, value: &Uint256) -> bool {
+    match amount.to_usize() {
+        _ if value.is_zero() => false,
+        Some(amount) => value.shift_left(amount).shift_right(amount) != *value,
+        None => true,
+    }
+}
+
+/// Determines whether casting `value` to an `EthAddress` drops any bits above the low 160.
+fn address_discards_bits(value: &Uint256) -> bool {
+    !value.shift_right(160).is_zero()
+}
+
+/// Discovers operations on constants that lose set bits, which the typechecker leaves unfolded so
+/// they can be reported here. These are left shifts past the top of the 256-bit word and address
+/// casts of values wider than 160 bits, both of which are more likely typos than intended. Nodes
+/// marked `allow(lossy_constants)` are skipped.
+pub fn flowcheck_lossy_constants<T: AbstractSyntaxTree>(
+    node: &mut T,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            let warning = match &expr.kind {
+                TypeCheckedExprKind::Binary(BinaryOp::ShiftLeft, amount, value, _) => {
+                    match (&amount.kind, &value.kind) {
+                        (
+                            TypeCheckedExprKind::Const(Value::Int(amount), _),
+                            TypeCheckedExprKind::Const(Value::Int(value), _),
+                        ) if shift_discards_bits(amount, value) => Some(format!(
+                            "shifting {} left by {} discards set bits beyond bit 255",
+                            Color::color(warn_color, value),
+                            Color::color(warn_color, amount),
+                        )),
+                        _ => None,
+                    }
+                }
+                TypeCheckedExprKind::UnaryOp(UnaryOp::ToAddress, value, _) => match &value.kind {
+                    TypeCheckedExprKind::Const(Value::Int(value), _)
+                        if address_discards_bits(value) =>
+                    {
+                        Some(format!(
+                            "address {} is wider than 160 bits and will be truncated to {}",
+                            Color::color(warn_color, value),
+                            Color::color(warn_color, value.shift_left(96).shift_right(96)),
+                        ))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            let allowed = expr.debug_info.attributes.allow;
+            if let (Some(description), false) = (warning, allowed.contains(Lints::LOSSY_CONSTANTS))
+            {
+                warnings.push(CompileError::new_warning(
+                    String::from("Compile warning"),
+                    description,
+                    expr.debug_info.locs(),
+                ));
+            }
+        }
+        warnings.extend(flowcheck_lossy_constants(&mut child, warn_color));
+    }
+
+    warnings
+}
+
+/// Determines the result of a comparison whose operands are the same pure expression, such as
+/// `x == x` or `x < x`. Only operands that read locals, globals, constants, and their fields are
+/// considered, since evaluating these twice can't produce different values or trap.
+pub fn self_comparison(expr: &TypeCheckedExpr) -> Option<bool> {
+    fn same_pure_value(left: &TypeCheckedExpr, right: &TypeCheckedExpr) -> bool {
+        match (&left.kind, &right.kind) {
+            (
+                TypeCheckedExprKind::LocalVariableRef(left, _),
+                TypeCheckedExprKind::LocalVariableRef(right, _),
+            )
+            | (
+                TypeCheckedExprKind::GlobalVariableRef(left, _),
+                TypeCheckedExprKind::GlobalVariableRef(right, _),
+            ) => left == right,
+            (TypeCheckedExprKind::Const(left, _), TypeCheckedExprKind::Const(right, _)) => {
+                left == right
+            }
+            (
+                TypeCheckedExprKind::TupleRef(left, left_offset, ..),
+                TypeCheckedExprKind::TupleRef(right, right_offset, ..),
+            ) => left_offset == right_offset && same_pure_value(left, right),
+            (
+                TypeCheckedExprKind::Cast(left, left_type),
+                TypeCheckedExprKind::Cast(right, right_type),
+            ) => left_type == right_type && same_pure_value(left, right),
+            _ => false,
+        }
+    }
+
+    match &expr.kind {
+        TypeCheckedExprKind::Binary(op, left, right, _) if same_pure_value(left, right) => match op
+        {
+            BinaryOp::Equal
+            | BinaryOp::LessEq
+            | BinaryOp::GreaterEq
+            | BinaryOp::SLessEq
+            | BinaryOp::SGreaterEq => Some(true),
+            BinaryOp::NotEqual
+            | BinaryOp::LessThan
+            | BinaryOp::GreaterThan
+            | BinaryOp::SLessThan
+            | BinaryOp::SGreaterThan => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Discovers comparisons of a value against itself, which always produce the same result and are
+/// usually a copy-paste mistake. The simplifier later replaces them with their result. Nodes
+/// marked `allow(self_comparison)` are skipped.
+pub fn flowcheck_self_comparisons<T: AbstractSyntaxTree>(
+    node: &mut T,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            let allowed = expr.debug_info.attributes.allow;
+            if let (Some(result), false) = (
+                self_comparison(expr),
+                allowed.contains(Lints::SELF_COMPARISON),
+            ) {
+                warnings.push(CompileError::new_warning(
+                    String::from("Compile warning"),
+                    format!(
+                        "this compares a value to itself, so it's always {}",
+                        Color::color(warn_color, result),
+                    ),
+                    expr.debug_info.locs(),
+                ));
+            }
+        }
+        warnings.extend(flowcheck_self_comparisons(&mut child, warn_color));
+    }
+
+    warnings
+}
+
+/// Determines whether an `if` does nothing but yield one bool constant when its condition holds
+/// and the other when it doesn't, in which case it's equivalent to its condition or the negation
+/// thereof. Returns whether the condition must be negated.
+pub fn bool_if(expr: &TypeCheckedExpr) -> Option<bool> {
+    fn yielded_bool(block: &TypeCheckedCodeBlock) -> Option<bool> {
+        match (block.body.as_slice(), block.ret_expr.as_deref()) {
+            (
+                [],
+                Some(TypeCheckedExpr {
+                    kind: TypeCheckedExprKind::Const(Value::Int(value), Type::Bool),
+                    ..
+                }),
+            ) => Some(!value.is_zero()),
+            _ => None,
+        }
+    }
+
+    match &expr.kind {
+        TypeCheckedExprKind::If(_, block, Some(else_block), _) => {
+            match (yielded_bool(block)?, yielded_bool(else_block)?) {
+                (true, false) => Some(false),
+                (false, true) => Some(true),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Discovers ifs of the form `if cond { true } else { false }` and their inversions, which read
+/// more plainly as `cond` or `!cond`. The simplifier later replaces them accordingly. Nodes marked
+/// `allow(bool_if)` are skipped.
+pub fn flowcheck_bool_ifs<T: AbstractSyntaxTree>(
+    node: &mut T,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            let allowed = expr.debug_info.attributes.allow;
+            if let (Some(negated), false) = (bool_if(expr), allowed.contains(Lints::BOOL_IF)) {
+                warnings.push(CompileError::new_warning(
+                    String::from("Compile warning"),
+                    format!(
+                        "this if just yields {}, so it can be replaced by that",
+                        Color::color(
+                            warn_color,
+                            match negated {
+                                true => "the negation of its condition",
+                                false => "its condition",
+                            }
+                        ),
+                    ),
+                    expr.debug_info.locs(),
+                ));
+            }
+        }
+        warnings.extend(flowcheck_bool_ifs(&mut child, warn_color));
+    }
+
+    warnings
+}
+
+/// Discovers locals holding values that can't be freely duplicated, such as funcs, which are
+/// copied wholesale into another local. Nodes marked `allow(non_copy)` are skipped.
+pub fn flowcheck_non_copy_duplicates<T: AbstractSyntaxTree>(
+    node: &mut T,
+    type_tree: &TypeTree,
+    string_table: &StringTable,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Statement(stat) = &child {
+            let allowed = stat.debug_info.attributes.allow;
+            if let (TypeCheckedStatementKind::SetLocals(assigned, value), false) =
+                (&stat.kind, allowed.contains(Lints::NON_COPY))
+            {
+                if let ([_], TypeCheckedExprKind::LocalVariableRef(id, tipe)) =
+                    (assigned.as_slice(), &value.kind)
+                {
+                    if !tipe.is_copy(type_tree) {
+                        warnings.push(CompileError::new_warning(
+                            String::from("Compile warning"),
+                            format!(
+                                "local {} holds a value that can't be freely duplicated, but it's copied here",
+                                Color::color(warn_color, string_table.name_from_id(*id)),
+                            ),
+                            stat.debug_info.locs(),
+                        ));
+                    }
+                }
+            }
+        }
+        warnings.extend(flowcheck_non_copy_duplicates(
+            &mut child,
+            type_tree,
+            string_table,
+            warn_color,
+        ));
+    }
+
+    warnings
+}
+
+/// A struct field as it's accessed, identified by its name, its slot, and the number of fields in
+/// its struct.
+pub type FieldAccess = (String, usize, usize);
+
+/// Records every struct field read beneath `node` in `reads`, and every field replaced by a struct
+/// modifier in `writes`. Fields are recorded by shape rather than by the named type they belong to,
+/// so a read through an anonymous struct counts for every named struct of the same layout.
+pub fn struct_field_accesses<T: AbstractSyntaxTree>(
+    node: &mut T,
+    type_tree: &TypeTree,
+    reads: &mut BTreeSet<FieldAccess>,
+    writes: &mut BTreeSet<FieldAccess>,
+) {
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            let accessed = match &expr.kind {
+                TypeCheckedExprKind::TupleRef(inner, slot, ..) => {
+                    Some((true, inner.get_type(), *slot))
+                }
+                TypeCheckedExprKind::StructMod(_, slot, _, _, tipe) => {
+                    Some((false, tipe.clone(), *slot))
+                }
+                _ => None,
+            };
+            if let Some((read, tipe, slot)) = accessed {
+                if let Ok(Type::Struct(fields)) = tipe.rep(type_tree) {
+                    if let Some(field) = fields.get(slot) {
+                        let access = (field.name.clone(), slot, fields.len());
+                        match read {
+                            true => reads.insert(access),
+                            false => writes.insert(access),
+                        };
+                    }
+                }
+            }
+        }
+        struct_field_accesses(&mut child, type_tree, reads, writes);
+    }
+}
+
+/// Determines whether the option `expr` is always `Some` or always `None`, if either is known.
+fn constant_variant(expr: &TypeCheckedExpr) -> Option<bool> {
+    match &expr.kind {
+        TypeCheckedExprKind::Variant(_) => Some(true),
+        TypeCheckedExprKind::Const(Value::Tuple(fields), Type::Option(_)) => match fields.first() {
+            Some(Value::Int(tag)) => Some(!tag.is_zero()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Discovers `if let` expressions whose scrutinee is always `Some` or always `None`, which leaves
+/// one of the branches unreachable. The dead branch is reported first, followed by the `if let`
+/// itself. Nodes marked `allow(unreachable)` are skipped.
+pub fn flowcheck_constant_scrutinees<T: AbstractSyntaxTree>(
+    node: &mut T,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            if let TypeCheckedExprKind::IfLet(_, cond, block, else_block, _) = &expr.kind {
+                let dead = match constant_variant(cond) {
+                    Some(true) => else_block.as_ref().map(|block| ("Some", "else", block)),
+                    Some(false) => Some(("None", "then", block)),
+                    None => None,
+                };
+                let allowed = expr.debug_info.attributes.allow;
+
+                if let (Some((variant, branch, block)), false) =
+                    (dead, allowed.contains(Lints::UNREACHABLE))
+                {
+                    let start = block
+                        .body
+                        .iter()
+                        .map(|stat| stat.debug_info.location)
+                        .chain(block.ret_expr.iter().map(|expr| expr.debug_info.location))
+                        .flatten()
+                        .next();
+
+                    warnings.push(CompileError::new_warning(
+                        String::from("Compile warning"),
+                        format!(
+                            "the scrutinee of this if let is always {}, so its {} branch is unreachable",
+                            Color::color(warn_color, variant),
+                            Color::color(warn_color, branch),
+                        ),
+                        start.into_iter().chain(expr.debug_info.location).collect(),
+                    ));
+                }
+            }
+        }
+        warnings.extend(flowcheck_constant_scrutinees(&mut child, warn_color));
+    }
+
+    warnings
+}
+
+/// Finds the instructions of the `asm` blocks beneath `node` that write state, when `write` is
+/// set, or that read it otherwise. Such instructions are easy to miss when declaring a func's
+/// purity, so they're pointed out alongside the func.
+pub fn impure_asm_insns<T: AbstractSyntaxTree>(
+    node: &mut T,
+    type_tree: &TypeTree,
+    write: bool,
+) -> Vec<Instruction> {
+    let mut insns = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            if let TypeCheckedExprKind::Asm(_, body, _) = &expr.kind {
+                insns.extend(body.iter().cloned().filter(|insn| match write {
+                    true => insn.is_write(type_tree),
+                    false => insn.is_view(type_tree),
+                }));
+            }
+        }
+        insns.extend(impure_asm_insns(&mut child, type_tree, write));
+    }
+
+    insns
+}
+
+/// Finds where `setGas` is used beneath `node`, when `write` is set, or `getGas` otherwise.
+/// Handling gas is low-level work that's rarely intended in a func not declared to touch state, so
+/// each use is pointed out alongside the func.
+pub fn gas_accesses<T: AbstractSyntaxTree>(node: &mut T, write: bool) -> Vec<Location> {
+    let mut accesses = vec![];
+
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Expression(expr) = &child {
+            match (&expr.kind, write) {
+                (TypeCheckedExprKind::GetGas, false) | (TypeCheckedExprKind::SetGas(_), true) => {
+                    accesses.extend(expr.debug_info.location)
+                }
+                _ => {}
+            }
+        }
+        accesses.extend(gas_accesses(&mut child, write));
+    }
+
+    accesses
+}
+
+/// Determines whether `func` is a placeholder whose whole body is `error;` or `return error;`.
+/// Such funcs are reported for programs compiled with `--warn-unimplemented`, unless marked
+/// `allow(unimplemented)`.
+pub fn flowcheck_unimplemented(
+    func: &TypeCheckedFunc,
+    name: &str,
+    warn_color: &str,
+) -> Option<CompileError> {
+    // funcs without a return type get a trailing `return;`
+    let body = match func.code.as_slice() {
+        [body @ .., TypeCheckedStatement {
+            kind: TypeCheckedStatementKind::ReturnVoid(),
+            ..
+        }] => body,
+        body => body,
+    };
+    let stub = match body {
+        [stat] => match &stat.kind {
+            TypeCheckedStatementKind::Expression(expr) | TypeCheckedStatementKind::Return(expr) => {
+                matches!(expr.kind, TypeCheckedExprKind::Error)
+            }
+            _ => false,
+        },
+        _ => false,
+    };
+
+    match stub
+        && !func
+            .debug_info
+            .attributes
+            .allow
+            .contains(Lints::UNIMPLEMENTED)
+    {
+        true => Some(CompileError::new_warning(
+            String::from("Compile warning"),
+            format!("func {} is unimplemented", Color::color(warn_color, name)),
+            func.debug_info.locs(),
+        )),
+        false => None,
+    }
+}
+
+/// Discovers values that are silently widened to `any`, for programs compiled with
+/// `--no-implicit-any`. A value is widened when it's passed as an `any` arg, returned from a func
+/// returning `any`, or assigned to an `any` global. The null value that fills a `newarray` of `any`
+/// is reported there too, since nothing was written to produce it. Widening explicitly, with a
+/// cast or a `let` declared `any`, isn't reported, nor is anything marked `allow(implicit_any)`.
+pub fn flowcheck_implicit_any<T: AbstractSyntaxTree>(
+    node: &mut T,
+    ret_type: &Type,
+    globals: &HashMap<StringId, Type>,
+    type_tree: &TypeTree,
+    warn_color: &str,
+) -> Vec<CompileError> {
+    let mut warnings = vec![];
+
+    let is_any = |tipe: &Type| matches!(tipe.rep(type_tree), Ok(Type::Any));
+    let widened = |expected: &Type, expr: &TypeCheckedExpr, allowed: Lints| {
+        let actual = expr.get_type();
+        let null = matches!(&expr.kind, TypeCheckedExprKind::Const(_, tipe) if is_any(tipe));
+        if !is_any(expected) || allowed.contains(Lints::IMPLICIT_ANY) {
+            return None;
+        }
+        match (null, is_any(&actual) || matches!(actual, Type::Every)) {
+            (true, _) => Some(CompileError::new_warning(
+                String::from("Compile warning"),
+                format!(
+                    "{} value of type {} is implicitly filled in",
+                    Color::color(warn_color, "null"),
+                    Color::color(warn_color, "any"),
+                ),
+                expr.debug_info.locs(),
+            )),
+            (false, false) => Some(CompileError::new_warning(
+                String::from("Compile warning"),
+                format!(
+                    "value of type {} is implicitly widened to {}",
+                    Color::color(warn_color, actual.print(type_tree)),
+                    Color::color(warn_color, "any"),
+                ),
+                expr.debug_info.locs(),
+            )),
+            (false, true) => None,
+        }
+    };
+
+    for mut child in node.child_nodes() {
+        match &child {
+            TypeCheckedNode::Statement(stat) => {
+                let allowed = stat.debug_info.attributes.allow;
+                match &stat.kind {
+                    TypeCheckedStatementKind::Return(expr) => {
+                        warnings.extend(widened(ret_type, expr, allowed))
+                    }
+                    TypeCheckedStatementKind::AssignGlobal(id, expr) => {
+                        if let Some(tipe) = globals.get(id) {
+                            warnings.extend(widened(tipe, expr, allowed));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            TypeCheckedNode::Expression(expr) => {
+                if let TypeCheckedExprKind::FunctionCall(func, args, ..) = &expr.kind {
+                    if let Ok(Type::Func(_, arg_types, _)) = func.get_type().rep(type_tree) {
+                        for (arg, tipe) in args.iter().zip(arg_types.iter()) {
+                            let allowed = arg.debug_info.attributes.allow;
+                            warnings.extend(widened(tipe, arg, allowed));
+                        }
+                    }
+                }
+            }
+            TypeCheckedNode::Type(_) => {}
+        }
+        warnings.extend(flowcheck_implicit_any(
+            &mut child, ret_type, globals, type_tree, warn_color,
+        ));
+    }
+
+    warnings
+}
+
+/// Discovers reassignments of locals that weren't declared `mut`, for programs compiled with
+/// `--require-mut`. The `bindings` map each local in scope to whether it's mutable and where it
+/// was declared. Func args and the values bound by `if let` may always be reassigned.
+pub fn flowcheck_mutability<T: AbstractSyntaxTree>(
+    node: &mut T,
+    bindings: &mut HashMap<StringId, (bool, Option<Location>)>,
+    string_table: &StringTable,
+) -> Vec<CompileError> {
+    let mut errors = vec![];
+
+    for mut child in node.child_nodes() {
+        let mut scope = bindings.clone();
+        if let TypeCheckedNode::Expression(expr) = &child {
+            if let TypeCheckedExprKind::IfLet(id, ..) = &expr.kind {
+                scope.insert(*id, (true, None));
+            }
+        }
+        errors.extend(flowcheck_mutability(&mut child, &mut scope, string_table));
+
+        if let TypeCheckedNode::Statement(stat) = &child {
+            if let TypeCheckedStatementKind::SetLocals(assigned, _) = &stat.kind {
+                for local in assigned {
+                    if local.shadow {
+                        bindings.insert(local.id, (local.mutable, local.debug_info.location));
+                        continue;
+                    }
+                    if let Some((false, declared)) = bindings.get(&local.id) {
+                        let name = string_table.name_from_id(local.id);
+                        let error = CompileError::new_type_error(
+                            format!(
+                                "local {} is reassigned but wasn't declared {}",
+                                Color::red(name),
+                                Color::red("mut"),
+                            ),
+                            local
+                                .debug_info
+                                .location
+                                .iter()
+                                .chain(declared)
+                                .cloned()
+                                .collect(),
+                        );
+                        errors.push(match declared {
+                            Some(loc) => error.with_suggestion(Suggestion::replace(
+                                *loc,
+                                name,
+                                format!("mut {}", name),
+                            )),
+                            None => error,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+/// Collects the locations of the statements beneath `node` that allow `lint`, along with those of
+/// the locals they assign, so that warnings raised at these places can be dropped.
+fn allowed_locations<T: AbstractSyntaxTree>(
+    node: &mut T,
+    lint: Lints,
+    allowed: &mut BTreeSet<Location>,
+) {
+    for mut child in node.child_nodes() {
+        if let TypeCheckedNode::Statement(stat) = &child {
+            if stat.debug_info.attributes.allow.contains(lint) {
+                allowed.extend(stat.debug_info.location);
+                if let TypeCheckedStatementKind::SetLocals(assigned, _) = &stat.kind {
+                    allowed.extend(
+                        assigned
+                            .iter()
+                            .filter_map(|local| local.debug_info.location),
+                    );
+                }
+            }
+        }
+        allowed_locations(&mut child, lint, allowed);
+    }
+}
+
 /// Discovers assigned values that are never used
 fn flowcheck_liveliness(
     mut nodes: Vec<TypeCheckedNode>,
@@ -432,25 +1170,35 @@ impl TypeCheckedFunc {
         let (killed, reborn) =
             flowcheck_liveliness(self.child_nodes(), &mut unused_assignments, false);
 
+        let mut allowed = BTreeSet::new();
+        allowed_locations(self, Lints::UNUSED, &mut allowed);
+        let allow_args = self.debug_info.attributes.allow.contains(Lints::UNUSED);
+
         for arg in self.args.iter() {
             // allow intentional lack of use
-            if !string_table.name_from_id(arg.name.clone()).starts_with('_') {
+            if !string_table.name_from_id(arg.name.clone()).starts_with('_') && !allow_args {
                 if !killed.contains(&arg.name) {
-                    flowcheck_warnings.push(CompileError::new_warning(
+                    let name = string_table.name_from_id(arg.name.clone());
+                    let warning = CompileError::new_warning(
                         String::from("Compile warning"),
                         format!(
                             "func {}'s argument {} is declared but never used",
                             Color::color(error_system.warn_color, &self.name),
-                            Color::color(
-                                error_system.warn_color,
-                                string_table.name_from_id(arg.name.clone())
-                            ),
+                            Color::color(error_system.warn_color, name),
                         ),
                         arg.debug_info.location.into_iter().collect(),
-                    ));
+                    );
+                    flowcheck_warnings.push(match arg.debug_info.location {
+                        Some(loc) => warning.with_suggestion(Suggestion::replace(
+                            loc,
+                            name,
+                            format!("_{}", name),
+                        )),
+                        None => warning,
+                    });
                 }
 
-                if let Some(loc) = reborn.get(&arg.name) {
+                if let Some(loc) = reborn.get(&arg.name).filter(|loc| !allowed.contains(loc)) {
                     flowcheck_warnings.push(CompileError::new_warning(
                         String::from("Compile warning"),
                         format!(
@@ -469,7 +1217,7 @@ impl TypeCheckedFunc {
 
         for &(loc, id) in unused_assignments.iter() {
             // allow intentional lack of use
-            if !string_table.name_from_id(id.clone()).starts_with('_') {
+            if !string_table.name_from_id(id.clone()).starts_with('_') && !allowed.contains(&loc) {
                 flowcheck_warnings.push(CompileError::new_warning(
                     String::from("Compile warning"),
                     format!(
@@ -502,7 +1250,9 @@ pub enum TypeCheckedStatementKind {
     AssignGlobal(StringId, TypeCheckedExpr),
     While(TypeCheckedExpr, TypeCheckedCodeBlock),
     DebugPrint(TypeCheckedExpr),
-    Assert(TypeCheckedExpr),
+    Assert(TypeCheckedExpr, AssertLevel),
+    /// Whether the values should be equal, along with their static types for failure messages.
+    AssertEq(TypeCheckedExpr, TypeCheckedExpr, bool, String),
 }
 
 impl AbstractSyntaxTree for TypeCheckedStatement {
@@ -513,8 +1263,12 @@ impl AbstractSyntaxTree for TypeCheckedStatement {
             | TypeCheckedStatementKind::Expression(exp)
             | TypeCheckedStatementKind::SetLocals(_, exp)
             | TypeCheckedStatementKind::AssignGlobal(_, exp)
-            | TypeCheckedStatementKind::Assert(exp)
+            | TypeCheckedStatementKind::Assert(exp, _)
             | TypeCheckedStatementKind::DebugPrint(exp) => vec![TypeCheckedNode::Expression(exp)],
+            TypeCheckedStatementKind::AssertEq(left, right, ..) => vec![
+                TypeCheckedNode::Expression(left),
+                TypeCheckedNode::Expression(right),
+            ],
             TypeCheckedStatementKind::While(exp, block) => vec![TypeCheckedNode::Expression(exp)]
                 .into_iter()
                 .chain(block.child_nodes())
@@ -549,6 +1303,8 @@ pub struct TypeCheckedExpr {
 pub enum TypeCheckedExprKind {
     NewBuffer,
     Quote(Vec<u8>),
+    /// The contents of a file, which the compiler reads in place of this before codegen.
+    IncludeBytes(String),
     UnaryOp(UnaryOp, Box<TypeCheckedExpr>, Type),
     Binary(BinaryOp, Box<TypeCheckedExpr>, Box<TypeCheckedExpr>, Type),
     Trinary(
@@ -623,6 +1379,7 @@ impl AbstractSyntaxTree for TypeCheckedExpr {
             | TypeCheckedExprKind::Const(..)
             | TypeCheckedExprKind::NewBuffer
             | TypeCheckedExprKind::Quote(..)
+            | TypeCheckedExprKind::IncludeBytes(..)
             | TypeCheckedExprKind::GetGas
             | TypeCheckedExprKind::Error => vec![],
             TypeCheckedExprKind::UnaryOp(_, exp, _)
@@ -763,7 +1520,9 @@ impl TypeCheckedExpr {
     pub fn get_type(&self) -> Type {
         match &self.kind {
             TypeCheckedExprKind::NewBuffer => Type::Buffer,
-            TypeCheckedExprKind::Quote(_) => Type::Tuple(vec![Type::Uint, Type::Buffer]),
+            TypeCheckedExprKind::Quote(_) | TypeCheckedExprKind::IncludeBytes(_) => {
+                Type::Tuple(vec![Type::Uint, Type::Buffer])
+            }
             TypeCheckedExprKind::Error => Type::Every,
             TypeCheckedExprKind::GetGas => Type::Uint,
             TypeCheckedExprKind::SetGas(_t) => Type::Void,
@@ -821,6 +1580,7 @@ fn builtin_func_decls() -> Vec<Import> {
         Import::new_builtin("kvs", "builtin_kvsNew"),
         Import::new_builtin("kvs", "builtin_kvsGet"),
         Import::new_builtin("kvs", "builtin_kvsSet"),
+        Import::new_builtin("kvs", "builtin_kvsKeys"),
     ]
 }
 
@@ -836,6 +1596,7 @@ pub fn sort_top_level_decls(
     HashMap<usize, Type>,
     Vec<GlobalVar>,
     HashMap<usize, Type>,
+    HashMap<usize, Location>,
 ) {
     let (decls, closures) = parsed;
 
@@ -856,6 +1617,7 @@ pub fn sort_top_level_decls(
     //let mut imports = vec![];
     let mut funcs = vec![];
     let mut named_types = HashMap::new();
+    let mut type_locations = HashMap::new();
     let mut func_table = HashMap::new();
     let mut globals = vec![];
 
@@ -869,6 +1631,9 @@ pub fn sort_top_level_decls(
                 funcs.push(fd);
             }
             TopLevelDecl::TypeDecl(td) => {
+                if let Some(location) = td.location {
+                    type_locations.insert(td.name, location);
+                }
                 named_types.insert(td.name, td.tipe);
             }
             TopLevelDecl::VarDecl(vd) => {
@@ -882,11 +1647,21 @@ pub fn sort_top_level_decls(
         func_table.insert(*id, closure.tipe.clone());
     }
 
-    (imports, funcs, named_types, globals, func_table)
+    (
+        imports,
+        funcs,
+        named_types,
+        globals,
+        func_table,
+        type_locations,
+    )
 }
 
 /// Performs typechecking various top level declarations, `FuncDecl`s,
 /// named `Type`s, and global variables.
+///
+/// Every func is checked even after one fails, so that the errors of all the funcs that fail are
+/// returned together.
 pub fn typecheck_top_level_decls(
     funcs: Vec<Func>,
     named_types: &HashMap<usize, Type>,
@@ -902,7 +1677,7 @@ pub fn typecheck_top_level_decls(
         Vec<GlobalVar>,
         StringTable,
     ),
-    CompileError,
+    Vec<CompileError>,
 > {
     if let Some(var) = global_vars
         .iter()
@@ -920,13 +1695,28 @@ pub fn typecheck_top_level_decls(
     let mut undefinable_ids = HashMap::new(); // ids no one is allowed to define
     for import in imports {
         undefinable_ids.insert(
-            string_table.get_if_exists(&import.name).unwrap(),
+            string_table.get_if_exists(import.local_name()).unwrap(),
             import.location,
         );
     }
 
+    for global in &global_vars {
+        if let Some((name, fields, locations)) = global.tipe.recursive_default(type_tree) {
+            return Err(vec![CompileError::new_type_error(
+                format!(
+                    "global {} has no default value, since {} contains itself as {}; wrap the recursive part in an option",
+                    Color::red(&global.name),
+                    Color::red(&name),
+                    Color::red(format!("{}{}", name, fields)),
+                ),
+                global.debug_info.locs().into_iter().chain(locations).collect(),
+            )]);
+        }
+    }
+
     let mut checked_funcs = BTreeMap::new();
     let mut checked_closures = BTreeMap::new();
+    let mut errors = vec![];
 
     for func in &funcs {
         let mut type_tree = type_tree.clone();
@@ -939,19 +1729,25 @@ pub fn typecheck_top_level_decls(
                 ),
             );
         }
-        checked_funcs.insert(
-            func.id,
-            typecheck_function(
-                &func,
-                &type_table,
-                &global_vars_map,
-                &func_table,
-                &type_tree,
-                &string_table,
-                &mut checked_closures,
-                &mut undefinable_ids,
-            )?,
-        );
+        match typecheck_function(
+            &func,
+            &type_table,
+            &global_vars_map,
+            &func_table,
+            &type_tree,
+            &string_table,
+            &mut checked_closures,
+            &mut undefinable_ids,
+        ) {
+            Ok(checked) => {
+                checked_funcs.insert(func.id, checked);
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
     checked_funcs.extend(checked_closures);
@@ -985,6 +1781,21 @@ pub fn typecheck_function(
         .iter_mut()
         .for_each(|arg| arg.tipe = arg.tipe.commit_generic_slots());
 
+    // bind the names of any destructured args before the body runs
+    let destructures: Vec<_> = func
+        .args
+        .iter()
+        .filter_map(|arg| {
+            let pattern = arg.pattern.clone()?;
+            let value = Expr::new(ExprKind::VariableRef(arg.name, vec![]), arg.debug_info);
+            Some(Statement::new(
+                StatementKind::Let(pattern, value),
+                arg.debug_info,
+            ))
+        })
+        .collect();
+    func.code.splice(0..0, destructures);
+
     let mut hm = HashMap::new();
 
     if let Some(location_option) = undefinable_ids.get(&func.id) {
@@ -1062,25 +1873,19 @@ pub fn typecheck_function(
                     Color::red(string_table.name_from_id(func.id))
                 ),
                 func.debug_info.locs(),
-            ));
+            )
+            .with_code(explain::MISSING_RETURN));
         }
-        if let Some(stat) = func.code.last() {
-            match &stat.kind {
-                StatementKind::Return(_) => {}
-                _ => {
-                    return Err(CompileError::new_type_error(
-                        format!(
-                            "Func {}'s last statement does not a return a value",
-                            Color::red(string_table.name_from_id(func.id)),
-                        ),
-                        func.debug_info
-                            .location
-                            .into_iter()
-                            .chain(stat.debug_info.location.into_iter())
-                            .collect(),
-                    ))
-                }
-            }
+        if ControlFlowGraph::new(&tc_stats).falls_through() {
+            let last = tc_stats.last().and_then(|stat| stat.debug_info.location);
+            return Err(CompileError::new_type_error(
+                format!(
+                    "Func {} can reach its end without returning a value",
+                    Color::red(string_table.name_from_id(func.id)),
+                ),
+                func.debug_info.location.into_iter().chain(last).collect(),
+            )
+            .with_code(explain::MISSING_RETURN));
         }
     }
 
@@ -1201,6 +2006,9 @@ fn typecheck_statement<'a>(
     let debug_info = statement.debug_info;
 
     macro_rules! error {
+        (@$code:ident $text:expr $(,$args:expr)* $(,)?) => {
+            return Err(CompileError::new("Typecheck error", format!($text, $(Color::red($args),)*), debug_info.locs()).with_code(explain::$code))
+        };
         ($text:expr $(,$args:expr)* $(,)?) => {
             return Err(CompileError::new("Typecheck error", format!($text, $(Color::red($args),)*), debug_info.locs()))
         };
@@ -1218,8 +2026,9 @@ fn typecheck_statement<'a>(
             }
         }
         StatementKind::Return(expr) => {
-            let expr = typecheck_expr(
+            let expr = typecheck_expr_expecting(
                 expr,
+                &func.ret_type,
                 type_table,
                 global_vars,
                 func_table,
@@ -1237,7 +2046,7 @@ fn typecheck_statement<'a>(
             if ret_type.assignable(&tipe, type_tree, HashSet::new()) {
                 Ok((TypeCheckedStatementKind::Return(expr), vec![]))
             } else {
-                error!(
+                error!(@TYPE_MISMATCH
                     "return statement has wrong type:\nencountered {}\ninstead of  {}",
                     tipe.print(type_tree),
                     ret_type.print(type_tree),
@@ -1260,6 +2069,7 @@ fn typecheck_statement<'a>(
                 closures,
                 scopes,
             )?;
+            // every value must be used, or explicitly dropped with `let _ = ...`
             let tipe = expr.get_type();
             if !matches!(tipe, Type::Void | Type::Every) {
                 error!("Statement discards {} value", tipe.print(type_tree));
@@ -1267,18 +2077,36 @@ fn typecheck_statement<'a>(
             Ok((TypeCheckedStatementKind::Expression(expr), vec![]))
         }
         StatementKind::Let(assigned, expr) => {
-            let expr = typecheck_expr(
-                expr,
-                type_table,
-                global_vars,
-                func_table,
-                func,
-                type_tree,
-                string_table,
-                undefinable_ids,
-                closures,
-                scopes,
-            )?;
+            let expr = match assigned.as_slice() {
+                [AssignRef {
+                    tipe: Some(expected),
+                    ..
+                }] => typecheck_expr_expecting(
+                    expr,
+                    expected,
+                    type_table,
+                    global_vars,
+                    func_table,
+                    func,
+                    type_tree,
+                    string_table,
+                    undefinable_ids,
+                    closures,
+                    scopes,
+                )?,
+                _ => typecheck_expr(
+                    expr,
+                    type_table,
+                    global_vars,
+                    func_table,
+                    func,
+                    type_tree,
+                    string_table,
+                    undefinable_ids,
+                    closures,
+                    scopes,
+                )?,
+            };
 
             let types = match expr.get_type() {
                 Type::Tuple(vec) if assigned.len() > 1 => vec.clone(),
@@ -1286,11 +2114,31 @@ fn typecheck_statement<'a>(
             };
 
             if types.len() != assigned.len() {
-                error!(
-                    "Left side needs {} items but right has {}",
-                    assigned.len(),
-                    types.len()
-                );
+                // point at the patterns left without a value, or at the values of a tuple literal
+                // left without a pattern, falling back to the whole statement
+                let unmatched: Vec<_> = match &expr.kind {
+                    _ if assigned.len() > types.len() => assigned[types.len()..]
+                        .iter()
+                        .filter_map(|extra| extra.debug_info.location)
+                        .collect(),
+                    TypeCheckedExprKind::Tuple(values, _) => values[assigned.len()..]
+                        .iter()
+                        .filter_map(|extra| extra.debug_info.location)
+                        .collect(),
+                    _ => vec![],
+                };
+                return Err(CompileError::new(
+                    "Typecheck error",
+                    format!(
+                        "Left side needs {} items but right has {}",
+                        Color::red(assigned.len()),
+                        Color::red(types.len()),
+                    ),
+                    match unmatched.is_empty() {
+                        true => debug_info.locs(),
+                        false => unmatched,
+                    },
+                ));
             }
 
             let mut bindings = vec![];
@@ -1327,6 +2175,38 @@ fn typecheck_statement<'a>(
                     ))?
                 }
 
+                let tipe = match &assigned.tipe {
+                    Some(declared) => {
+                        if !declared.assignable(&tipe, type_tree, HashSet::new()) {
+                            let hint = match tipe.display_source(type_tree) {
+                                Some(source) => format!(
+                                    "\n the value could be declared as {}",
+                                    Color::red(format!(
+                                        "{}: {}",
+                                        string_table.name_from_id(id),
+                                        source
+                                    ))
+                                ),
+                                None => String::new(),
+                            };
+                            Err(CompileError::new_type_error(
+                                format!(
+                                    "Cannot make assignment for {}\n {}{}",
+                                    name,
+                                    declared
+                                        .mismatch_string(&tipe, type_tree)
+                                        .unwrap_or("Did not find mismatch".to_string()),
+                                    hint,
+                                ),
+                                assigned.debug_info.locs(),
+                            )
+                            .with_code(explain::TYPE_MISMATCH))?
+                        }
+                        declared.clone()
+                    }
+                    None => tipe,
+                };
+
                 if assigned.shadow {
                     bindings.push((id, tipe));
                 } else {
@@ -1348,7 +2228,8 @@ fn typecheck_statement<'a>(
                         None => Err(CompileError::new_type_error(
                             format!("assignment to undeclared variable {}", name),
                             assigned.debug_info.locs(),
-                        ))?,
+                        )
+                        .with_code(explain::UNRECOGNIZED_IDENTIFIER))?,
                     }
                 }
             }
@@ -1378,7 +2259,7 @@ fn typecheck_statement<'a>(
                     let assigned = vec![AssignRef::new(*id, false, debug_info)];
                     Ok((TypeCheckedStatementKind::SetLocals(assigned, expr), vec![]))
                 } else {
-                    error!(
+                    error!(@TYPE_MISMATCH
                         "mismatched types in assignment statement {}",
                         var_type
                             .mismatch_string(&tipe, type_tree)
@@ -1389,7 +2270,7 @@ fn typecheck_statement<'a>(
                 if var_type.assignable(&tipe, type_tree, HashSet::new()) {
                     Ok((TypeCheckedStatementKind::AssignGlobal(*id, expr), vec![]))
                 } else {
-                    error!(
+                    error!(@TYPE_MISMATCH
                         "mismatched types in assignment statement {}",
                         var_type
                             .mismatch_string(&tipe, type_tree)
@@ -1397,7 +2278,7 @@ fn typecheck_statement<'a>(
                     );
                 }
             } else {
-                error!(
+                error!(@UNRECOGNIZED_IDENTIFIER
                     "assignment to undeclared variable {}",
                     string_table.name_from_id(*id)
                 );
@@ -1453,7 +2334,7 @@ fn typecheck_statement<'a>(
             )?;
             Ok((TypeCheckedStatementKind::DebugPrint(tce), vec![]))
         }
-        StatementKind::Assert(expr) => {
+        StatementKind::Assert(expr, level) => {
             let tce = typecheck_expr(
                 expr,
                 type_table,
@@ -1468,7 +2349,7 @@ fn typecheck_statement<'a>(
             )?;
             match tce.get_type() {
                 Type::Tuple(vec) if vec.len() == 2 && vec[0] == Type::Bool => {
-                    Ok((TypeCheckedStatementKind::Assert(tce), vec![]))
+                    Ok((TypeCheckedStatementKind::Assert(tce, *level), vec![]))
                 }
                 _ => error!(
                     "assert condition must be of type (bool, any), found {}",
@@ -1476,6 +2357,45 @@ fn typecheck_statement<'a>(
                 ),
             }
         }
+        StatementKind::AssertEq(left, right, equal) => {
+            let mut operands = vec![];
+            for expr in &[left, right] {
+                operands.push(typecheck_expr(
+                    expr,
+                    type_table,
+                    global_vars,
+                    func_table,
+                    func,
+                    type_tree,
+                    string_table,
+                    undefinable_ids,
+                    closures,
+                    scopes,
+                )?);
+            }
+            let right = operands.pop().unwrap();
+            let left = operands.pop().unwrap();
+            let (left_type, right_type) = (left.get_type(), right.get_type());
+            if !left_type.assignable(&right_type, type_tree, HashSet::new())
+                || !right_type.assignable(&left_type, type_tree, HashSet::new())
+            {
+                error!(
+                    "assert_{} operands can't be compared: {} and {}",
+                    if *equal { "eq" } else { "ne" },
+                    left_type.print(type_tree),
+                    right_type.print(type_tree),
+                );
+            }
+            let types = format!(
+                "({}, {})",
+                left_type.print(type_tree),
+                right_type.print(type_tree)
+            );
+            Ok((
+                TypeCheckedStatementKind::AssertEq(left, right, *equal, types),
+                vec![],
+            ))
+        }
     }?;
     Ok((
         TypeCheckedStatement {
@@ -1486,6 +2406,65 @@ fn typecheck_statement<'a>(
     ))
 }
 
+/// Performs type checking on an expression whose type is known from context, such as the value of
+/// a let with a type annotation, an argument to a func, or the value of a return statement. Only
+/// these positions provide an `expected` type.
+///
+/// When `int` is expected, an unsuffixed integer literal, or the negation of one, adopts that type
+/// instead of `uint`, provided its value fits. Every other expression is checked by
+/// `typecheck_expr` as usual, so the caller must still verify the result is assignable.
+fn typecheck_expr_expecting(
+    expr: &Expr,
+    expected: &Type,
+    type_table: &TypeTable,
+    global_vars: &HashMap<StringId, Type>,
+    func_table: &TypeTable,
+    func: &Func,
+    type_tree: &TypeTree,
+    string_table: &StringTable,
+    undefinable_ids: &mut HashMap<StringId, Option<Location>>,
+    closures: &mut BTreeMap<StringId, TypeCheckedFunc>,
+    scopes: &mut Vec<(String, Option<Type>)>,
+) -> Result<TypeCheckedExpr, CompileError> {
+    if let Ok(Type::Int) = expected.rep(type_tree) {
+        if let Some(value) = signed_literal(expr) {
+            return Ok(TypeCheckedExpr::new(
+                TypeCheckedExprKind::Const(Value::Int(value), Type::Int),
+                expr.debug_info,
+            ));
+        }
+    }
+    typecheck_expr(
+        expr,
+        type_table,
+        global_vars,
+        func_table,
+        func,
+        type_tree,
+        string_table,
+        undefinable_ids,
+        closures,
+        scopes,
+    )
+}
+
+/// Reads an unsuffixed integer literal, or the negation of one, as an `int`, returning `None` if
+/// `expr` isn't such a literal or its value lies outside the range of an `int`.
+fn signed_literal(expr: &Expr) -> Option<Uint256> {
+    match &expr.kind {
+        ExprKind::Constant(Constant::Uint(value)) => {
+            Some(value.clone()).filter(|value| !value.s_less_than(&Uint256::zero()))
+        }
+        ExprKind::UnaryOp(UnaryOp::Minus, inner) => match &inner.kind {
+            ExprKind::Constant(Constant::Uint(value)) => value
+                .unary_minus()
+                .filter(|negated| !Uint256::zero().s_less_than(negated)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// Performs type checking on the expression expr.  Returns `TypeCheckedExpr` if successful, and
 /// `CompileError` otherwise.
 ///
@@ -1509,6 +2488,9 @@ fn typecheck_expr(
     let loc = debug_info.location;
 
     macro_rules! error {
+        (@$code:ident $text:expr $(,$args:expr)* $(,)?) => {
+            return Err(CompileError::new("Typecheck error", format!($text, $(Color::red($args),)*), debug_info.locs()).with_code(explain::$code))
+        };
         ($text:expr $(,$args:expr)* $(,)?) => {
             return Err(CompileError::new("Typecheck error", format!($text, $(Color::red($args),)*), debug_info.locs()))
         };
@@ -1518,6 +2500,7 @@ fn typecheck_expr(
         kind: match &expr.kind {
             ExprKind::NewBuffer => Ok(TypeCheckedExprKind::NewBuffer),
             ExprKind::Quote(buf) => Ok(TypeCheckedExprKind::Quote(buf.clone())),
+            ExprKind::IncludeBytes(path) => Ok(TypeCheckedExprKind::IncludeBytes(path.clone())),
             ExprKind::Error => Ok(TypeCheckedExprKind::Error),
             ExprKind::UnaryOp(op, subexpr) => {
                 let tc_sub = typecheck_expr(
@@ -1674,20 +2657,18 @@ fn typecheck_expr(
                     Box::new(tc_sub2),
                 ))
             }
-            ExprKind::OptionInitializer(inner) => {
-                Ok(TypeCheckedExprKind::Variant(Box::new(typecheck_expr(
-                    inner,
-                    type_table,
-                    global_vars,
-                    func_table,
-                    func,
-                    type_tree,
-                    string_table,
-                    undefinable_ids,
-                    closures,
-                    scopes,
-                )?)))
-            }
+            ExprKind::OptionInitializer(inner) => Ok(fold_variant(typecheck_expr(
+                inner,
+                type_table,
+                global_vars,
+                func_table,
+                func,
+                type_tree,
+                string_table,
+                undefinable_ids,
+                closures,
+                scopes,
+            )?)),
             ExprKind::VariableRef(id, spec) => {
                 if let Some(tipe) = func_table.get(id) {
                     let template_type = tipe.rep(type_tree)?;
@@ -1738,7 +2719,7 @@ fn typecheck_expr(
 
                     Ok(TypeCheckedExprKind::GlobalVariableRef(*id, tipe))
                 } else {
-                    error!(
+                    error!(@UNRECOGNIZED_IDENTIFIER
                         "reference to unrecognized identifier {}",
                         string_table.name_from_id(*id)
                     );
@@ -1757,16 +2738,15 @@ fn typecheck_expr(
                     closures,
                     scopes,
                 )?;
-                let offset = offset_value.to_usize().unwrap();
-
                 let tipe = match tuple_expr.get_type().rep(type_tree)? {
                     Type::Tuple(tup) => tup,
                     wrong => error!("{} isn't a tuple", wrong.print(type_tree)),
                 };
 
-                if offset >= tipe.len() {
-                    error!("tuple is not wide enough")
-                }
+                let offset = match offset_value.to_usize() {
+                    Some(offset) if offset < tipe.len() => offset,
+                    _ => error!("tuple is not wide enough"),
+                };
 
                 Ok(TypeCheckedExprKind::TupleRef(
                     Box::new(tuple_expr),
@@ -1896,6 +2876,9 @@ fn typecheck_expr(
                     TypeCheckedExprKind::Const(Value::Int(Uint256::from_bool(*b)), Type::Bool)
                 }
                 Constant::Option(o) => TypeCheckedExprKind::Const(o.value(), o.type_of()),
+                Constant::Address(a) => {
+                    TypeCheckedExprKind::Const(Value::Int(a.clone()), Type::EthAddress)
+                }
             }),
             ExprKind::FunctionCall(expr, args) => {
                 let expr = typecheck_expr(
@@ -1911,11 +2894,18 @@ fn typecheck_expr(
                     scopes,
                 )?;
 
+                let arg_types = match expr.get_type().rep(type_tree) {
+                    Ok(Type::Func(_, arg_types, _)) => arg_types,
+                    _ => vec![],
+                };
+
                 let args = args
                     .iter()
-                    .map(|arg| {
-                        typecheck_expr(
+                    .enumerate()
+                    .map(|(index, arg)| {
+                        typecheck_expr_expecting(
                             arg,
+                            arg_types.get(index).unwrap_or(&Type::Any),
                             type_table,
                             global_vars,
                             func_table,
@@ -2028,6 +3018,17 @@ fn typecheck_expr(
                     scopes,
                 )?;
 
+                if let Some((name, fields, locations)) = tipe.recursive_default(type_tree) {
+                    return Err(CompileError::new_type_error(
+                        format!(
+                            "newarray has no default value to fill the array with, since {} contains itself as {}; wrap the recursive part in an option",
+                            Color::red(&name),
+                            Color::red(format!("{}{}", name, fields)),
+                        ),
+                        debug_info.locs().into_iter().chain(locations).collect(),
+                    ));
+                }
+
                 let fill = TypeCheckedExpr::new(
                     TypeCheckedExprKind::Const(tipe.default_value(type_tree), tipe.clone()),
                     debug_info,
@@ -2073,6 +3074,61 @@ fn typecheck_expr(
                     Type::FixedArray(Box::new(tipe), *size),
                 ))
             }
+            ExprKind::ArrayLiteral(elements) => {
+                let mut tc_elements = vec![];
+                let mut elem_type = Type::Every;
+                for element in elements {
+                    let tc_element = typecheck_expr(
+                        element,
+                        type_table,
+                        global_vars,
+                        func_table,
+                        func,
+                        type_tree,
+                        string_table,
+                        undefinable_ids,
+                        closures,
+                        scopes,
+                    )?;
+                    let tipe = tc_element.get_type();
+                    elem_type = match common_type(&elem_type, &tipe, type_tree) {
+                        Some(common) => common,
+                        None => error!(
+                            "array literal elements have incompatible types {} and {}",
+                            elem_type.print(type_tree),
+                            tipe.print(type_tree),
+                        ),
+                    };
+                    tc_elements.push(tc_element);
+                }
+
+                let size = tc_elements.len();
+                let array_type = Type::FixedArray(Box::new(elem_type), size);
+
+                // the last element fills the array, then the rest are written from last to first,
+                // since codegen evaluates the written value before the array it's written into
+                let mut tc_elements = tc_elements.into_iter().rev();
+                let fill = match tc_elements.next() {
+                    Some(fill) => fill,
+                    None => error!("array literal must have at least one element"),
+                };
+                let mut array =
+                    TypeCheckedExprKind::NewFixedArray(size, Box::new(fill), array_type.clone());
+                for (index, element) in (0..size - 1).rev().zip(tc_elements) {
+                    let key = TypeCheckedExpr::new(
+                        TypeCheckedExprKind::Const(Value::from(index), Type::Uint),
+                        debug_info,
+                    );
+                    array = TypeCheckedExprKind::FixedArrayMod(
+                        Box::new(TypeCheckedExpr::new(array, debug_info)),
+                        Box::new(key),
+                        Box::new(element),
+                        size,
+                        array_type.clone(),
+                    );
+                }
+                Ok(array)
+            }
             ExprKind::NewUnion(types, expr) => {
                 let tc_expr = typecheck_expr(
                     expr,
@@ -2091,10 +3147,7 @@ fn typecheck_expr(
                     .iter()
                     .any(|t| t.assignable(&tc_type, type_tree, HashSet::new()))
                 {
-                    Ok(TypeCheckedExprKind::Cast(
-                        Box::new(tc_expr),
-                        Type::Union(types.clone()),
-                    ))
+                    Ok(fold_union(tc_expr, Type::Union(types.clone())))
                 } else {
                     error!(
                         "Type {} is not a member of type union: {}",
@@ -2140,12 +3193,23 @@ fn typecheck_expr(
                         closures,
                         scopes,
                     )?;
-                    types.push(StructField::new(field.name.clone(), expr.get_type()));
+                    types.push(StructField::new(
+                        field.name.clone(),
+                        expr.get_type(),
+                        expr.debug_info,
+                    ));
                     fields.push(expr);
                 }
                 Ok(TypeCheckedExprKind::Tuple(fields, Type::Struct(types)))
             }
             ExprKind::NewMap(key_type, value_type) => {
+                if !key_type.is_map_key(type_tree) {
+                    error!(
+                        "{} can't be used as a map key since its values can't be compared",
+                        key_type.print(type_tree)
+                    );
+                }
+
                 // In order to best simulate a call to the builtin, we alter the signature
                 //   In kvs.mini   func builtin_kvsNew() -> Kvs
                 //   Best effort   func builtin_kvsNew() -> map<k,v>
@@ -2166,6 +3230,48 @@ fn typecheck_expr(
                     type_tree,
                 )?)
             }
+            ExprKind::MapKeys(unchecked_map) => {
+                let map = typecheck_expr(
+                    unchecked_map,
+                    type_table,
+                    global_vars,
+                    func_table,
+                    func,
+                    type_tree,
+                    string_table,
+                    undefinable_ids,
+                    closures,
+                    scopes,
+                )?;
+                let map_type = map.get_type().rep(type_tree)?;
+                let key_type = match &map_type {
+                    Type::Map(key_type, _) => key_type.clone(),
+                    other => error!(
+                        "Can only iterate over a map, found {}",
+                        other.print(type_tree)
+                    ),
+                };
+
+                // In order to best simulate a call to the builtin, we alter the signature
+                //   In kvs.mini   func builtin_kvsKeys(Kvs) -> []any
+                //   Best effort   func builtin_kvsKeys(map<k,v>) -> []k
+
+                let builtin_ref = TypeCheckedExpr::builtin_ref(
+                    "builtin_kvsKeys",
+                    vec![&map_type],
+                    &Type::Array(key_type),
+                    func_table,
+                    string_table,
+                    debug_info,
+                )?;
+
+                Ok(build_function_call(
+                    builtin_ref,
+                    vec![map],
+                    string_table,
+                    type_tree,
+                )?)
+            }
             ExprKind::ArrayOrMapRef(unchecked_store, unchecked_key) => {
                 let store = typecheck_expr(
                     &*unchecked_store,
@@ -2451,6 +3557,19 @@ fn typecheck_expr(
                 )?),
                 t.clone(),
             )),
+            ExprKind::SizeOf(tipe) => match tipe.size_in_slots(type_tree) {
+                Ok(size) => Ok(TypeCheckedExprKind::Const(
+                    Value::Int(Uint256::from_usize(size)),
+                    Type::Uint,
+                )),
+                Err(text) => {
+                    return Err(CompileError::new(
+                        "Typecheck error",
+                        text,
+                        debug_info.locs(),
+                    ))
+                }
+            },
             ExprKind::Asm(ret_type, insns, unchecked_args) => {
                 let mut args = vec![];
                 for (index, unchecked) in unchecked_args.into_iter().enumerate() {
@@ -2579,22 +3698,14 @@ fn typecheck_expr(
                         cond_expr.get_type().print(type_tree)
                     );
                 } else {
-                    let block_type = block.get_type();
-                    let else_type = else_block
-                        .clone()
-                        .map(|b| b.get_type())
-                        .unwrap_or(Type::Void);
-                    let if_type = if block_type.assignable(&else_type, type_tree, HashSet::new()) {
-                        block_type
-                    } else if else_type.assignable(&block_type, type_tree, HashSet::new()) {
-                        else_type
-                    } else {
-                        error!(
-                            "Mismatch of if and else types found: {} and {}",
-                            block_type.print(type_tree),
-                            else_type.print(type_tree)
-                        );
-                    };
+                    let if_type = if_expr_type(
+                        block.get_type(),
+                        else_block.as_ref().map(|b| b.get_type()),
+                        type_tree,
+                    )
+                    .map_err(|text| {
+                        CompileError::new("Typecheck error", text, debug_info.locs())
+                    })?;
                     Ok(TypeCheckedExprKind::If(
                         Box::new(cond_expr),
                         block,
@@ -2653,22 +3764,12 @@ fn typecheck_expr(
                         )
                     })
                     .transpose()?;
-                let block_type = checked_block.get_type();
-                let else_type = checked_else
-                    .clone()
-                    .map(|b| b.get_type())
-                    .unwrap_or(Type::Void);
-                let if_let_type = if block_type.assignable(&else_type, type_tree, HashSet::new()) {
-                    block_type
-                } else if else_type.assignable(&block_type, type_tree, HashSet::new()) {
-                    else_type
-                } else {
-                    error!(
-                        "Mismatch of if and else types found: {} and {}",
-                        block_type.print(type_tree),
-                        else_type.print(type_tree)
-                    );
-                };
+                let if_let_type = if_expr_type(
+                    checked_block.get_type(),
+                    checked_else.as_ref().map(|b| b.get_type()),
+                    type_tree,
+                )
+                .map_err(|text| CompileError::new("Typecheck error", text, debug_info.locs()))?;
                 Ok(TypeCheckedExprKind::IfLet(
                     *l,
                     Box::new(tcr),
@@ -2727,10 +3828,47 @@ fn typecheck_expr(
     })
 }
 
+/// Evaluates the expression a top-level `const` is declared with, which may combine uint literals
+/// and earlier constants with any operators that fold when given constants.
+pub fn fold_constant(expr: &Expr) -> Result<Uint256, CompileError> {
+    fn unfolded(expr: &Expr) -> CompileError {
+        CompileError::new(
+            "Parser error",
+            "Constants must be made of uints and operators that fold at compile time",
+            expr.debug_info.locs(),
+        )
+    }
+
+    fn fold(expr: &Expr) -> Result<TypeCheckedExpr, CompileError> {
+        let loc = expr.debug_info.location;
+        let kind = match &expr.kind {
+            ExprKind::Constant(Constant::Uint(value)) => {
+                TypeCheckedExprKind::Const(Value::Int(value.clone()), Type::Uint)
+            }
+            ExprKind::UnaryOp(op, inner) => {
+                typecheck_unary_op(*op, fold(inner)?, loc, &TypeTree::new())?
+            }
+            ExprKind::Binary(op, left, right) => {
+                typecheck_binary_op(*op, fold(left)?, fold(right)?, &TypeTree::new(), loc)?
+            }
+            _ => return Err(unfolded(expr)),
+        };
+        Ok(TypeCheckedExpr::new(kind, expr.debug_info))
+    }
+
+    match fold(expr)?.kind {
+        TypeCheckedExprKind::Const(Value::Int(value), Type::Uint) => Ok(value),
+        _ => Err(unfolded(expr)),
+    }
+}
+
 /// Attempts to apply the `UnaryOp` op, to `TypeCheckedExpr` sub_expr, producing a `TypeCheckedExpr`
 /// if successful, and a `CompileError` otherwise.  The argument loc is used to record the location of
 /// op for use in formatting the `CompileError`.
-fn typecheck_unary_op(
+///
+/// Since sub_expr has already been folded, a chain of casts applied to a constant collapses into a
+/// single `Const` however deep it goes.
+pub fn typecheck_unary_op(
     op: UnaryOp,
     sub_expr: TypeCheckedExpr,
     loc: Option<Location>,
@@ -2915,39 +4053,28 @@ fn typecheck_unary_op(
                 }
             }
         }
-        UnaryOp::ToAddress => {
-            if let TypeCheckedExprKind::Const(Value::Int(val), _) = sub_expr.kind {
-                Ok(TypeCheckedExprKind::Const(
-                    Value::Int(
-                        val.modulo(
-                            &Uint256::from_string_hex(
-                                "1__0000_0000__0000_0000__0000_0000__0000_0000__0000_0000",
-                            ) //2^160, 1+max address
-                            .unwrap(), //safe because we know this str is valid
-                        )
-                        .unwrap(), //safe because we know this str isn't 0
-                    ),
-                    Type::EthAddress,
-                ))
-            } else {
-                match tc_type {
-                    Type::Uint | Type::Int | Type::Bytes32 | Type::EthAddress | Type::Bool => {
-                        Ok(TypeCheckedExprKind::UnaryOp(
-                            UnaryOp::ToAddress,
-                            Box::new(sub_expr),
-                            Type::EthAddress,
-                        ))
-                    }
-                    other => Err(CompileError::new_type_error(
-                        format!(
-                            "invalid operand type {} for address cast",
-                            Color::red(other.print(type_tree))
-                        ),
-                        loc.into_iter().collect(),
-                    )),
+        UnaryOp::ToAddress => match &sub_expr.kind {
+            // left in place so the lost bits can be warned about
+            TypeCheckedExprKind::Const(Value::Int(val), _) if !address_discards_bits(val) => Ok(
+                TypeCheckedExprKind::Const(Value::Int(val.clone()), Type::EthAddress),
+            ),
+            _ => match tc_type {
+                Type::Uint | Type::Int | Type::Bytes32 | Type::EthAddress | Type::Bool => {
+                    Ok(TypeCheckedExprKind::UnaryOp(
+                        UnaryOp::ToAddress,
+                        Box::new(sub_expr),
+                        Type::EthAddress,
+                    ))
                 }
-            }
-        }
+                other => Err(CompileError::new_type_error(
+                    format!(
+                        "invalid operand type {} for address cast",
+                        Color::red(other.print(type_tree))
+                    ),
+                    loc.into_iter().collect(),
+                )),
+            },
+        },
     }
 }
 
@@ -2963,11 +4090,22 @@ fn typecheck_binary_op(
     type_tree: &TypeTree,
     loc: Option<Location>,
 ) -> Result<TypeCheckedExprKind, CompileError> {
+    if let TypeCheckedExprKind::Const(Value::Int(divisor), _) = &tcs2.kind {
+        // this would trap at runtime no matter the dividend
+        if matches!(op, BinaryOp::Div | BinaryOp::Mod) && divisor.is_zero() {
+            return Err(CompileError::new_type_error(
+                "divide by constant zero".to_string(),
+                loc.into_iter().collect(),
+            ));
+        }
+    }
     if let TypeCheckedExprKind::Const(Value::Int(val2), t2) = tcs2.kind.clone() {
         if let TypeCheckedExprKind::Const(Value::Int(val1), t1) = tcs1.kind.clone() {
             // both args are constants, so we can do the op at compile time
             match op {
                 BinaryOp::GetBuffer256 | BinaryOp::GetBuffer64 | BinaryOp::GetBuffer8 => {}
+                // left in place so the lost bits can be warned about
+                BinaryOp::ShiftLeft if shift_discards_bits(&val1, &val2) => {}
                 _ => {
                     return typecheck_binary_op_const(op, val1, t1, val2, t2, type_tree, loc);
                 }
@@ -3006,6 +4144,11 @@ fn typecheck_binary_op(
     }
     let subtype1 = tcs1.get_type().rep(type_tree)?;
     let subtype2 = tcs2.get_type().rep(type_tree)?;
+    // a diverging operand never produces a value, so check the op as if it matched the other
+    let (subtype1, subtype2) = match (subtype1, subtype2) {
+        (Type::Every, other) | (other, Type::Every) => (other.clone(), other),
+        pair => pair,
+    };
     match op {
         BinaryOp::Plus | BinaryOp::Minus | BinaryOp::Times => match (subtype1, subtype2) {
             (Type::Uint, Type::Uint) => Ok(TypeCheckedExprKind::Binary(
@@ -3511,7 +4654,7 @@ fn typecheck_binary_op_const(
                             if let Type::Bytes32 = t1 {
                                 return Ok(TypeCheckedExprKind::Const(
                                     Value::avm_hash2(&Value::Int(val1), &Value::Int(val2)),
-                                    Type::Bool,
+                                    Type::Bytes32,
                                 ));
                             } else {
                                 return Err(CompileError::new_type_error(
@@ -3528,7 +4671,10 @@ fn typecheck_binary_op_const(
                             panic!();
                         }
                     }),
-                    Type::Bool,
+                    match op {
+                        BinaryOp::Equal | BinaryOp::NotEqual => Type::Bool,
+                        _ => t1,
+                    },
                 ))
             } else {
                 Err(CompileError::new_type_error(
@@ -3545,22 +4691,12 @@ fn typecheck_binary_op_const(
             if t1 == Type::Uint {
                 Ok(TypeCheckedExprKind::Const(
                     Value::Int(match t2 {
-                        Type::Uint | Type::Int | Type::Bytes32 => {
-                            let x = val1.to_usize().ok_or_else(|| {
-                                CompileError::new_type_error(
-                                    format!(
-                                        "Attempt to shift {} left by {}, causing overflow",
-                                        val2, val1
-                                    ),
-                                    loc.into_iter().collect(),
-                                )
-                            })?;
-                            if op == BinaryOp::ShiftLeft {
-                                val2.shift_left(x)
-                            } else {
-                                val2.shift_right(x)
-                            }
-                        }
+                        // the shift amount is on the left, since it's on top of the stack
+                        Type::Uint | Type::Int | Type::Bytes32 => match val1.to_usize() {
+                            Some(x) if op == BinaryOp::ShiftLeft => val2.shift_left(x),
+                            Some(x) => val2.shift_right(x),
+                            None => Uint256::zero(),
+                        },
                         _ => {
                             return Err(CompileError::new_type_error(
                                 format!(
@@ -3649,6 +4785,80 @@ impl AbstractSyntaxTree for TypeCheckedCodeBlock {
     }
 }
 
+/// Wraps `inner` in an option, producing a constant when `inner` is itself constant.
+fn fold_variant(inner: TypeCheckedExpr) -> TypeCheckedExprKind {
+    match inner.kind {
+        TypeCheckedExprKind::Const(value, tipe) => TypeCheckedExprKind::Const(
+            Value::new_tuple(vec![Value::Int(Uint256::one()), value]),
+            Type::Option(Box::new(tipe)),
+        ),
+        _ => TypeCheckedExprKind::Variant(Box::new(inner)),
+    }
+}
+
+/// Views `member` as the `union` type, producing a constant when `member` is itself constant.
+fn fold_union(member: TypeCheckedExpr, union: Type) -> TypeCheckedExprKind {
+    match member.kind {
+        TypeCheckedExprKind::Const(value, _) => TypeCheckedExprKind::Const(value, union),
+        _ => TypeCheckedExprKind::Cast(Box::new(member), union),
+    }
+}
+
+/// Determines the type of an `If` or `IfLet` expression from the types of its branches.
+///
+/// A missing else is only allowed when the if block yields no value, since otherwise the result
+/// would be consumed without anything to produce it on the else path.
+fn if_expr_type(
+    block_type: Type,
+    else_type: Option<Type>,
+    type_tree: &TypeTree,
+) -> Result<Type, String> {
+    let yields_value = !matches!(block_type, Type::Void | Type::Every);
+    let else_type = match else_type {
+        Some(else_type) => else_type,
+        None if yields_value => {
+            return Err(format!(
+                "If expression yields {} but has no else block",
+                Color::red(block_type.print(type_tree))
+            ))
+        }
+        None => Type::Void,
+    };
+    if yields_value && else_type == Type::Void {
+        return Err(format!(
+            "If expression yields {} but its else block produces no value",
+            Color::red(block_type.print(type_tree))
+        ));
+    }
+    common_type(&block_type, &else_type, type_tree).ok_or_else(|| {
+        format!(
+            "Mismatch of if and else types found: {} and {}",
+            Color::red(block_type.print(type_tree)),
+            Color::red(else_type.print(type_tree))
+        )
+    })
+}
+
+/// Finds a type that values of both `left` and `right` can be assigned to, preferring one of the
+/// two when it already accepts the other.
+fn common_type(left: &Type, right: &Type, type_tree: &TypeTree) -> Option<Type> {
+    if let Some(joined) = left.join(right) {
+        // a diverging value takes on the shape of the other, even when nested in a tuple
+        if joined.assignable(left, type_tree, HashSet::new())
+            && joined.assignable(right, type_tree, HashSet::new())
+        {
+            return Some(joined);
+        }
+    }
+    if left.assignable(right, type_tree, HashSet::new()) {
+        Some(left.clone())
+    } else if right.assignable(left, type_tree, HashSet::new()) {
+        Some(right.clone())
+    } else {
+        None
+    }
+}
+
 fn typecheck_codeblock(
     block: &CodeBlock,
     type_table: &TypeTable,
@@ -3774,7 +4984,8 @@ fn build_function_call(
                     Color::red(tipe.print(type_tree)),
                 ),
                 arg.debug_info.locs(),
-            ));
+            )
+            .with_code(explain::TYPE_MISMATCH));
         }
     }
 
@@ -3785,3 +4996,95 @@ fn build_function_call(
         prop,
     ))
 }
+
+#[test]
+fn test_constant_wrapping() {
+    let constant = |value: u64| {
+        TypeCheckedExpr::new(
+            TypeCheckedExprKind::Const(Value::Int(Uint256::from_u64(value)), Type::Uint),
+            DebugInfo::default(),
+        )
+    };
+
+    let nested = TypeCheckedExpr::new(fold_variant(constant(7)), DebugInfo::default());
+    assert_eq!(
+        fold_variant(nested),
+        TypeCheckedExprKind::Const(
+            Value::new_tuple(vec![
+                Value::Int(Uint256::one()),
+                Value::new_tuple(vec![
+                    Value::Int(Uint256::one()),
+                    Value::Int(Uint256::from_u64(7))
+                ]),
+            ]),
+            Type::Option(Box::new(Type::Option(Box::new(Type::Uint)))),
+        )
+    );
+
+    let union = Type::Union(vec![Type::Uint, Type::Bool]);
+    assert_eq!(
+        fold_union(constant(3), union.clone()),
+        TypeCheckedExprKind::Const(Value::Int(Uint256::from_u64(3)), union)
+    );
+}
+
+#[test]
+fn test_shift_discards_bits() {
+    let one = Uint256::one();
+    let max = Uint256::max_uint();
+    assert!(!shift_discards_bits(&Uint256::from_usize(255), &one));
+    assert!(shift_discards_bits(&Uint256::from_usize(256), &one));
+    assert!(shift_discards_bits(&Uint256::from_usize(1), &max));
+    assert!(!shift_discards_bits(&Uint256::from_usize(0), &max));
+    assert!(shift_discards_bits(&max, &one));
+    assert!(!shift_discards_bits(&max, &Uint256::zero()));
+}
+
+#[test]
+fn test_address_discards_bits() {
+    let largest = Uint256::one().shift_left(160).sub(&Uint256::one()).unwrap();
+    assert!(!address_discards_bits(&largest));
+    assert!(address_discards_bits(&largest.add(&Uint256::one())));
+    assert!(address_discards_bits(&Uint256::max_uint()));
+}
+
+#[test]
+fn test_signed_literal() {
+    let literal = |value: Uint256| Expr {
+        kind: ExprKind::Constant(Constant::Uint(value)),
+        debug_info: DebugInfo::default(),
+    };
+    let negated = |value: Uint256| Expr {
+        kind: ExprKind::UnaryOp(UnaryOp::Minus, Box::new(literal(value))),
+        debug_info: DebugInfo::default(),
+    };
+    let half = Uint256::one().shift_left(255);
+    let below = half.sub(&Uint256::one()).unwrap();
+
+    assert_eq!(signed_literal(&literal(below.clone())), Some(below.clone()));
+    assert_eq!(signed_literal(&literal(half.clone())), None);
+    assert_eq!(signed_literal(&negated(half.clone())), Some(half.clone()));
+    assert_eq!(signed_literal(&negated(half.add(&Uint256::one()))), None);
+    assert_eq!(
+        signed_literal(&negated(Uint256::from_usize(3))),
+        Uint256::from_usize(3).unary_minus()
+    );
+}
+
+#[test]
+fn test_cast_chain_folding() {
+    let type_tree = TypeTree::new();
+    let mut expr = TypeCheckedExpr::new(
+        TypeCheckedExprKind::Const(Value::Int(Uint256::from_u64(5)), Type::Uint),
+        DebugInfo::default(),
+    );
+    let chain = [UnaryOp::ToUint, UnaryOp::ToInt, UnaryOp::ToBytes32];
+    for op in chain.iter().rev() {
+        let kind = typecheck_unary_op(*op, expr, None, &type_tree).unwrap();
+        expr = TypeCheckedExpr::new(kind, DebugInfo::default());
+    }
+    assert_eq!(
+        expr.kind,
+        TypeCheckedExprKind::Const(Value::Int(Uint256::from_u64(5)), Type::Uint)
+    );
+}
@@ -0,0 +1,126 @@
+/*
+ * Copyright 2021, Offchain Labs, Inc. All rights reserved.
+ */
+
+//! Builds an index of where each symbol is defined and used, for reference navigation
+
+use super::ast::GlobalVar;
+use super::typecheck::{
+    AbstractSyntaxTree, TypeCheckedExprKind, TypeCheckedFunc, TypeCheckedNode,
+    TypeCheckedStatementKind,
+};
+use crate::link::Import;
+use crate::pos::Location;
+use crate::stringtable::{StringId, StringTable};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The places a single symbol appears in source.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SymbolReferences {
+    /// The symbol's name.
+    pub name: String,
+    /// Where the symbol is declared, bound, or imported.
+    pub definitions: Vec<Location>,
+    /// Where the symbol is read, called, or assigned.
+    pub uses: Vec<Location>,
+}
+
+/// Maps each module's path to the symbols appearing in it, keyed by the `StringId`s of that
+/// module's `StringTable`.
+pub type SymbolIndex = BTreeMap<String, BTreeMap<StringId, SymbolReferences>>;
+
+/// Accumulates the references within a single module.
+struct SymbolCollector<'a> {
+    string_table: &'a StringTable,
+    symbols: BTreeMap<StringId, SymbolReferences>,
+}
+
+impl<'a> SymbolCollector<'a> {
+    fn entry(&mut self, id: StringId) -> &mut SymbolReferences {
+        let string_table = self.string_table;
+        self.symbols.entry(id).or_insert_with(|| SymbolReferences {
+            name: string_table.name_from_id(id).clone(),
+            ..SymbolReferences::default()
+        })
+    }
+
+    fn define(&mut self, id: StringId, location: Option<Location>) {
+        self.entry(id).definitions.extend(location);
+    }
+
+    fn refer(&mut self, id: StringId, location: Option<Location>) {
+        self.entry(id).uses.extend(location);
+    }
+
+    fn collect<T: AbstractSyntaxTree>(&mut self, node: &mut T) {
+        for mut child in node.child_nodes() {
+            match &child {
+                TypeCheckedNode::Statement(stat) => match &stat.kind {
+                    TypeCheckedStatementKind::SetLocals(assigned, _) => {
+                        for local in assigned {
+                            match local.shadow {
+                                true => self.define(local.id, local.debug_info.location),
+                                false => self.refer(local.id, local.debug_info.location),
+                            }
+                        }
+                    }
+                    TypeCheckedStatementKind::AssignGlobal(id, _) => {
+                        self.refer(*id, stat.debug_info.location)
+                    }
+                    _ => {}
+                },
+                TypeCheckedNode::Expression(expr) => match &expr.kind {
+                    TypeCheckedExprKind::LocalVariableRef(id, _)
+                    | TypeCheckedExprKind::GlobalVariableRef(id, _)
+                    | TypeCheckedExprKind::FuncRef(id, _)
+                    | TypeCheckedExprKind::ClosureLoad(id, ..) => {
+                        self.refer(*id, expr.debug_info.location)
+                    }
+                    TypeCheckedExprKind::IfLet(id, ..) => {
+                        self.define(*id, expr.debug_info.location)
+                    }
+                    _ => {}
+                },
+                TypeCheckedNode::Type(_) => {}
+            }
+            self.collect(&mut child);
+        }
+    }
+}
+
+/// Finds where each symbol of a module is defined and used. Symbols the compiler introduces
+/// itself, such as builtins and the hidden locals of desugared loops, are left out.
+pub fn index_module(
+    funcs: &mut BTreeMap<StringId, TypeCheckedFunc>,
+    global_vars: &[GlobalVar],
+    imports: &[Import],
+    string_table: &StringTable,
+) -> BTreeMap<StringId, SymbolReferences> {
+    let mut collector = SymbolCollector {
+        string_table,
+        symbols: BTreeMap::new(),
+    };
+
+    for import in imports {
+        if let Some(id) = import.id {
+            collector.define(id, import.location);
+        }
+    }
+    for global in global_vars {
+        collector.define(global.id, global.debug_info.location);
+    }
+    for (id, func) in funcs {
+        collector.define(*id, func.debug_info.location);
+        for arg in &func.args {
+            collector.define(arg.name, arg.debug_info.location);
+        }
+        collector.collect(func);
+    }
+
+    collector
+        .symbols
+        .into_iter()
+        .filter(|(_, symbol)| !symbol.definitions.is_empty() && !symbol.name.contains('#'))
+        .collect()
+}
@@ -2,7 +2,7 @@
 // Copyright 2020-2021, Offchain Labs, Inc. All rights reserved.
 //
 
-use crate::compile::{AbstractSyntaxTree, StructField, Type, TypeCheckedNode, TypeTree};
+use crate::compile::{AbstractSyntaxTree, DebugInfo, StructField, Type, TypeCheckedNode, TypeTree};
 use crate::console::Color;
 use crate::link::LinkedProgram;
 use crate::GenUpgrade;
@@ -87,15 +87,27 @@ pub(crate) fn gen_upgrade_code(input: GenUpgrade) -> Result<(), GenCodeError> {
     let (mut input_fields, in_recursers, in_tree, old_arbos_version) =
         get_globals_and_version_from_file(&from)?;
     let (mut output_fields, out_recursers, out_tree, _) = get_globals_and_version_from_file(&to)?;
-    output_fields.push(StructField::new(String::from("_jump_table"), Type::Any));
-    input_fields.push(StructField::new(String::from("_jump_table"), Type::Any));
+    output_fields.push(StructField::new(
+        String::from("_jump_table"),
+        Type::Any,
+        DebugInfo::default(),
+    ));
+    input_fields.push(StructField::new(
+        String::from("_jump_table"),
+        Type::Any,
+        DebugInfo::default(),
+    ));
     let mut intersection: HashSet<&StructField> = input_fields
         .iter()
         .collect::<HashSet<_>>()
         .intersection(&(output_fields.iter().collect::<HashSet<_>>()))
         .cloned()
         .collect();
-    intersection.remove(&StructField::new(String::from("_jump_table"), Type::Any));
+    intersection.remove(&StructField::new(
+        String::from("_jump_table"),
+        Type::Any,
+        DebugInfo::default(),
+    ));
     let output_only: HashSet<&StructField> = output_fields
         .iter()
         .collect::<HashSet<_>>()
@@ -287,7 +299,7 @@ fn get_globals_and_version_from_file(
             path.to_str().unwrap_or("")
         ))
     })?;
-    let globals: LinkedProgram = serde_json::from_str(&s).map_err(|error| {
+    let globals = LinkedProgram::from_reader(&mut s.as_bytes()).map_err(|error| {
         GenCodeError::new(format!(
             "Failed to deserialize file \"{}\"\n{}",
             Color::red(path.to_str().unwrap_or("")),
@@ -315,7 +327,7 @@ fn get_globals_and_version_from_file(
             }
             tipe.recursive_apply(replace_nominal, &type_tree, &mut state);
 
-            fields.push(StructField::new(global.name, tipe))
+            fields.push(StructField::new(global.name, tipe, global.debug_info))
         }
     }
     loop {
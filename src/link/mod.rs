@@ -9,22 +9,26 @@ use crate::compile::{
     GlobalVar, Type, TypeTree,
 };
 use crate::console::Color;
-use crate::mavm::{AVMOpcode, Instruction, LabelId, Opcode, Value};
+use crate::mavm::{
+    compact_read_varint, compact_write_varint, AVMOpcode, Instruction, Label, LabelId, Opcode,
+    Value,
+};
 use crate::pos::{try_display_location, Location};
 use crate::stringtable::StringId;
 use petgraph::dot::{Config, Dot};
-use petgraph::graph::DiGraph;
-use petgraph::visit::DfsPostOrder;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{DfsPostOrder, EdgeRef};
+use petgraph::Direction;
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::{DefaultHasher, HashMap};
+use serde_json::json;
+use std::collections::hash_map::HashMap;
 use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
-use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::Write;
 
-use crate::compile::miniconstants::init_constant_table;
-use std::path::Path;
+use crate::compile::miniconstants::read_constants_file;
+use std::path::{Path, PathBuf};
 pub use xformcode::{TupleTree, TUPLE_SIZE};
 
 mod optimize;
@@ -40,10 +44,43 @@ impl SerializableTypeTree {
     pub fn from_type_tree(tree: TypeTree) -> Self {
         let mut inner = BTreeMap::new();
         for ((path, id), tipe) in tree.into_iter() {
-            inner.insert(format!("{}, {}", comma_list(&path), id), tipe);
+            inner.insert(SerializableTypeTree::key(&path, id), tipe);
         }
         Self { inner }
     }
+
+    /// Builds the serializable form of `tree`, first ensuring each nominal type within its types
+    /// refers to another of its entries. A tree with a dangling reference would deserialize into
+    /// one that can't resolve it, so an error naming the missing path is returned instead.
+    pub fn canonicalize(tree: TypeTree) -> Result<Self, CompileError> {
+        let serializable = SerializableTypeTree::from_type_tree(tree);
+
+        for (key, (tipe, name)) in &serializable.inner {
+            for nominal in tipe.find(&|tipe| matches!(tipe, Type::Nominal(..))) {
+                if let Type::Nominal(path, id, _) = nominal {
+                    let target = SerializableTypeTree::key(&path, id);
+                    if !serializable.inner.contains_key(&target) {
+                        return Err(CompileError::new(
+                            String::from("Postlink error"),
+                            format!(
+                                "type {} at {} refers to {}, which isn't in the type tree",
+                                Color::red(name),
+                                Color::red(key),
+                                Color::red(target),
+                            ),
+                            vec![],
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(serializable)
+    }
+
+    /// Formats the path and id of a type as the key it's stored under.
+    fn key(path: &[String], id: StringId) -> String {
+        format!("{}, {}", comma_list(path), id)
+    }
     pub fn into_type_tree(self) -> TypeTree {
         let mut type_tree = HashMap::new();
         for (path, tipe) in self.inner.into_iter() {
@@ -59,11 +96,21 @@ impl SerializableTypeTree {
     }
 }
 
+/// The version of the serialized `LinkedProgram` layout, to be bumped whenever its fields change.
+pub const LINKED_PROGRAM_FORMAT_VERSION: u32 = 1;
+
+/// Programs serialized before the format was versioned share the layout of version 1.
+fn unversioned_format() -> u32 {
+    1
+}
+
 /// Represents a mini program that has gone through the post-link compilation step.
 ///
 /// This is typically constructed via the `postlink_compile` function.
 #[derive(Serialize, Deserialize)]
 pub struct LinkedProgram {
+    #[serde(default = "unversioned_format")]
+    pub format_version: u32,
     #[serde(default)]
     pub arbos_version: u64,
     pub code: Vec<Instruction<AVMOpcode>>,
@@ -116,11 +163,87 @@ impl LinkedProgram {
                     writeln!(output, "bincode serialization error: {:?}", e).unwrap();
                 }
             },
+            Some("compact") => match self.to_compact() {
+                Ok(encoded) => {
+                    if let Err(e) = output.write_all(&encoded) {
+                        writeln!(output, "compact write error: {:?}", e).unwrap();
+                    }
+                }
+                Err(e) => {
+                    writeln!(output, "compact serialization error: {}", e).unwrap();
+                }
+            },
             Some(weird_value) => {
                 writeln!(output, "invalid format: {}", weird_value).unwrap();
             }
         }
     }
+
+    /// Deserializes a json program from `reader`, checking its format version before anything else
+    /// so that programs written in an incompatible layout produce a clear error.
+    pub fn from_reader(reader: &mut dyn io::Read) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(default = "unversioned_format")]
+            format_version: u32,
+        }
+
+        let mut input = vec![];
+        reader
+            .read_to_end(&mut input)
+            .map_err(|why| format!("could not read program: {}", why))?;
+        let program: serde_json::Value = serde_json::from_slice(&input)
+            .map_err(|why| format!("program is not valid json: {}", why))?;
+        let header =
+            Header::deserialize(&program).map_err(|why| format!("malformed program: {}", why))?;
+        if header.format_version != LINKED_PROGRAM_FORMAT_VERSION {
+            return Err(format!(
+                "program has format version {} but only version {} can be read",
+                Color::red(header.format_version),
+                Color::red(LINKED_PROGRAM_FORMAT_VERSION),
+            ));
+        }
+        serde_json::from_value(program).map_err(|why| format!("malformed program: {}", why))
+    }
+
+    /// Encodes the version, static value, and code of self in the compact binary format of
+    /// `Instruction::compact_encode`. Debug info, globals, and type information are not included.
+    pub fn to_compact(&self) -> Result<Vec<u8>, String> {
+        let mut encoded = vec![];
+        compact_write_varint(&mut encoded, self.arbos_version as usize);
+        self.static_val.compact_encode(&mut encoded)?;
+        compact_write_varint(&mut encoded, self.code.len());
+        for insn in &self.code {
+            insn.compact_encode(&mut encoded)?;
+        }
+        Ok(encoded)
+    }
+
+    /// Decodes a program produced by `to_compact`, leaving all debugging information empty.
+    pub fn from_compact(mut input: &[u8]) -> Result<Self, String> {
+        let arbos_version = compact_read_varint(&mut input)? as u64;
+        let static_val = Value::compact_decode(&mut input)?;
+        let code_len = compact_read_varint(&mut input)?;
+        let mut code = Vec::with_capacity(code_len);
+        for _ in 0..code_len {
+            code.push(Instruction::compact_decode(&mut input)?);
+        }
+        if !input.is_empty() {
+            return Err(format!(
+                "{} trailing bytes after compact program",
+                input.len()
+            ));
+        }
+        Ok(LinkedProgram {
+            format_version: LINKED_PROGRAM_FORMAT_VERSION,
+            arbos_version,
+            code,
+            static_val,
+            globals: vec![],
+            file_info_chart: BTreeMap::new(),
+            type_tree: SerializableTypeTree::from_type_tree(HashMap::new()),
+        })
+    }
 }
 
 /// Represents an import generated by a `use` statement.
@@ -130,6 +253,9 @@ pub struct Import {
     pub path: Vec<String>,
     /// Name of `Type` or function to be imported.
     pub name: String,
+    /// Local name given via `as`, if the import is renamed in the importing module.
+    #[serde(default)]
+    pub alias: Option<String>,
     /// Unique global id this import refers to
     pub unique_id: LabelId,
     /// `StringId` of the use-statement from parsing according to the containing module's `StringTable`
@@ -142,6 +268,7 @@ impl Import {
     pub fn new(
         path: Vec<String>,
         name: String,
+        alias: Option<String>,
         id: Option<StringId>,
         location: Option<Location>,
     ) -> Self {
@@ -149,12 +276,18 @@ impl Import {
         Import {
             path,
             name,
+            alias,
             unique_id,
             id,
             location,
         }
     }
 
+    /// The name this import is known by in the importing module.
+    pub fn local_name(&self) -> &String {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+
     pub fn loc(&self) -> Vec<Location> {
         self.location.into_iter().collect()
     }
@@ -166,32 +299,85 @@ impl Import {
         Import {
             path,
             name,
+            alias: None,
             unique_id,
             id: None,
             location: None,
         }
     }
 
+    /// Derives the id a func is known by across modules. Since ids end up in linked output, this
+    /// is the 64-bit FNV-1a hash of the number of path segments, then each segment and finally the
+    /// name, where counts and lengths are written as 8 little-endian bytes ahead of what they
+    /// count. Changing any of this changes every compiled program.
     pub fn unique_id(path: &Vec<String>, name: &String) -> LabelId {
-        let mut hasher = DefaultHasher::new();
-        path.hash(&mut hasher);
-        name.hash(&mut hasher);
-        hasher.finish()
+        fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+            hash
+        }
+
+        let mut hash = fnv1a(0xcbf2_9ce4_8422_2325, &(path.len() as u64).to_le_bytes());
+        for part in path.iter().chain(std::iter::once(name)) {
+            hash = fnv1a(hash, &(part.len() as u64).to_le_bytes());
+            hash = fnv1a(hash, part.as_bytes());
+        }
+        hash
     }
 }
 
 pub type FuncGraph = DiGraph<CompiledFunc, usize>;
 
+/// Settings for `link`.
+#[derive(Clone, Debug, Default)]
+pub struct LinkOptions<'a> {
+    /// Whether the program is linked to be run as a test rather than as an upgradable program.
+    pub test_mode: bool,
+    /// The public funcs reachable from outside the program besides `main`.
+    pub entry_points: &'a [String],
+    /// Whether to describe the entry points' args and returns with a JSON Schema.
+    pub json_schema: bool,
+    /// Where to write the call graph, in Graphviz's dot format.
+    pub call_graph: Option<&'a str>,
+    /// Whether funcs are laid out by `weighted_order`.
+    pub weighted_layout: bool,
+    /// Whether funcs with identical code are merged by `merge_identical_funcs`.
+    pub merge_funcs: bool,
+}
+
 /// Creates a graph of the `CompiledProgram`s and then combines them into a single
 /// `CompiledProgram` in such a way as to reduce the number of backward jumps.
+///
+/// Besides `main`, any public func named in `options.entry_points` is considered externally reachable, and
+/// the traversal is seeded from each of them. A name may be qualified by its module's path, as in
+/// `other::start`, to pick out the func of just that module.
+/// Entry points and public funcs of `main`'s module whose signatures carry funcs are reported,
+/// since nothing outside could call them.
+/// When `options.json_schema` is set, a JSON Schema for the args and returns of every entry point,
+/// as described by `Type::json_schema`, is returned along with the program. Entry points whose
+/// types can't be described are left out with a warning. When `options.weighted_layout` is set,
+/// the traversal order is further refined by `weighted_order`.
 pub fn link(
     funcs: Vec<CompiledFunc>,
     globals: Vec<GlobalVar>,
     error_system: &mut ErrorSystem,
-    test_mode: bool,
-) -> CompiledProgram {
+    options: &LinkOptions,
+) -> (CompiledProgram, Option<serde_json::Value>) {
     let type_tree = funcs[0].type_tree.clone();
 
+    let funcs = match options.merge_funcs {
+        true => {
+            let (funcs, merged) = merge_identical_funcs(funcs);
+            error_system
+                .notes
+                .push(format!("merged {} identical funcs", merged));
+            funcs
+        }
+        false => funcs,
+    };
+
     let mut graph = FuncGraph::new();
     let mut id_to_node = HashMap::new();
 
@@ -225,7 +411,7 @@ pub fn link(
         .any(|x| x.debug_info.attributes.codegen_print);
 
     // Initialize globals or allow jump table retrieval
-    let mut linked_code = if test_mode {
+    let mut linked_code = if options.test_mode {
         vec![
             Instruction::from_opcode_imm(
                 Opcode::AVMOpcode(AVMOpcode::Noop),
@@ -258,12 +444,113 @@ pub fn link(
         .node_indices()
         .find(|node| graph[*node].name == "main")
         .expect("no main func");
+    let mut roots = vec![main];
+    for entry_point in options.entry_points {
+        let (module, name) = match entry_point.rsplit_once("::") {
+            Some((module, name)) => (Some(module), name),
+            None => (None, entry_point.as_str()),
+        };
+        let found: Vec<_> = graph
+            .node_indices()
+            .filter(|node| {
+                let func = &graph[*node];
+                func.public
+                    && func.name == name
+                    && module.map_or(true, |module| func.path.join("::") == module)
+            })
+            .collect();
+        if found.is_empty() {
+            error_system.warnings.push(CompileError::new_warning(
+                String::from("Compile warning"),
+                format!(
+                    "entry point {} is not a public func",
+                    Color::color(error_system.warn_color, entry_point)
+                ),
+                vec![],
+            ));
+        }
+        roots.extend(found);
+    }
+
+    // the args and returns of entry points cross the program's boundary, as do those of the
+    // public funcs of main's module, while the public funcs of other modules are exports to the
+    // modules that import them and may pass anything
+    let exported = graph.node_indices().filter(|node| {
+        let func = &graph[*node];
+        func.public && func.path == graph[main].path && !roots.contains(node)
+    });
+    let boundary_funcs: Vec<_> = roots.iter().copied().chain(exported).collect();
+    let mut schemas = serde_json::Map::new();
+    let mut defs = BTreeMap::new();
+    for node in &boundary_funcs {
+        let func = &graph[*node];
+        if let Type::Func(_, args, ret) = &func.tipe {
+            let mut boundary = args.iter().chain(std::iter::once(&**ret));
+            if let Some(tipe) = boundary.find(|tipe| !tipe.is_serializable(&type_tree)) {
+                error_system.errors.push(CompileError::new(
+                    String::from("Compile error"),
+                    format!(
+                        "{} {} can't be called from outside the program since its signature includes {}",
+                        match roots.contains(node) {
+                            true => "entry point",
+                            false => "public func",
+                        },
+                        Color::red(&func.name),
+                        Color::red(tipe.print(&type_tree)),
+                    ),
+                    func.debug_info.locs(),
+                ));
+            } else if options.json_schema && roots.contains(node) {
+                let schema = args
+                    .iter()
+                    .map(|arg| arg.json_schema(&type_tree, &mut defs))
+                    .collect::<Result<Vec<_>, _>>()
+                    .and_then(|args| Ok((args, ret.json_schema(&type_tree, &mut defs)?)));
+                match schema {
+                    Ok((args, returns)) => {
+                        let name = format!("{}::{}", func.path.join("::"), func.name);
+                        schemas.insert(name, json!({ "args": args, "returns": returns }));
+                    }
+                    Err(why) => error_system.warnings.push(CompileError::new_warning(
+                        String::from("Compile warning"),
+                        format!(
+                            "entry point {} is left out of the json schema: {}",
+                            Color::color(error_system.warn_color, &func.name),
+                            why,
+                        ),
+                        func.debug_info.locs(),
+                    )),
+                }
+            }
+        }
+    }
+    let schema = match options.json_schema {
+        true => Some(json!({ "entry_points": schemas, "$defs": defs })),
+        false => None,
+    };
+
     let mut dfs = DfsPostOrder::new(&graph, main);
     let mut traversal = vec![];
-    while let Some(node) = dfs.next(&graph) {
-        traversal.push(node);
+    for root in roots {
+        let mut root_traversal = vec![];
+        dfs.move_to(root);
+        while let Some(node) = dfs.next(&graph) {
+            root_traversal.push(node);
+        }
+        root_traversal.reverse();
+        traversal.extend(root_traversal);
+    }
+
+    if options.weighted_layout {
+        let reordered = weighted_order(&graph, &traversal);
+        let (count_before, distance_before) = backward_jump_cost(&graph, &traversal);
+        let (count_after, distance_after) = backward_jump_cost(&graph, &reordered);
+        error_system.notes.push(format!(
+            "backward jumps: {} before weighted layout, {} after (weighted distance {} to {})",
+            count_before, count_after, distance_before, distance_after
+        ));
+        traversal = reordered;
     }
-    traversal.reverse();
 
     let mut unvisited: HashSet<_> = graph.node_indices().collect();
     for node in traversal {
@@ -284,42 +571,508 @@ pub fn link(
         if unvisited.contains(&node) && !name.starts_with('_') {
             error_system.warnings.push(CompileError::new_warning(
                 String::from("Compile warning"),
-                format!(
-                    "func {} is unreachable",
-                    Color::color(error_system.warn_color, name)
-                ),
+                match graph[node].public {
+                    true => format!(
+                        "public func {} is unreachable and not a declared entry point",
+                        Color::color(error_system.warn_color, name)
+                    ),
+                    false => format!(
+                        "func {} is unreachable",
+                        Color::color(error_system.warn_color, name)
+                    ),
+                },
                 debug_info.locs(),
             ));
         }
     }
 
-    let graph = graph.map(|_, prog| prog.name.clone(), |_, e| e);
-
-    let mut file = File::create("callgraph.dot").expect("failed to open file");
-    let dot = Dot::with_config(&graph, &[Config::EdgeNoLabel]);
-    writeln!(&mut file, "{:?}", dot).expect("failed to write .dot file");
+    if let Some(path) = options.call_graph {
+        let graph = graph.map(|_, prog| prog.name.clone(), |_, e| e);
+        let dot = Dot::with_config(&graph, &[Config::EdgeNoLabel]);
+        let written = File::create(path).and_then(|mut file| writeln!(&mut file, "{:?}", dot));
+        if let Err(why) = written {
+            error_system.errors.push(CompileError::new(
+                "Compile error",
+                format!("Could not write call graph to {}: {}", path, why),
+                vec![],
+            ));
+        }
+    }
 
     // check for unvisited
 
-    CompiledProgram::new(
+    let program = CompiledProgram::new(
         String::from("entry_point"),
         vec![String::from("/meta"), String::from("link")],
         linked_code,
         globals,
         type_tree,
         DebugInfo::default(),
-    )
+    );
+    (program, schema)
+}
+
+/// Measures how far `order` is from laying every call out as a forward jump.
+///
+/// Each edge whose callee comes before its caller contributes its usage count to the first total,
+/// and that count times the number of funcs jumped back over to the second.
+pub fn backward_jump_cost<N>(graph: &DiGraph<N, usize>, order: &[NodeIndex]) -> (usize, usize) {
+    let position: HashMap<_, _> = order
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (*node, index))
+        .collect();
+
+    let mut count = 0;
+    let mut distance = 0;
+    for edge in graph.raw_edges() {
+        let (source, target) = match (position.get(&edge.source()), position.get(&edge.target())) {
+            (Some(source), Some(target)) => (*source, *target),
+            _ => continue,
+        };
+        if target <= source {
+            count += edge.weight;
+            distance += edge.weight * (source - target);
+        }
+    }
+    (count, distance)
+}
+
+/// Reorders the funcs in `order` so that heavily used calls tend to jump forward and short.
+///
+/// Funcs are first laid out greedily, placing callers of many hot edges early and callees late,
+/// then adjacent funcs are swapped while doing so reduces the weighted backward-jump count, or
+/// keeps it the same while reducing the weighted backward-jump distance. The first func always
+/// stays first since execution falls into it, and the original order is kept if the result is no
+/// better.
+pub fn weighted_order<N>(graph: &DiGraph<N, usize>, order: &[NodeIndex]) -> Vec<NodeIndex> {
+    if order.len() < 3 {
+        return order.to_vec();
+    }
+
+    // greedily peel off sinks, sources, and then the node with the most net outgoing weight
+    let mut remaining: Vec<_> = order[1..].to_vec();
+    let mut unplaced: HashSet<_> = remaining.iter().cloned().collect();
+    let mut out_flow = HashMap::new();
+    let mut in_flow = HashMap::new();
+    for node in &remaining {
+        out_flow.insert(*node, 0);
+        in_flow.insert(*node, 0);
+    }
+    for edge in graph.raw_edges() {
+        if unplaced.contains(&edge.source()) && unplaced.contains(&edge.target()) {
+            *out_flow.get_mut(&edge.source()).unwrap() += edge.weight;
+            *in_flow.get_mut(&edge.target()).unwrap() += edge.weight;
+        }
+    }
+
+    let mut front = vec![order[0]];
+    let mut back = vec![];
+    while !remaining.is_empty() {
+        let (index, is_sink) =
+            if let Some(sink) = remaining.iter().position(|node| out_flow[node] == 0) {
+                (sink, true)
+            } else if let Some(source) = remaining.iter().position(|node| in_flow[node] == 0) {
+                (source, false)
+            } else {
+                let net = |node: &NodeIndex| out_flow[node] as isize - in_flow[node] as isize;
+                let mut best = 0;
+                for (index, node) in remaining.iter().enumerate() {
+                    if net(node) > net(&remaining[best]) {
+                        best = index;
+                    }
+                }
+                (best, false)
+            };
+
+        let node = remaining.remove(index);
+        unplaced.remove(&node);
+        for edge in graph.edges_directed(node, Direction::Outgoing) {
+            if unplaced.contains(&edge.target()) {
+                *in_flow.get_mut(&edge.target()).unwrap() -= *edge.weight();
+            }
+        }
+        for edge in graph.edges_directed(node, Direction::Incoming) {
+            if unplaced.contains(&edge.source()) {
+                *out_flow.get_mut(&edge.source()).unwrap() -= *edge.weight();
+            }
+        }
+        match is_sink {
+            true => back.push(node),
+            false => front.push(node),
+        }
+    }
+    back.reverse();
+    front.extend(back);
+    let mut layout = front;
+
+    // swapping neighbors only moves those two, so only their own edges change cost
+    let mut position: HashMap<_, _> = layout
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (*node, index))
+        .collect();
+    let mut improved = true;
+    let mut passes = 0;
+    while improved && passes < layout.len() {
+        improved = false;
+        passes += 1;
+        for index in 1..layout.len() - 1 {
+            let (left, right) = (layout[index], layout[index + 1]);
+            let mut flipped = 0;
+            let mut delta = 0;
+            for (node, step) in &[(left, 1), (right, -1)] {
+                for edge in graph.edges_directed(*node, Direction::Outgoing) {
+                    match position.get(&edge.target()) {
+                        Some(&place) if place < index => delta += step * *edge.weight() as isize,
+                        _ => {}
+                    }
+                }
+                for edge in graph.edges_directed(*node, Direction::Incoming) {
+                    match position.get(&edge.source()) {
+                        Some(&place) if place > index + 1 => {
+                            delta -= step * *edge.weight() as isize
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            for edge in graph.edges_connecting(left, right) {
+                flipped += *edge.weight() as isize;
+            }
+            for edge in graph.edges_connecting(right, left) {
+                flipped -= *edge.weight() as isize;
+            }
+            delta += flipped;
+            if (flipped, delta) < (0, 0) {
+                layout.swap(index, index + 1);
+                position.insert(left, index + 1);
+                position.insert(right, index);
+                improved = true;
+            }
+        }
+    }
+
+    if backward_jump_cost(graph, &layout) < backward_jump_cost(graph, order) {
+        layout
+    } else {
+        order.to_vec()
+    }
+}
+
+/// Merges funcs whose code is identical, keeping the first of each group and redirecting every
+/// reference to the others. Code is compared with each func's own labels made relative to its
+/// unique id, and merged funcs must also agree in arity and frame size. Closures, public funcs,
+/// and `main` are never merged away since they're found by label kind or by name. Returns the
+/// funcs that remain along with how many were merged.
+pub fn merge_identical_funcs(funcs: Vec<CompiledFunc>) -> (Vec<CompiledFunc>, usize) {
+    // labels a func makes for itself are numbered from its unique id
+    let relative = |label: Label, unique_id: LabelId| match label {
+        Label::Func(id) if id == unique_id => Label::Anon(0),
+        Label::Anon(id) => Label::Anon(id.wrapping_sub(unique_id)),
+        other => other,
+    };
+    let normalize = |func: &CompiledFunc| -> Vec<(Opcode, Option<Value>)> {
+        func.code
+            .iter()
+            .map(|insn| {
+                let opcode = match insn.opcode {
+                    Opcode::Label(label) => Opcode::Label(relative(label, func.unique_id)),
+                    opcode => opcode,
+                };
+                let immediate = insn.immediate.clone().map(|value| {
+                    value.replace(
+                        &mut |value| match value {
+                            Value::Label(label) => Value::Label(relative(label, func.unique_id)),
+                            value => value,
+                        },
+                        &mut |value| matches!(value, Value::Label(_)),
+                    )
+                });
+                (opcode, immediate)
+            })
+            .collect()
+    };
+    let arity = |func: &CompiledFunc| match &func.tipe {
+        Type::Func(_, args, _) => Some(args.len()),
+        _ => None,
+    };
+
+    let mut kept: Vec<CompiledFunc> = vec![];
+    let mut groups: HashMap<Vec<Opcode>, Vec<(usize, Vec<(Opcode, Option<Value>)>)>> =
+        HashMap::new();
+    let mut redirects = HashMap::new();
+
+    for func in funcs {
+        let closure = func
+            .code
+            .iter()
+            .any(|insn| insn.opcode == Opcode::Label(Label::Closure(func.unique_id)));
+        let mergeable = !closure && !func.public && func.name != "main";
+        let code = normalize(&func);
+        let opcodes: Vec<_> = code.iter().map(|(opcode, _)| *opcode).collect();
+        let group = groups.entry(opcodes).or_insert_with(Vec::new);
+
+        let canonical = group
+            .iter()
+            .find(|(index, other)| {
+                let other_func = &kept[*index];
+                *other == code
+                    && other_func.frame_size == func.frame_size
+                    && arity(other_func) == arity(&func)
+            })
+            .map(|(index, _)| *index);
+        match canonical {
+            Some(index) if mergeable => {
+                redirects.insert(func.unique_id, kept[index].unique_id);
+            }
+            _ => {
+                if !closure {
+                    group.push((kept.len(), code));
+                }
+                kept.push(func);
+            }
+        }
+    }
+
+    let merged = redirects.len();
+    if merged == 0 {
+        return (kept, 0);
+    }
+
+    let redirect = |label: Label| match label {
+        Label::Func(id) => Label::Func(*redirects.get(&id).unwrap_or(&id)),
+        other => other,
+    };
+    for func in &mut kept {
+        for insn in &mut func.code {
+            if let Opcode::Label(label) = insn.opcode {
+                insn.opcode = Opcode::Label(redirect(label));
+            }
+            if let Some(value) = &mut insn.immediate {
+                value.replace2(&mut |value| {
+                    if let Value::Label(label) = value {
+                        *label = redirect(*label);
+                    }
+                });
+            }
+        }
+    }
+    (kept, merged)
+}
+
+/// Ensures every opcode in `code` is one `allowed` expects to remain after `phase`, so that a
+/// transform that fails to lower a virtual opcode is caught where it happens. Immediates on
+/// opcodes that would silently drop them are reported too.
+fn verify_phase<F>(code: &[Instruction], phase: &str, allowed: F) -> Result<(), CompileError>
+where
+    F: Fn(&Opcode) -> bool,
+{
+    for insn in code {
+        let problem = match &insn.immediate {
+            _ if !allowed(&insn.opcode) => format!(
+                "After {} encountered unexpected opcode {}",
+                phase,
+                Color::red(insn.opcode.pretty_print(Color::RED))
+            ),
+            Some(value) if insn.opcode.drops_immediate() => format!(
+                "After {} encountered opcode {} with immediate {}, which it would drop",
+                phase,
+                Color::red(insn.opcode.pretty_print(Color::RED)),
+                Color::red(value.pretty_print(Color::RED)),
+            ),
+            _ => continue,
+        };
+        return Err(CompileError::new(
+            String::from("Postlink error"),
+            problem,
+            insn.debug_info.location.into_iter().collect(),
+        ));
+    }
+    Ok(())
+}
+
+/// Writes `code` to `path` one numbered instruction per line, in a form meant for diffing.
+fn write_listing(code: &[Instruction], path: &str) -> Result<(), CompileError> {
+    let written = File::create(path).and_then(|mut file| {
+        code.iter()
+            .enumerate()
+            .try_for_each(|(idx, insn)| writeln!(file, "{:04}  {}", idx, insn))
+    });
+    written.map_err(|why| {
+        CompileError::new(
+            String::from("Postlink error"),
+            format!("Could not write code listing to {}: {}", path, why),
+            vec![],
+        )
+    })
+}
+
+/// Globals and immediates with more nodes than this are reported, since each copy of them costs
+/// the AVM proportionally more.
+const LARGE_VALUE_NODES: usize = 1024;
+
+/// Programs with more backward jumps than this are reported, since the jump table grows deep
+/// enough that every backward jump pays for an extra tuple lookup.
+const MANY_BACKWARD_JUMPS: usize = TUPLE_SIZE * TUPLE_SIZE * TUPLE_SIZE;
+
+/// Counts the tuples and leaves making up `value`.
+fn value_nodes(value: &Value) -> usize {
+    match value {
+        Value::Tuple(tup) => 1 + tup.iter().map(value_nodes).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+/// Records warnings for the parts of a linked program that are likely to be costly at runtime:
+/// globals whose initial values are large, an oversized jump table, and large immediates.
+fn warn_postlink_costs(
+    code: &[Instruction],
+    globals: &[GlobalVar],
+    type_tree: &TypeTree,
+    backward_jumps: usize,
+    error_system: &mut ErrorSystem,
+) {
+    for global in globals {
+        let nodes = value_nodes(&global.tipe.default_value(type_tree));
+        if nodes > LARGE_VALUE_NODES {
+            error_system.warnings.push(CompileError::new_warning(
+                String::from("Postlink warning"),
+                format!(
+                    "global {} starts out as a value of {} nodes",
+                    Color::color(error_system.warn_color, &global.name),
+                    nodes,
+                ),
+                global.debug_info.locs(),
+            ));
+        }
+    }
+
+    if backward_jumps > MANY_BACKWARD_JUMPS {
+        error_system.warnings.push(CompileError::new_warning(
+            String::from("Postlink warning"),
+            format!(
+                "program has {} backward jumps, enough to slow every lookup in the jump table",
+                Color::color(error_system.warn_color, backward_jumps),
+            ),
+            vec![],
+        ));
+    }
+
+    for insn in code {
+        if let Some(nodes) = insn.immediate.as_ref().map(value_nodes) {
+            if nodes > LARGE_VALUE_NODES {
+                error_system.warnings.push(CompileError::new_warning(
+                    String::from("Postlink warning"),
+                    format!(
+                        "constant of {} nodes is embedded in the code",
+                        Color::color(error_system.warn_color, nodes),
+                    ),
+                    insn.debug_info.locs(),
+                ));
+            }
+        }
+    }
+}
+
+/// The constants file the arbos version is read from when the program wasn't compiled with one.
+const DEFAULT_CONSTANTS_PATH: &str = "arb_os/constants.json";
+
+/// Determines the arbos version a linked program is stamped with, which is `pinned` when given
+/// and otherwise the version recorded in the constants file at `constants_path`, or in
+/// `DEFAULT_CONSTANTS_PATH` when there's no such file.
+pub fn arbos_version(
+    pinned: Option<u64>,
+    constants_path: Option<&Path>,
+) -> Result<u64, CompileError> {
+    if let Some(version) = pinned {
+        return Ok(version);
+    }
+    let describe = |problem: String| {
+        CompileError::new(
+            String::from("Compile error"),
+            format!(
+                "Could not determine the arbos version: {}, pass {} to set it explicitly",
+                problem,
+                Color::red("--arbos-version"),
+            ),
+            vec![],
+        )
+    };
+    let constants_path = constants_path.unwrap_or_else(|| Path::new(DEFAULT_CONSTANTS_PATH));
+    read_constants_file(constants_path)
+        .map(|constants| constants.arbos_version)
+        .map_err(|err| describe(err.description))
+}
+
+/// Settings for `postlink_compile`.
+#[derive(Clone, Debug)]
+pub struct PostlinkOptions<'a> {
+    /// Whether the program is compiled to be run as a test rather than as an upgradable program.
+    pub test_mode: bool,
+    /// Whether the code is printed after each phase.
+    pub debug: bool,
+    /// Whether the opcodes are checked after each phase.
+    pub verify: bool,
+    /// Whether the position of each global and jump table entry is printed.
+    pub globals_layout: bool,
+    /// How many times the peephole optimizations may be repeated while they still change the code.
+    pub peephole_passes: usize,
+    /// Whether pure instructions are reordered to shed swaps once the peephole optimizations are
+    /// done.
+    pub schedule_pure: bool,
+    /// Where to write the code entering and leaving the optimizations, with `.before` and `.after`
+    /// appended, so the two can be diffed.
+    pub optimizer_listings: Option<&'a str>,
+    /// Globals whose default values would nest tuples deeper than this are rejected.
+    pub max_tuple_depth: Option<usize>,
+    /// The version the program is stamped with, or `None` to use the one in `constants_path`.
+    pub arbos_version: Option<u64>,
+    /// The constants file the program was compiled with.
+    pub constants_path: Option<PathBuf>,
+}
+
+impl Default for PostlinkOptions<'_> {
+    fn default() -> Self {
+        PostlinkOptions {
+            test_mode: false,
+            debug: false,
+            verify: false,
+            globals_layout: false,
+            peephole_passes: 1,
+            schedule_pure: false,
+            optimizer_listings: None,
+            max_tuple_depth: None,
+            arbos_version: None,
+            constants_path: None,
+        }
+    }
 }
 
 /// Converts a linked `CompiledProgram` into a `LinkedProgram` by fixing non-forward jumps,
 /// converting wide tuples to nested tuples, performing code optimizations, converting the jump
 /// table to a static value, and combining the file info chart with the associated argument.
+///
+/// See `PostlinkOptions` for the settings that steer each phase. Warnings about costly globals,
+/// jump tables, and constants are recorded in `error_system`.
 pub fn postlink_compile(
     program: CompiledProgram,
     file_info_chart: BTreeMap<u64, FileInfo>,
-    test_mode: bool,
-    debug: bool,
+    error_system: &mut ErrorSystem,
+    options: &PostlinkOptions,
 ) -> Result<LinkedProgram, CompileError> {
+    let PostlinkOptions {
+        test_mode,
+        debug,
+        verify,
+        globals_layout,
+        peephole_passes,
+        schedule_pure,
+        optimizer_listings,
+        max_tuple_depth,
+        arbos_version: pinned_arbos_version,
+        ref constants_path,
+    } = *options;
     let consider_debug_printing = |code: &Vec<Instruction>, did_print: bool, phase: &str| {
         if debug {
             println!("========== {} ==========", phase);
@@ -368,23 +1121,100 @@ pub fn postlink_compile(
         }
     }
 
+    let only_avm_or_labels =
+        |opcode: &Opcode| matches!(opcode, Opcode::AVMOpcode(_) | Opcode::Label(_));
+
+    if verify {
+        verify_phase(&program.code, "linking", |_| true)?;
+    }
+
     let (code, jump_table) =
         striplabels::fix_backward_labels(&program.code, program.globals.len() - 1);
     consider_debug_printing(&code, did_print, "after fix_backward_labels");
+    if verify {
+        verify_phase(&code, "fix_backward_labels", |opcode| {
+            matches!(
+                opcode,
+                Opcode::AVMOpcode(_)
+                    | Opcode::Label(_)
+                    | Opcode::MakeFrame(..)
+                    | Opcode::GetLocal(_)
+                    | Opcode::SetLocal(_)
+                    | Opcode::TupleGet(..)
+                    | Opcode::TupleSet(..)
+                    | Opcode::GetGlobalVar(_)
+                    | Opcode::SetGlobalVar(_)
+                    | Opcode::UncheckedFixedArrayGet(_)
+                    | Opcode::Return
+            )
+        })?;
+    }
 
     let code = xformcode::fix_tuple_size(code, program.globals.len())?;
     consider_debug_printing(&code, did_print, "after fix_tuple_size");
+    if verify {
+        verify_phase(&code, "fix_tuple_size", only_avm_or_labels)?;
+    }
 
-    let code = optimize::peephole(&code);
-    consider_debug_printing(&code, did_print, "after peephole optimization");
+    let unoptimized = optimizer_listings.map(|_| code.clone());
+
+    let (code, passes) = optimize::peephole_to_fixed_point(&code, peephole_passes);
+    let phase = format!("after peephole optimization ({} passes)", passes);
+    consider_debug_printing(&code, did_print, &phase);
+    if verify {
+        verify_phase(&code, "peephole optimization", only_avm_or_labels)?;
+    }
+
+    let code = if schedule_pure {
+        let code = optimize::schedule_pure(&code);
+        consider_debug_printing(&code, did_print, "after pure instruction scheduling");
+        if verify {
+            verify_phase(&code, "pure instruction scheduling", only_avm_or_labels)?;
+        }
+        code
+    } else {
+        code
+    };
+
+    if let (Some(path), Some(unoptimized)) = (optimizer_listings, unoptimized) {
+        write_listing(&unoptimized, &format!("{}.before", path))?;
+        write_listing(&code, &format!("{}.after", path))?;
+    }
 
     let (mut code, jump_table_final) = striplabels::strip_labels(code, &jump_table)?;
+    if verify {
+        verify_phase(&code, "strip_labels", |opcode| {
+            matches!(opcode, Opcode::AVMOpcode(_))
+        })?;
+    }
+    if globals_layout {
+        xformcode::write_globals_layout(&program.globals, &jump_table, &mut io::stdout()).map_err(
+            |e| {
+                CompileError::new(
+                    String::from("Postlink error"),
+                    format!("Could not write globals layout: {}", e),
+                    vec![],
+                )
+            },
+        )?;
+    }
     let jump_table_len = jump_table_final.len();
+    warn_postlink_costs(
+        &code,
+        &program.globals,
+        &program.type_tree,
+        jump_table_len,
+        error_system,
+    );
     let jump_table_value = xformcode::jump_table_to_value(jump_table_final);
 
     // hardcode globals & set error codepoints
-    let globals =
-        xformcode::make_globals_tuple(&program.globals, &jump_table_value, &program.type_tree);
+    let globals = xformcode::make_globals_tuple(
+        &program.globals,
+        &jump_table_value,
+        &program.type_tree,
+        max_tuple_depth,
+    )?;
     let write_offset = if test_mode { 1 } else { 2 };
     code[write_offset].immediate = Some(globals.clone());
     code = xformcode::set_error_codepoints(code);
@@ -446,16 +1276,117 @@ pub fn postlink_compile(
     }
 
     Ok(LinkedProgram {
-        arbos_version: init_constant_table(Some(Path::new("arb_os/constants.json")))
-            .unwrap()
-            .get("ArbosVersionNumber")
-            .unwrap()
-            .clone()
-            .trim_to_u64(),
+        format_version: LINKED_PROGRAM_FORMAT_VERSION,
+        arbos_version: arbos_version(pinned_arbos_version, constants_path.as_deref())?,
         code: code_final,
         static_val: Value::none(),
         globals: program.globals.clone(),
         file_info_chart,
-        type_tree: SerializableTypeTree::from_type_tree(program.type_tree),
+        type_tree: SerializableTypeTree::canonicalize(program.type_tree)?,
     })
 }
+
+#[test]
+fn test_weighted_order() {
+    // main calls a, which calls b once, while b calls back into a five times
+    let mut graph = DiGraph::<(), usize>::new();
+    let main = graph.add_node(());
+    let a = graph.add_node(());
+    let b = graph.add_node(());
+    let c = graph.add_node(());
+    graph.add_edge(main, a, 1);
+    graph.add_edge(a, b, 1);
+    graph.add_edge(b, a, 5);
+    graph.add_edge(b, c, 2);
+    graph.add_edge(c, b, 3);
+
+    let default = vec![main, a, b, c];
+    assert_eq!(backward_jump_cost(&graph, &default), (8, 8));
+
+    let reordered = weighted_order(&graph, &default);
+    assert_eq!(reordered[0], main);
+    assert_eq!(reordered, vec![main, c, b, a]);
+    assert_eq!(backward_jump_cost(&graph, &reordered), (3, 3));
+}
+
+#[test]
+fn test_verify_dropped_immediates() {
+    use crate::uint256::Uint256;
+
+    let debug_info = DebugInfo::default();
+    let kept = vec![Instruction::from_opcode_imm(
+        Opcode::AVMOpcode(AVMOpcode::Noop),
+        Value::Int(Uint256::one()),
+        debug_info,
+    )];
+    assert!(verify_phase(&kept, "test", |_| true).is_ok());
+
+    let dropped = vec![Instruction::from_opcode_imm(
+        Opcode::GetLocal(0),
+        Value::Int(Uint256::one()),
+        debug_info,
+    )];
+    let error = verify_phase(&dropped, "test", |_| true).err().unwrap();
+    assert!(error.description.contains("drop"));
+}
+
+#[test]
+fn test_format_version() {
+    let unversioned = r#"{"code":[],"static_val":{"Tuple":[]},"globals":[],"file_info_chart":{},"type_tree":{"inner":{}}}"#;
+    let program = LinkedProgram::from_reader(&mut unversioned.as_bytes()).unwrap();
+    assert_eq!(program.format_version, LINKED_PROGRAM_FORMAT_VERSION);
+
+    let future = unversioned.replacen('{', r#"{"format_version":2,"#, 1);
+    let error = LinkedProgram::from_reader(&mut future.as_bytes())
+        .err()
+        .unwrap();
+    assert!(error.contains("format version"));
+}
+
+#[test]
+fn test_type_tree_canonicalization() {
+    let path = vec!["main".to_string()];
+    let mut type_tree = TypeTree::new();
+    type_tree.insert(
+        (path.clone(), 1),
+        (
+            Type::Option(Box::new(Type::Nominal(path.clone(), 2, vec![]))),
+            "wrapper".to_string(),
+        ),
+    );
+    type_tree.insert((path.clone(), 2), (Type::Uint, "inner".to_string()));
+    assert!(SerializableTypeTree::canonicalize(type_tree.clone()).is_ok());
+
+    type_tree.remove(&(path.clone(), 2));
+    let error = SerializableTypeTree::canonicalize(type_tree).err().unwrap();
+    assert!(error.description.contains("main, 2"));
+}
+
+#[test]
+fn test_arbos_version() {
+    let missing = Some(Path::new("arb_os/missing-constants.json"));
+    assert_eq!(arbos_version(Some(7), missing).unwrap(), 7);
+
+    let err = arbos_version(None, missing).unwrap_err();
+    assert!(err
+        .description
+        .contains("Could not determine the arbos version"));
+    assert!(err.description.contains("missing-constants.json"));
+
+    let configured = arbos_version(None, Some(Path::new("arb_os/constants.json"))).unwrap();
+    assert_ne!(configured, 0);
+    assert_eq!(arbos_version(None, None).unwrap(), configured);
+}
+
+#[test]
+fn test_unique_id_is_stable() {
+    let path = vec![String::from("std"), String::from("queue")];
+    assert_eq!(
+        Import::unique_id(&path, &String::from("queue_new")),
+        15660890359742546036,
+    );
+    assert_ne!(
+        Import::unique_id(&vec![String::from("ab")], &String::from("c")),
+        Import::unique_id(&vec![String::from("a")], &String::from("bc")),
+    );
+}
@@ -4,7 +4,7 @@
 
 //! Provides functions for modifying a sequence of Instructions to improve performance and lower gas costs.
 
-use crate::mavm::{AVMOpcode, Instruction, Opcode};
+use crate::mavm::{AVMOpcode, Instruction, Opcode, Value};
 
 /// Removes instructions that have no effect on the output of the program.
 fn useless_opcodes_layer<'a, I>(iter: I) -> impl Iterator<Item = &'a Instruction>
@@ -36,6 +36,8 @@ where
 /// same immediate
 /// * A Noop with an immediate followed by any instruction without an immediate, replaced by the
 /// second instruction with the immediate from the first.
+/// * A Noop with an integer immediate followed by a Cjump with an immediate destination, replaced
+/// by a Jump to that destination when the integer is nonzero and removed otherwise.
 pub fn peephole(code_in: &[Instruction]) -> Vec<Instruction> {
     let mut code_out = Vec::new();
 
@@ -191,6 +193,32 @@ pub fn peephole(code_in: &[Instruction]) -> Vec<Instruction> {
                         }
                     }
                 }
+                Instruction {
+                    opcode: Opcode::AVMOpcode(AVMOpcode::Cjump),
+                    immediate: Some(_),
+                    debug_info: loc1,
+                } => {
+                    let insn1 = code_out[code_out.len() - 1].clone();
+                    let insn2 = code_out[code_out.len() - 2].clone();
+                    if let Instruction {
+                        opcode: Opcode::AVMOpcode(AVMOpcode::Noop),
+                        immediate: Some(Value::Int(cond)),
+                        debug_info: _,
+                    } = insn2
+                    {
+                        code_out.pop();
+                        code_out.pop();
+                        if !cond.is_zero() {
+                            code_out.push(Instruction::new(
+                                Opcode::AVMOpcode(AVMOpcode::Jump),
+                                insn1.immediate,
+                                loc1,
+                            ));
+                        }
+                    } else {
+                        done = true;
+                    }
+                }
                 Instruction {
                     opcode: Opcode::AVMOpcode(avm_opcode),
                     immediate: None,
@@ -222,3 +250,236 @@ pub fn peephole(code_in: &[Instruction]) -> Vec<Instruction> {
     }
     code_out
 }
+
+/// Applies `peephole` until the code stops changing or `max_passes` passes have run, whichever
+/// comes first, returning the optimized code along with the number of passes that ran. A single
+/// pass is always run, even when `max_passes` is zero.
+pub fn peephole_to_fixed_point(
+    code_in: &[Instruction],
+    max_passes: usize,
+) -> (Vec<Instruction>, usize) {
+    let mut code = peephole(code_in);
+    let mut passes = 1;
+    while passes < max_passes {
+        let next = peephole(&code);
+        passes += 1;
+        if next == code {
+            break;
+        }
+        code = next;
+    }
+    (code, passes)
+}
+
+/// The most instructions the computation of a single value may span and still be moved by
+/// `schedule_pure`.
+const SCHEDULE_WINDOW: usize = 16;
+
+/// Finds the shortest run of pure instructions ending just before `end` that pushes exactly one
+/// value without consuming anything already on the stack, returning where the run starts.
+fn pure_producer(code: &[Instruction], end: usize) -> Option<usize> {
+    (end.saturating_sub(SCHEDULE_WINDOW)..end)
+        .rev()
+        .find(|&start| {
+            let mut height = 0;
+            for insn in &code[start..end] {
+                match insn.stack_effect() {
+                    Some((pops, pushes)) if pops <= height => height = height - pops + pushes,
+                    _ => return false,
+                }
+            }
+            height == 1
+        })
+}
+
+/// Reorders pure instructions within basic blocks to shed stack shuffling. When a `Swap1` exchanges
+/// two values that were each computed by pure instructions from nothing beneath them, the two
+/// computations are emitted in the opposite order and the swap is dropped. Since neither
+/// computation touches anything but the values it pushes, the stack that results is the same.
+pub fn schedule_pure(code_in: &[Instruction]) -> Vec<Instruction> {
+    let mut code_out: Vec<Instruction> = Vec::with_capacity(code_in.len());
+
+    for insn in code_in {
+        if insn.opcode == Opcode::AVMOpcode(AVMOpcode::Swap1) && insn.immediate.is_none() {
+            let producers = pure_producer(&code_out, code_out.len())
+                .and_then(|second| Some((pure_producer(&code_out, second)?, second)));
+
+            if let Some((first, second)) = producers {
+                let computed: Vec<_> = code_out.drain(first..).collect();
+                let (earlier, later) = computed.split_at(second - first);
+                code_out.extend_from_slice(later);
+                code_out.extend_from_slice(earlier);
+                continue;
+            }
+        }
+        code_out.push(insn.clone());
+    }
+    code_out
+}
+
+#[test]
+fn test_constant_branches() {
+    use crate::compile::DebugInfo;
+    use crate::mavm::Label;
+
+    let debug_info = DebugInfo::default();
+    let opcode = |opcode: AVMOpcode, immediate: Option<Value>| {
+        Instruction::new(Opcode::AVMOpcode(opcode), immediate, debug_info)
+    };
+    let target = Value::Label(Label::Anon(7));
+    let branch_on = |cond: u64| {
+        peephole(&[
+            opcode(AVMOpcode::Noop, Some(Value::from(cond))),
+            opcode(AVMOpcode::Cjump, Some(target.clone())),
+            opcode(AVMOpcode::Pop, None),
+        ])
+    };
+
+    assert_eq!(
+        branch_on(1),
+        vec![
+            opcode(AVMOpcode::Jump, Some(target.clone())),
+            opcode(AVMOpcode::Pop, None),
+        ]
+    );
+    assert_eq!(branch_on(0), vec![opcode(AVMOpcode::Pop, None)]);
+
+    // a condition computed at runtime must be left alone
+    let computed = vec![
+        opcode(AVMOpcode::Dup0, None),
+        opcode(AVMOpcode::Cjump, Some(target.clone())),
+    ];
+    assert_eq!(peephole(&computed), computed);
+}
+
+#[test]
+fn test_peephole_to_fixed_point() {
+    use crate::compile::DebugInfo;
+
+    let debug_info = DebugInfo::default();
+    let opcode = |opcode: AVMOpcode, immediate: Option<Value>| {
+        Instruction::new(Opcode::AVMOpcode(opcode), immediate, debug_info)
+    };
+    let code = vec![
+        opcode(AVMOpcode::Dup0, None),
+        opcode(AVMOpcode::AuxPush, None),
+        opcode(AVMOpcode::Noop, None),
+        opcode(AVMOpcode::AuxPop, None),
+        opcode(AVMOpcode::Pop, None),
+        opcode(AVMOpcode::Noop, Some(Value::from(3))),
+        opcode(AVMOpcode::Add, None),
+    ];
+    let optimized = vec![opcode(AVMOpcode::Add, Some(Value::from(3)))];
+
+    // a single pass is the default, and the second pass only confirms nothing changes
+    assert_eq!(peephole_to_fixed_point(&code, 1), (optimized.clone(), 1));
+    assert_eq!(peephole_to_fixed_point(&code, 10), (optimized.clone(), 2));
+    assert_eq!(peephole_to_fixed_point(&optimized, 0), (optimized, 1));
+}
+
+#[test]
+fn test_schedule_pure() {
+    use crate::compile::DebugInfo;
+    use rand::prelude::*;
+    use rand::rngs::SmallRng;
+
+    // runs code symbolically, so matching stacks mean matching results for every input
+    fn evaluate(code: &[Instruction], inputs: usize) -> Option<(Vec<String>, Vec<String>)> {
+        let mut stack: Vec<_> = (0..inputs).map(|input| format!("in{}", input)).collect();
+        let mut aux = vec![];
+        for insn in code {
+            if let Some(value) = &insn.immediate {
+                stack.push(format!("{:?}", value));
+            }
+            if insn.opcode == Opcode::AVMOpcode(AVMOpcode::AuxPush) {
+                aux.push(stack.pop()?);
+                continue;
+            }
+            let (pops, pushes) = insn.opcode.pure_stack_effect()?;
+            let args = stack.split_off(stack.len().checked_sub(pops)?);
+            match insn.opcode {
+                Opcode::AVMOpcode(AVMOpcode::Dup0 | AVMOpcode::Dup1 | AVMOpcode::Dup2) => {
+                    stack.extend(args.iter().cloned());
+                    stack.push(args[0].clone());
+                }
+                Opcode::AVMOpcode(AVMOpcode::Swap1 | AVMOpcode::Swap2) => {
+                    let mut args = args;
+                    let last = args.len() - 1;
+                    args.swap(0, last);
+                    stack.extend(args);
+                }
+                opcode => stack
+                    .extend((0..pushes).map(|output| format!("{:?}{}{:?}", opcode, output, args))),
+            }
+        }
+        Some((stack, aux))
+    }
+
+    let debug_info = DebugInfo::default();
+    let opcode = |opcode: AVMOpcode, immediate: Option<Value>| {
+        Instruction::new(Opcode::AVMOpcode(opcode), immediate, debug_info)
+    };
+
+    let swapped = vec![
+        opcode(AVMOpcode::Noop, Some(Value::from(2))),
+        opcode(AVMOpcode::Noop, Some(Value::from(3))),
+        opcode(AVMOpcode::IsZero, None),
+        opcode(AVMOpcode::Swap1, None),
+        opcode(AVMOpcode::Sub, None),
+    ];
+    let scheduled = schedule_pure(&swapped);
+    assert_eq!(
+        scheduled,
+        vec![
+            opcode(AVMOpcode::Noop, Some(Value::from(3))),
+            opcode(AVMOpcode::IsZero, None),
+            opcode(AVMOpcode::Noop, Some(Value::from(2))),
+            opcode(AVMOpcode::Sub, None),
+        ]
+    );
+    assert_eq!(evaluate(&scheduled, 0), evaluate(&swapped, 0));
+
+    // a swap whose operands came from beneath the block must be left alone
+    let inherited = vec![
+        opcode(AVMOpcode::Noop, Some(Value::from(2))),
+        opcode(AVMOpcode::Swap1, None),
+    ];
+    assert_eq!(schedule_pure(&inherited), inherited);
+
+    let pool = [
+        AVMOpcode::Noop,
+        AVMOpcode::Pop,
+        AVMOpcode::IsZero,
+        AVMOpcode::Add,
+        AVMOpcode::Sub,
+        AVMOpcode::AddMod,
+        AVMOpcode::Dup0,
+        AVMOpcode::Dup1,
+        AVMOpcode::Dup2,
+        AVMOpcode::Swap1,
+        AVMOpcode::Swap1,
+        AVMOpcode::Swap2,
+        AVMOpcode::AuxPush,
+    ];
+    let mut rng = SmallRng::seed_from_u64(745);
+    let mut shortened = 0;
+    for _ in 0..5000 {
+        let code: Vec<_> = (0..rng.gen_range(2..12))
+            .map(|_| {
+                let immediate = match rng.gen_bool(0.4) {
+                    true => Some(Value::from(rng.gen_range(0..100))),
+                    false => None,
+                };
+                opcode(pool[rng.gen_range(0..pool.len())], immediate)
+            })
+            .collect();
+        let scheduled = schedule_pure(&code);
+        if let Some(result) = evaluate(&code, 3) {
+            assert_eq!(evaluate(&scheduled, 3), Some(result), "{:?}", code);
+        }
+        if scheduled.len() < code.len() {
+            shortened += 1;
+        }
+    }
+    assert!(shortened > 0);
+}
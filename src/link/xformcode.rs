@@ -6,8 +6,9 @@
 
 use crate::compile::{CompileError, DebugInfo, GlobalVar, TypeTree};
 use crate::console::Color;
-use crate::mavm::{AVMOpcode, CodePt, Instruction, Opcode, Value};
+use crate::mavm::{AVMOpcode, CodePt, Instruction, Label, Opcode, Value};
 use crate::uint256::Uint256;
+use std::io;
 
 /// The maximum size of an AVM tuple
 pub const TUPLE_SIZE: usize = 8;
@@ -167,17 +168,40 @@ pub fn make_named_tuple(globals: &Vec<GlobalVar>) -> Value {
 }
 
 /// Creates a globals tuple with default values. The jump table is inserted at the end.
+///
+/// When `max_depth` is set, a global whose default value would lie more than that many tuples
+/// deep within the globals tuple is reported as an error.
 pub fn make_globals_tuple(
     globals: &Vec<GlobalVar>,
     jump_table: &Value,
     type_tree: &TypeTree,
-) -> Value {
-    let mut values: Vec<_> = globals
-        .iter()
-        .map(|g| g.tipe.default_value(type_tree))
-        .collect();
+    max_depth: Option<usize>,
+) -> Result<Value, CompileError> {
+    let slot_depths = TupleTree::new(globals.len(), false).leaf_depths();
+    let mut values = vec![];
+
+    for (global, slot_depth) in globals.iter().zip(slot_depths) {
+        let (value, depth) = global.tipe.default_layout(type_tree);
+        let depth = slot_depth + depth;
+        match max_depth {
+            Some(max_depth) if depth > max_depth => {
+                return Err(CompileError::new(
+                    String::from("Postlink error"),
+                    format!(
+                        "global {} of type {} nests tuples {} deep, beyond the limit of {}",
+                        Color::red(&global.name),
+                        Color::red(global.tipe.print(type_tree)),
+                        depth,
+                        max_depth,
+                    ),
+                    global.debug_info.locs(),
+                ));
+            }
+            _ => values.push(value),
+        }
+    }
     values[globals.len() - 1] = jump_table.clone();
-    TupleTree::fold_into_tuple(values)
+    Ok(TupleTree::fold_into_tuple(values))
 }
 
 /// Creates a globals tuple with (global-name, default-value) pairs
@@ -194,6 +218,24 @@ pub fn make_globals_tuple_debug(globals: &Vec<GlobalVar>, type_tree: &TypeTree)
     TupleTree::fold_into_tuple(values)
 }
 
+/// Writes the position of each global within the globals tuple next to its name, followed by the
+/// position of each label within the jump table.
+///
+/// Positions are given as the sequence of tuple slots leading from the root to the leaf.
+pub fn write_globals_layout(
+    globals: &[GlobalVar],
+    jump_table: &[Label],
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let names: Vec<_> = globals.iter().map(|global| global.name.clone()).collect();
+    writeln!(out, "Global Vars {}", names.len())?;
+    TupleTree::new(names.len(), false).write_labeled(&names, out)?;
+
+    let labels: Vec<_> = jump_table.iter().map(|label| label.to_string()).collect();
+    writeln!(out, "\nJump Table {}", labels.len())?;
+    TupleTree::new(labels.len(), false).write_labeled(&labels, out)
+}
+
 /// Replaces all instances of CodePt::Null with the error codepoint.
 pub fn set_error_codepoints(mut code: Vec<Instruction>) -> Vec<Instruction> {
     let error_codepoint = Value::CodePoint(CodePt::Internal(code.len() - 1));
@@ -296,6 +338,42 @@ impl TupleTree {
         }
     }
 
+    /// Gets how many tuples deep each leaf lies, ordered left to right.
+    pub fn leaf_depths(&self) -> Vec<usize> {
+        self.leaf_paths().iter().map(|path| path.len()).collect()
+    }
+
+    /// Gets the slots leading from the root to each leaf, ordered left to right.
+    fn leaf_paths(&self) -> Vec<Vec<usize>> {
+        match self {
+            TupleTree::Single => vec![vec![]],
+            TupleTree::Tree(_, subtrees) => subtrees
+                .iter()
+                .enumerate()
+                .flat_map(|(slot, subtree)| {
+                    subtree.leaf_paths().into_iter().map(move |mut path| {
+                        path.insert(0, slot);
+                        path
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes one line per leaf, pairing its path of slots with the corresponding entry of labels.
+    fn write_labeled(&self, labels: &[String], out: &mut dyn io::Write) -> io::Result<()> {
+        let paths: Vec<_> = self
+            .leaf_paths()
+            .into_iter()
+            .map(|path| format!("{:?}", path))
+            .collect();
+        let width = paths.iter().map(String::len).max().unwrap_or(0);
+        for (path, label) in paths.iter().zip(labels) {
+            writeln!(out, "{:width$}  {}", path, label, width = width)?;
+        }
+        Ok(())
+    }
+
     /// Gets the total number of nodes in the `TupleTree`
     fn tsize(&self) -> usize {
         match self {
@@ -455,3 +533,17 @@ impl TupleTree {
         }
     }
 }
+
+#[test]
+fn test_leaf_paths() {
+    let paths = TupleTree::new(9, false).leaf_paths();
+    assert_eq!(paths.len(), 9);
+    assert_eq!(paths[0], vec![0]);
+    assert_eq!(paths[6], vec![6]);
+    assert_eq!(paths[7], vec![7, 0]);
+    assert_eq!(paths[8], vec![7, 1]);
+    assert_eq!(
+        TupleTree::new(1, false).leaf_paths(),
+        vec![Vec::<usize>::new()]
+    );
+}
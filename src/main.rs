@@ -16,9 +16,10 @@ use run::{
     profile_gen_from_file, replay_from_testlog_file, run_from_file, ProfilerMode,
     RuntimeEnvironment,
 };
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
@@ -139,23 +140,109 @@ fn main() -> Result<(), CompileError> {
 
     match matches {
         Args::Compile(compile) => {
+            if let Some(code) = &compile.explain {
+                let text = compile::explain(code).ok_or_else(|| {
+                    CompileError::new(
+                        String::from("Explain error"),
+                        format!("No explanation exists for error code {}", code),
+                        vec![],
+                    )
+                })?;
+                println!("{}", text);
+                return Ok(());
+            }
+
+            if compile.check {
+                let error_system = match compile.check_only() {
+                    Ok(error_system) => error_system,
+                    Err(error_system) => error_system,
+                };
+                error_system.print();
+                return match error_system.errors.is_empty() {
+                    true => Ok(()),
+                    false => Err(CompileError::new(
+                        String::from("Check Failure"),
+                        String::from("Problems were found while checking"),
+                        vec![],
+                    )),
+                };
+            }
+
+            if compile.emit.as_deref() == Some("deps") {
+                let mut depends = BTreeMap::new();
+                for input in &compile.input {
+                    let (folder, library, main) = compile::locate_main(Path::new(input))?;
+                    depends.extend(compile::dependencies_from_folder(
+                        folder,
+                        library,
+                        main,
+                        compile.consts_file.as_deref().map(Path::new),
+                        !compile.no_builtins,
+                    )?);
+                }
+                let mut output = match compile.output {
+                    Some(ref path) => Box::new(create_output(path)?) as Box<dyn io::Write>,
+                    None => Box::new(io::stdout()),
+                };
+                match compile.format.as_deref() {
+                    Some("json") => serde_json::to_writer_pretty(&mut output, &depends).unwrap(),
+                    _ => {
+                        for (file, imports) in depends {
+                            let imports: Vec<_> = imports.into_iter().collect();
+                            writeln!(output, "{}: {}", file, imports.join(" ")).unwrap();
+                        }
+                    }
+                }
+                return Ok(());
+            }
+
             let mut output = match compile.output {
-                Some(ref path) => File::create(path)
-                    .map(|f| Box::new(f) as Box<dyn io::Write>)
-                    .unwrap(),
+                Some(ref path) => Box::new(create_output(path)?) as Box<dyn io::Write>,
                 None => Box::new(io::sink()),
             };
 
-            let error_system = match compile.invoke() {
-                Ok((program, error_system)) => {
-                    program.to_output(&mut output, compile.format.as_deref());
-                    error_system
-                }
-                Err(error_system) => error_system,
+            let error_system = match compile.emit.as_deref() {
+                Some("mao") => match compile.link_only() {
+                    Ok((program, mut error_system)) => {
+                        if let Err(why) = program.to_output(&mut output, compile.format.as_deref())
+                        {
+                            error_system.errors.push(CompileError::new(
+                                String::from("Compile error"),
+                                why,
+                                vec![],
+                            ));
+                        }
+                        error_system
+                    }
+                    Err(error_system) => error_system,
+                },
+                _ => match compile.invoke() {
+                    Ok((program, error_system)) => {
+                        program.to_output(&mut output, compile.format.as_deref());
+                        error_system
+                    }
+                    Err(error_system) => error_system,
+                },
             };
 
             error_system.print();
 
+            if let Some(path) = &compile.diagnostic_summary {
+                let written = File::create(path)
+                    .map_err(|why| why.to_string())
+                    .and_then(|file| {
+                        serde_json::to_writer(file, &error_system.summary())
+                            .map_err(|why| why.to_string())
+                    });
+                if let Err(why) = written {
+                    return Err(CompileError::new(
+                        String::from("Compile error"),
+                        format!("Could not write diagnostic summary to {}: {}", path, why),
+                        vec![],
+                    ));
+                }
+            }
+
             match error_system.errors.len() == 0 {
                 true => {}
                 false => {
@@ -241,21 +328,26 @@ fn main() -> Result<(), CompileError> {
                     vec![],
                 )
             })?;
-            let mut s = String::new();
-            file.read_to_string(&mut s).map_err(|_| {
-                CompileError::new(
-                    String::from("Reformat error"),
-                    format!("Failed to read input file \"{}\" to string", reformat.input),
-                    vec![],
-                )
-            })?;
-            let result: LinkedProgram = serde_json::from_str(&s).map_err(|_| {
+            let mut s = vec![];
+            file.read_to_end(&mut s).map_err(|_| {
                 CompileError::new(
                     String::from("Reformat error"),
-                    format!("Could not parse input file \"{}\" as json", reformat.input),
+                    format!("Failed to read input file \"{}\"", reformat.input),
                     vec![],
                 )
             })?;
+            let result: LinkedProgram = LinkedProgram::from_reader(&mut s.as_slice())
+                .or_else(|_| LinkedProgram::from_compact(&s))
+                .map_err(|_| {
+                    CompileError::new(
+                        String::from("Reformat error"),
+                        format!(
+                            "Could not parse input file \"{}\" as json or compact",
+                            reformat.input
+                        ),
+                        vec![],
+                    )
+                })?;
 
             result.to_output(
                 &mut get_output(reformat.output).unwrap(),
@@ -351,3 +443,14 @@ fn get_output(output_filename: Option<String>) -> Result<Box<dyn io::Write>, io:
         None => Ok(Box::new(io::stdout())),
     }
 }
+
+/// Creates the file at `path` for compiler output, reporting a failure as a `CompileError`.
+fn create_output(path: &str) -> Result<File, CompileError> {
+    File::create(path).map_err(|why| {
+        CompileError::new(
+            String::from("Compile error"),
+            format!("Could not create output file {}: {}", path, why),
+            vec![],
+        )
+    })
+}
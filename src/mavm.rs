@@ -144,6 +144,36 @@ impl Instruction<AVMOpcode> {
         }
     }
 
+    /// Appends a compact encoding of `self` to `out`, discarding any debug info.
+    ///
+    /// The opcode takes one byte and is followed by the immediate's encoding, or a single zero
+    /// byte if there is no immediate.
+    pub fn compact_encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        out.push(self.opcode.to_number());
+        match &self.immediate {
+            Some(val) => val.compact_encode(out),
+            None => {
+                out.push(0u8);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads an instruction encoded by `compact_encode` from the front of `input`, advancing it.
+    pub fn compact_decode(input: &mut &[u8]) -> Result<Self, String> {
+        let op_num = compact_read_byte(input)?;
+        let opcode = AVMOpcode::from_number(op_num as usize)
+            .ok_or_else(|| format!("invalid opcode {:#04x}", op_num))?;
+        let immediate = match input.first() {
+            Some(0u8) => {
+                *input = &input[1..];
+                None
+            }
+            _ => Some(Value::compact_decode(input)?),
+        };
+        Ok(Instruction::new(opcode, immediate, DebugInfo::default()))
+    }
+
     pub fn pretty_print(&self, highlight: &str) -> String {
         let label_color = Color::PINK;
         let op = Opcode::AVMOpcode(self.opcode).pretty_print(label_color);
@@ -161,6 +191,17 @@ impl Instruction {
     pub fn is_write(&self, type_tree: &TypeTree) -> bool {
         self.opcode.is_write(type_tree)
     }
+    /// The number of values the instruction pops and pushes if it affects nothing but the data
+    /// stack, treating its immediate, if any, as a value pushed just before its opcode runs.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        let (pops, pushes) = self.opcode.pure_stack_effect()?;
+        Some(match (&self.immediate, pops) {
+            (None, _) => (pops, pushes),
+            (Some(_), 0) => (0, pushes + 1),
+            (Some(_), _) => (pops - 1, pushes),
+        })
+    }
+
     pub fn get_label(&self) -> Option<Label> {
         match &self.opcode {
             Opcode::Label(label) => Some(*label),
@@ -604,6 +645,50 @@ fn _levels_needed(x: u128) -> (usize, u128) {
     (height, size)
 }
 
+// Value tags used by the compact encoding, a zero tag means an instruction has no immediate
+const COMPACT_INT_TAG: u8 = 0x01; // plus the number of bytes, up to 32
+const COMPACT_INT_MAX_TAG: u8 = 0x21;
+const COMPACT_TUPLE_TAG: u8 = 0x30; // plus the tuple's length, up to 8
+const COMPACT_TUPLE_MAX_TAG: u8 = 0x38;
+const COMPACT_CODEPOINT_TAG: u8 = 0x40;
+const COMPACT_BUFFER_TAG: u8 = 0x41;
+
+fn compact_read_byte(input: &mut &[u8]) -> Result<u8, String> {
+    match input.split_first() {
+        Some((byte, rest)) => {
+            *input = rest;
+            Ok(*byte)
+        }
+        None => Err(String::from("unexpected end of compact input")),
+    }
+}
+
+/// Writes `num` in unsigned LEB128 form.
+pub fn compact_write_varint(out: &mut Vec<u8>, mut num: usize) {
+    while num >= 0x80 {
+        out.push((num as u8 & 0x7f) | 0x80);
+        num >>= 7;
+    }
+    out.push(num as u8);
+}
+
+/// Reads an unsigned LEB128 number from the front of `input`, advancing it.
+pub fn compact_read_varint(input: &mut &[u8]) -> Result<usize, String> {
+    let mut num = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = compact_read_byte(input)?;
+        if shift >= 8 * std::mem::size_of::<usize>() {
+            return Err(String::from("compact varint overflows"));
+        }
+        num |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(num);
+        }
+        shift += 7;
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Value {
     Int(Uint256),
@@ -664,6 +749,83 @@ impl Value {
         }
     }
 
+    /// Appends a compact encoding of `self` to `out`.
+    ///
+    /// Integers are stored big-endian with leading zeros trimmed and the byte count folded into
+    /// the tag, tuples fold their length into the tag, and codepoints & buffer lengths use LEB128.
+    /// Only values that can appear in linked code are supported.
+    pub fn compact_encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Value::Int(ui) => {
+                let bytes = ui.to_bytes_be();
+                let first = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+                out.push(COMPACT_INT_TAG + (bytes.len() - first) as u8);
+                out.extend(&bytes[first..]);
+            }
+            Value::Tuple(tup) => {
+                if tup.len() > (COMPACT_TUPLE_MAX_TAG - COMPACT_TUPLE_TAG) as usize {
+                    return Err(format!(
+                        "tuple of length {} is too wide to encode",
+                        tup.len()
+                    ));
+                }
+                out.push(COMPACT_TUPLE_TAG + tup.len() as u8);
+                for subval in &**tup {
+                    subval.compact_encode(out)?;
+                }
+            }
+            Value::CodePoint(CodePt::Internal(pc)) => {
+                out.push(COMPACT_CODEPOINT_TAG);
+                compact_write_varint(out, *pc);
+            }
+            Value::Buffer(buf) => {
+                let size = buf.max_size() as usize;
+                out.push(COMPACT_BUFFER_TAG);
+                compact_write_varint(out, size);
+                out.extend(buf.as_bytes(size));
+            }
+            other => return Err(format!("unable to compactly encode value {}", other)),
+        }
+        Ok(())
+    }
+
+    /// Reads a value encoded by `compact_encode` from the front of `input`, advancing it.
+    pub fn compact_decode(input: &mut &[u8]) -> Result<Self, String> {
+        let tag = compact_read_byte(input)?;
+        match tag {
+            COMPACT_INT_TAG..=COMPACT_INT_MAX_TAG => {
+                let len = (tag - COMPACT_INT_TAG) as usize;
+                if input.len() < len {
+                    return Err(String::from("unexpected end of compact input"));
+                }
+                let (bytes, rest) = input.split_at(len);
+                *input = rest;
+                Ok(Value::Int(Uint256::from_bytes(bytes)))
+            }
+            COMPACT_TUPLE_TAG..=COMPACT_TUPLE_MAX_TAG => {
+                let len = (tag - COMPACT_TUPLE_TAG) as usize;
+                let mut tup = Vec::with_capacity(len);
+                for _ in 0..len {
+                    tup.push(Value::compact_decode(input)?);
+                }
+                Ok(Value::new_tuple(tup))
+            }
+            COMPACT_CODEPOINT_TAG => Ok(Value::CodePoint(CodePt::Internal(compact_read_varint(
+                input,
+            )?))),
+            COMPACT_BUFFER_TAG => {
+                let size = compact_read_varint(input)?;
+                if input.len() < size {
+                    return Err(String::from("unexpected end of compact input"));
+                }
+                let (bytes, rest) = input.split_at(size);
+                *input = rest;
+                Ok(Value::Buffer(Buffer::from_bytes(bytes.to_vec())))
+            }
+            other => Err(format!("invalid compact value tag {:#04x}", other)),
+        }
+    }
+
     pub fn is_none(&self) -> bool {
         self == &Value::none()
     }
@@ -1086,6 +1248,72 @@ impl Opcode {
         }
     }
 
+    /// Whether an immediate attached to this opcode would be lost when it's lowered. Labels are
+    /// removed outright, and the listed virtual opcodes expand to AVM code that doesn't carry it.
+    pub fn drops_immediate(&self) -> bool {
+        matches!(
+            self,
+            Opcode::Label(_)
+                | Opcode::MakeFrame(..)
+                | Opcode::GetLocal(_)
+                | Opcode::SetLocal(_)
+                | Opcode::TupleGet(..)
+                | Opcode::TupleSet(..)
+                | Opcode::GetGlobalVar(_)
+                | Opcode::SetGlobalVar(_)
+                | Opcode::UncheckedFixedArrayGet(_)
+        )
+    }
+
+    /// The number of values this opcode pops and pushes when its only effect is on the data stack.
+    /// Opcodes that touch anything else, such as the aux stack, registers, gas, or control flow,
+    /// aren't modeled and give `None`.
+    pub fn pure_stack_effect(&self) -> Option<(usize, usize)> {
+        let opcode = match self {
+            Opcode::AVMOpcode(opcode) => opcode,
+            _ => return None,
+        };
+        Some(match opcode {
+            AVMOpcode::Noop => (0, 0),
+            AVMOpcode::Pop => (1, 0),
+            AVMOpcode::IsZero
+            | AVMOpcode::BitwiseNeg
+            | AVMOpcode::Hash
+            | AVMOpcode::Type
+            | AVMOpcode::Tlen => (1, 1),
+            AVMOpcode::Add
+            | AVMOpcode::Mul
+            | AVMOpcode::Sub
+            | AVMOpcode::Div
+            | AVMOpcode::Sdiv
+            | AVMOpcode::Mod
+            | AVMOpcode::Smod
+            | AVMOpcode::Exp
+            | AVMOpcode::SignExtend
+            | AVMOpcode::LessThan
+            | AVMOpcode::GreaterThan
+            | AVMOpcode::SLessThan
+            | AVMOpcode::SGreaterThan
+            | AVMOpcode::Equal
+            | AVMOpcode::BitwiseAnd
+            | AVMOpcode::BitwiseOr
+            | AVMOpcode::BitwiseXor
+            | AVMOpcode::Byte
+            | AVMOpcode::ShiftLeft
+            | AVMOpcode::ShiftRight
+            | AVMOpcode::ShiftArith
+            | AVMOpcode::EthHash2
+            | AVMOpcode::Tget => (2, 1),
+            AVMOpcode::AddMod | AVMOpcode::MulMod | AVMOpcode::Tset => (3, 1),
+            AVMOpcode::Dup0 => (1, 2),
+            AVMOpcode::Dup1 => (2, 3),
+            AVMOpcode::Dup2 => (3, 4),
+            AVMOpcode::Swap1 => (2, 2),
+            AVMOpcode::Swap2 => (3, 3),
+            _ => return None,
+        })
+    }
+
     pub fn pretty_print(&self, label_color: &str) -> String {
         match self {
             Opcode::MakeFrame(space, prebuilt) => match prebuilt {
@@ -1534,6 +1762,52 @@ fn test_consistent_opcode_numbers() {
     }
 }
 
+#[test]
+fn test_compact_round_trip() {
+    let insns = vec![
+        Instruction::from_opcode(AVMOpcode::Add, DebugInfo::default()),
+        Instruction::from_opcode_imm(
+            AVMOpcode::Noop,
+            Value::Int(Uint256::zero()),
+            DebugInfo::default(),
+        ),
+        Instruction::from_opcode_imm(
+            AVMOpcode::Jump,
+            Value::CodePoint(CodePt::Internal(300)),
+            DebugInfo::default(),
+        ),
+        Instruction::from_opcode_imm(
+            AVMOpcode::Tset,
+            Value::new_tuple(vec![
+                Value::Int(Uint256::from_usize(7)),
+                Value::Int(Uint256::from_string_hex(&"ff".repeat(32)).unwrap()),
+                Value::new_tuple(vec![]),
+                Value::Buffer(Buffer::from_bytes(vec![0, 1, 2, 0])),
+            ]),
+            DebugInfo::default(),
+        ),
+    ];
+
+    let mut encoded = vec![];
+    for insn in &insns {
+        insn.compact_encode(&mut encoded).unwrap();
+    }
+
+    let mut input = &encoded[..];
+    for insn in &insns {
+        assert_eq!(&Instruction::compact_decode(&mut input).unwrap(), insn);
+    }
+    assert!(input.is_empty());
+
+    // a zero immediate costs a single byte
+    let mut small = vec![];
+    insns[1].compact_encode(&mut small).unwrap();
+    assert_eq!(small, vec![AVMOpcode::Noop.to_number(), 0x01]);
+
+    let label = Value::Label(Label::Anon(0));
+    assert!(label.compact_encode(&mut vec![]).is_err());
+}
+
 impl fmt::Display for Opcode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -1,15 +1,17 @@
-use crate::compile::{CompileError, CompileStruct, FileInfo};
-use crate::mavm::Value;
+use crate::compile::{
+    dependencies_from_folder, CompileError, CompileSession, CompileStruct, FileInfo,
+};
+use crate::mavm::{AVMOpcode, CodePt, Instruction, Value};
 use crate::run::{run, Machine, RuntimeEnvironment};
 use crate::uint256::Uint256;
 use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 fn compile_run_cycle(input: String) -> Machine {
-    let mut compile = CompileStruct::default();
-    compile.input = vec![input.clone()];
+    let mut compile = compile_options(&input);
     compile.test_mode = true;
-    compile.consts_file = Some(format!("arb_os/constants.json"));
+    compile.verify = true;
 
     let mexe = match compile.invoke() {
         Ok((mexe, _error_system)) => mexe,
@@ -22,6 +24,67 @@ fn compile_run_cycle(input: String) -> Machine {
     machine
 }
 
+/// Sets up compiling `input` with the arbos constants, leaving every other option at its default.
+fn compile_options(input: &str) -> CompileStruct {
+    let mut compile = CompileStruct::default();
+    compile.input = vec![input.to_string()];
+    compile.consts_file = Some("arb_os/constants.json".to_string());
+    compile
+}
+
+/// Compiles `input` in test mode and links it, returning each instruction that came from `input`
+/// along with its index in the linked code and the line it came from.
+fn located_code(input: &str) -> Vec<(usize, Instruction<AVMOpcode>, usize)> {
+    let mut compile = compile_options(input);
+    compile.test_mode = true;
+    let (program, _) = compile.invoke().ok().expect("failed to compile");
+    let name = Path::new(input).file_stem().and_then(|stem| stem.to_str());
+    program
+        .code
+        .iter()
+        .enumerate()
+        .filter_map(|(index, insn)| {
+            let location = insn.debug_info.location?;
+            let file = &program.file_info_chart[&location.file_id];
+            let line = location.line.to_usize() + 1;
+            (Some(file.name.as_str()) == name).then(|| (index, insn.clone(), line))
+        })
+        .collect()
+}
+
+/// Finds where the loop on `line` starts, the earliest point its jumps lead to.
+fn loop_start(located: &[(usize, Instruction<AVMOpcode>, usize)], line: usize) -> usize {
+    located
+        .iter()
+        .filter_map(|(_, insn, insn_line)| match &insn.immediate {
+            Some(Value::CodePoint(CodePt::Internal(start))) if *insn_line == line => Some(*start),
+            _ => None,
+        })
+        .min()
+        .expect("no loop")
+}
+
+/// Returns the index of each instruction from `line` with the given opcode.
+fn opcodes_on_line(
+    located: &[(usize, Instruction<AVMOpcode>, usize)],
+    line: usize,
+    opcode: AVMOpcode,
+) -> Vec<usize> {
+    located
+        .iter()
+        .filter(|(_, insn, insn_line)| *insn_line == line && insn.opcode == opcode)
+        .map(|(index, ..)| *index)
+        .collect()
+}
+
+/// Makes a folder for the files `test` writes, unique to this process so that concurrent runs of
+/// the suite don't overwrite each other's output.
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mini-{}-{}", std::process::id(), test));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 #[test]
 fn test_basic() {
     let machine = compile_run_cycle("minitests/basic.mini".to_string());
@@ -34,6 +97,784 @@ fn test_if_else() {
     assert_eq!(machine.stack_top(), Some(&Value::Int(Uint256::zero())));
 }
 
+#[test]
+fn test_import_alias() {
+    let machine = compile_run_cycle("minitests/import-alias.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(42)))
+    );
+}
+
+#[test]
+fn test_nested_modules() {
+    let machine = compile_run_cycle("minitests/nested/main.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(42)))
+    );
+}
+
+#[test]
+fn test_diverging() {
+    let machine = compile_run_cycle("minitests/diverging.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(36)))
+    );
+}
+
+#[test]
+fn test_arg_patterns() {
+    let machine = compile_run_cycle("minitests/arg-patterns.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(177)))
+    );
+}
+
+#[test]
+fn test_loop_invariants() {
+    let machine = compile_run_cycle("minitests/loop-invariants.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(1725)))
+    );
+
+    let located = located_code("minitests/loop-invariants.mini");
+
+    // base * scale and (base + scale) * 2 are computed once, before the outer loop starts
+    let outer_start = loop_start(&located, 11);
+    let products: Vec<_> = [11, 12]
+        .iter()
+        .flat_map(|line| opcodes_on_line(&located, *line, AVMOpcode::Mul))
+        .collect();
+    assert_eq!(products.len(), 2);
+    assert!(products.iter().all(|index| *index < outer_start));
+
+    // i * 2 only changes with the outer loop, so it leaves the inner loop but not the outer one
+    let inner_start = loop_start(&located, 14);
+    let inner_products = opcodes_on_line(&located, 15, AVMOpcode::Mul);
+    assert!(inner_products
+        .iter()
+        .any(|index| outer_start < *index && *index < inner_start));
+
+    // the division may trap, so it stays in its loop
+    let division_start = loop_start(&located, 23);
+    let divisions = opcodes_on_line(&located, 24, AVMOpcode::Div);
+    assert!(!divisions.is_empty());
+    assert!(divisions.iter().all(|index| *index > division_start));
+}
+
+#[test]
+fn test_literal_inference() {
+    let machine = compile_run_cycle("minitests/literal-inference.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(132)))
+    );
+}
+
+#[test]
+fn test_redundant_options() {
+    let machine = compile_run_cycle("minitests/redundant-options.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(154)))
+    );
+}
+
+#[test]
+fn test_bool_if() {
+    let machine = compile_run_cycle("minitests/bool-if.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(27)))
+    );
+}
+
+#[test]
+fn test_let_assign() {
+    let machine = compile_run_cycle("minitests/let-assign.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(36)))
+    );
+}
+
+#[test]
+fn test_nested_let() {
+    let machine = compile_run_cycle("minitests/nested-let.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(215)))
+    );
+}
+
+#[test]
+fn test_const_folding() {
+    let machine = compile_run_cycle("minitests/const-folding.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(3007 + 0x34)))
+    );
+}
+
+#[test]
+fn test_constant_loops() {
+    let machine = compile_run_cycle("minitests/constant-loops.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(42)))
+    );
+
+    let located = located_code("minitests/constant-loops.mini");
+
+    // the while false is gone, and the while true no longer tests its condition
+    assert!(located.iter().all(|(_, _, line)| *line != 8 && *line != 9));
+    assert!(located
+        .iter()
+        .all(|(_, insn, line)| { *line != 11 || insn.opcode != AVMOpcode::Cjump }));
+
+    // step * 2 is computed once, before the loop starts
+    let loop_start = loop_start(&located, 11);
+    let products = opcodes_on_line(&located, 12, AVMOpcode::Mul);
+    assert!(!products.is_empty());
+    assert!(products.iter().all(|index| *index < loop_start));
+}
+
+#[test]
+fn test_branch_returns() {
+    let machine = compile_run_cycle("minitests/branch-returns.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(127)))
+    );
+}
+
+#[test]
+fn test_assert_levels() {
+    let mut compile = compile_options("minitests/assert-levels.mini");
+    compile.test_mode = true;
+    let outcome = |compile: &CompileStruct| {
+        let (mexe, error_system) = compile.invoke().ok().expect("failed to compile");
+        let mut lines: Vec<_> = error_system
+            .warnings
+            .into_iter()
+            .filter(|warning| warning.description.contains("Stripped assert"))
+            .map(|warning| warning.locations[0].line.to_usize() + 1)
+            .collect();
+        lines.sort();
+        let mut machine = Machine::new(mexe, RuntimeEnvironment::new(None));
+        let result = run(&mut machine, vec![], false, None).map(|_| machine.stack_top().cloned());
+        (result, lines)
+    };
+    let two = Some(Value::Int(Uint256::from_u64(2)));
+
+    let (result, stripped) = outcome(&compile);
+    assert!(result.is_err(), "debug_assert wasn't checked");
+    assert!(stripped.is_empty());
+
+    compile.assert_level = Some("release".to_string());
+    let (result, stripped) = outcome(&compile);
+    assert_eq!(result.ok(), Some(two.clone()));
+    assert_eq!(stripped, vec![14]);
+
+    compile.assert_level = None;
+    compile.release_build = true;
+    let (result, stripped) = outcome(&compile);
+    assert_eq!(result.ok(), Some(two));
+    assert_eq!(stripped, vec![14, 15]);
+
+    compile.release_build = false;
+    compile.assert_level = Some("paranoid".to_string());
+    assert!(compile.invoke().is_err());
+}
+
+#[test]
+fn test_comment_literals() {
+    let machine = compile_run_cycle("minitests/comment-literals.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(24)))
+    );
+
+    let compile = compile_options("minitests/comment-columns.mini");
+    let error_system = compile.invoke().err().expect("mistyped let compiled");
+    let location = error_system.errors[0].locations[0];
+    assert_eq!(location.line.to_usize(), 6);
+    assert_eq!(location.column.to_usize(), 45);
+}
+
+#[test]
+fn test_comment_parse_error_column() {
+    let compile = compile_options("minitests/comment-parse-error.mini");
+    let error_system = compile.invoke().err().expect("bad expression parsed");
+    let location = error_system.errors[0].locations[0];
+    assert_eq!(location.line.to_usize(), 5);
+    assert_eq!(location.column.to_usize(), 35);
+}
+
+#[test]
+fn test_map_default() {
+    let machine = compile_run_cycle("minitests/map-default.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(42)))
+    );
+}
+
+#[test]
+fn test_cyclic_alias() {
+    let compile = compile_options("minitests/cyclic-alias.mini");
+    let error_system = compile.invoke().err().expect("cyclic alias compiled");
+    let description = &error_system.errors[0].description;
+    assert!(description.contains("cyclic type definition"));
+    assert!(description.contains("Ping") || description.contains("Pong"));
+    let line = match description.contains("Ping") {
+        true => 4,
+        false => 5,
+    };
+    assert_eq!(error_system.errors[0].locations[0].line.to_usize(), line);
+}
+
+#[test]
+fn test_array_literal() {
+    let machine = compile_run_cycle("minitests/array-literal.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(143)))
+    );
+}
+
+#[test]
+fn test_assert_eq() {
+    let machine = compile_run_cycle("minitests/assert-eq.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(42)))
+    );
+}
+
+#[test]
+fn test_symbol_index() {
+    let index_path = scratch_dir("test_symbol_index").join("symbol-index.json");
+    let mut compile = compile_options("minitests/import-alias.mini");
+    compile.symbol_index = Some(index_path.display().to_string());
+    compile.invoke().ok().expect("failed to compile");
+
+    let index: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(&index_path).unwrap()).unwrap();
+    let lines = |name: &str, kind: &str| -> Vec<u64> {
+        let symbols = index["import-alias"].as_object().unwrap();
+        let symbol = symbols
+            .values()
+            .find(|symbol| symbol["name"] == name)
+            .unwrap();
+        let mut lines: Vec<u64> = symbol[kind]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| location["line"].as_u64().unwrap() + 1)
+            .collect();
+        lines.sort();
+        lines
+    };
+
+    assert_eq!(lines("sizeOf", "definitions"), vec![17]);
+    assert_eq!(lines("sizeOf", "uses"), vec![14]);
+    assert_eq!(lines("newQueue", "definitions"), vec![6]);
+    assert_eq!(lines("q", "definitions"), vec![11, 17]);
+    assert_eq!(lines("put", "uses"), vec![12, 13]);
+}
+
+#[test]
+fn test_json_schema() {
+    let schema_path = scratch_dir("test_json_schema").join("entry-points.schema.json");
+    let mut compile = compile_options("minitests/entry-points.mini");
+    compile.entry_points = vec!["deposit".to_string()];
+    compile.json_schema = Some(schema_path.display().to_string());
+    compile.invoke().ok().expect("failed to compile");
+
+    let schema: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(&schema_path).unwrap()).unwrap();
+    let entry_points = schema["entry_points"].as_object().unwrap();
+    let mut names: Vec<_> = entry_points.keys().collect();
+    names.sort();
+    assert_eq!(names, vec!["entry-points::deposit", "entry-points::main"]);
+
+    let uint = serde_json::json!({ "type": "string", "pattern": "^[0-9]+$" });
+    let deposit = &entry_points["entry-points::deposit"];
+    assert_eq!(deposit["args"], serde_json::json!([uint]));
+    assert_eq!(deposit["returns"], uint);
+}
+
+#[test]
+fn test_unreachable_funcs_not_linked() {
+    use crate::link::Import;
+    use crate::mavm::{Label, Opcode};
+
+    let mut compile = compile_options("minitests/entry-points.mini");
+    compile.entry_points = vec!["deposit".to_string()];
+    let (linked, _) = compile.link_only().ok().expect("failed to link");
+
+    let labeled = |name: &str| {
+        let id = Import::unique_id(&vec!["entry-points".to_string()], &name.to_string());
+        linked
+            .code
+            .iter()
+            .any(|insn| insn.opcode == Opcode::Label(Label::Func(id)))
+    };
+    assert!(labeled("scaled"));
+    assert!(!labeled("withdraw"));
+    assert!(!labeled("stale"));
+}
+
+#[test]
+fn test_shared_global_names() {
+    let mut compile = compile_options("minitests/shared-globals/main.mini");
+    compile.test_mode = true;
+    let (mexe, _) = compile.invoke().ok().expect("failed to compile");
+
+    let counters: Vec<_> = mexe
+        .globals
+        .iter()
+        .filter(|global| global.name == "counter")
+        .collect();
+    assert_eq!(counters.len(), 2);
+    assert_ne!(counters[0].id, counters[1].id);
+
+    let mut machine = Machine::new(mexe, RuntimeEnvironment::new(None));
+    run(&mut machine, vec![], false, None).unwrap();
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(31)))
+    );
+}
+
+#[test]
+fn test_merge_identical_funcs() {
+    use crate::link::Import;
+    use crate::mavm::{Label, Opcode};
+
+    let mut compile = compile_options("minitests/identical-funcs.mini");
+    compile.merge_funcs = true;
+    let (linked, error_system) = compile.link_only().ok().expect("failed to link");
+    assert_eq!(error_system.notes, vec!["merged 1 identical funcs"]);
+
+    let id =
+        |name: &str| Import::unique_id(&vec!["identical-funcs".to_string()], &name.to_string());
+    let labeled = |name: &str| {
+        linked
+            .code
+            .iter()
+            .any(|insn| insn.opcode == Opcode::Label(Label::Func(id(name))))
+    };
+    let calls = |name: &str| {
+        linked
+            .code
+            .iter()
+            .filter(|insn| insn.immediate == Some(Value::Label(Label::Func(id(name)))))
+            .count()
+    };
+    assert!(labeled("doubled"));
+    assert!(!labeled("twice"));
+    assert_eq!(calls("doubled"), 2);
+    assert_eq!(calls("twice"), 0);
+
+    compile.test_mode = true;
+    let (mexe, _) = compile.invoke().ok().expect("failed to compile");
+    let mut machine = Machine::new(mexe, RuntimeEnvironment::new(None));
+    run(&mut machine, vec![], false, None).unwrap();
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(17)))
+    );
+}
+
+#[test]
+fn test_layout_by_usage() {
+    let mut compile = compile_options("minitests/identical-funcs.mini");
+    compile.layout_by_usage = true;
+    let (_, error_system) = compile.link_only().ok().expect("failed to link");
+    assert_eq!(error_system.notes.len(), 1);
+    assert!(error_system.notes[0].starts_with("backward jumps: "));
+}
+
+#[test]
+fn test_call_graph() {
+    let mut compile = compile_options("minitests/entry-points.mini");
+
+    // without the flag nothing is written, so a callgraph.dot left in the working directory by
+    // anything else is untouched
+    let modified = || {
+        std::fs::metadata("callgraph.dot")
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let before = modified();
+    compile.link_only().ok().expect("failed to link");
+    assert_eq!(modified(), before);
+
+    let dot = scratch_dir("test_call_graph").join("entry-points.dot");
+    compile.call_graph = Some(dot.display().to_string());
+    compile.link_only().ok().expect("failed to link");
+    let written = std::fs::read_to_string(&dot).unwrap();
+    assert!(written.starts_with("digraph"));
+    assert!(written.contains("main"));
+
+    compile.call_graph = Some("minitests/no-such-folder/callgraph.dot".to_string());
+    assert!(compile.link_only().is_err());
+}
+
+#[test]
+fn test_optimizer_listings() {
+    let listings = scratch_dir("test_optimizer_listings").join("bool-if");
+    let mut compile = compile_options("minitests/bool-if.mini");
+    compile.optimizer_listings = Some(listings.display().to_string());
+    compile.invoke().ok().expect("failed to compile");
+
+    let read = |suffix| std::fs::read_to_string(format!("{}.{}", listings.display(), suffix));
+    let before = read("before").unwrap();
+    let after = read("after").unwrap();
+    assert!(before.starts_with("0000  "));
+    assert!(after.lines().count() < before.lines().count());
+}
+
+#[test]
+fn test_warn_unimplemented() {
+    let mut compile = compile_options("minitests/unimplemented.mini");
+    let warnings = |compile: &CompileStruct| -> Vec<String> {
+        let (_, error_system) = compile.invoke().ok().expect("failed to compile");
+        error_system
+            .warnings
+            .into_iter()
+            .map(|warning| warning.description)
+            .filter(|description| description.contains("unimplemented"))
+            .collect()
+    };
+    assert!(warnings(&compile).is_empty());
+
+    compile.warn_unimplemented = true;
+    let stubs = warnings(&compile);
+    assert_eq!(stubs.len(), 2);
+    assert!(stubs.iter().any(|stub| stub.contains("fee")));
+    assert!(stubs.iter().any(|stub| stub.contains("settle")));
+}
+
+#[test]
+fn test_no_implicit_any() {
+    let mut compile = compile_options("minitests/implicit-any.mini");
+    let widened = |compile: &CompileStruct| -> Vec<usize> {
+        let (_, error_system) = compile.invoke().ok().expect("failed to compile");
+        error_system
+            .warnings
+            .into_iter()
+            .filter(|warning| warning.description.contains("implicitly"))
+            .map(|warning| warning.locations[0].line.to_usize() + 1)
+            .collect()
+    };
+    assert!(widened(&compile).is_empty());
+
+    compile.no_implicit_any = true;
+    let mut lines = widened(&compile);
+    lines.sort();
+    assert_eq!(lines, vec![12, 16, 21, 24]);
+}
+
+#[test]
+fn test_declared_type_hint() {
+    let compile = compile_options("minitests/many-type-errors.mini");
+    let outcome = compile.compile();
+    let mismatch = outcome
+        .diagnostics
+        .iter()
+        .find(|issue| issue.description.contains("Cannot make assignment"))
+        .expect("no mismatch");
+    assert!(mismatch.description.contains("pair: (bool, uint)"));
+}
+
+#[test]
+fn test_compile_session() {
+    let mut compile = compile_options("minitests/map-iteration.mini");
+    compile.test_mode = true;
+
+    let alone = compile.compile().program.expect("failed to compile").code;
+
+    let mut session = CompileSession::default();
+    let first = compile.compile_in_session(&mut session);
+    let cached = session.cached_len();
+    assert!(cached > 0, "no library modules were kept");
+
+    let second = compile.compile_in_session(&mut session);
+    assert_eq!(session.cached_len(), cached);
+    assert_eq!(alone, first.program.expect("failed to compile").code);
+    assert_eq!(alone, second.program.expect("failed to compile").code);
+}
+
+#[test]
+#[ignore]
+fn bench_compile_session() {
+    let mut compile = compile_options("minitests/map-iteration.mini");
+    compile.test_mode = true;
+    let mut session = CompileSession::default();
+
+    let start = std::time::Instant::now();
+    let mut alone = vec![];
+    for _ in 0..100 {
+        alone.push(compile.compile().program.expect("failed to compile").code);
+    }
+    let without_session = start.elapsed();
+    let start = std::time::Instant::now();
+    let mut shared = vec![];
+    for _ in 0..100 {
+        let outcome = compile.compile_in_session(&mut session);
+        shared.push(outcome.program.expect("failed to compile").code);
+    }
+    let with_session = start.elapsed();
+
+    println!(
+        "100 compiles took {:?} without a session and {:?} with one",
+        without_session, with_session
+    );
+    assert_eq!(alone, shared);
+}
+
+#[test]
+fn test_schedule_pure() {
+    for input in &[
+        "minitests/arithmetic.mini",
+        "minitests/map-iteration.mini",
+        "minitests/generics/queue.mini",
+    ] {
+        let mut compile = compile_options(input);
+        compile.test_mode = true;
+        compile.verify = true;
+
+        let mut results = vec![];
+        for schedule_pure in vec![false, true] {
+            compile.schedule_pure = schedule_pure;
+            let (mexe, _) = compile.invoke().ok().expect("failed to compile");
+            let size = mexe.code.len();
+            let mut machine = Machine::new(mexe, RuntimeEnvironment::new(None));
+            run(&mut machine, vec![], false, None).unwrap();
+            results.push((machine.stack_top().cloned(), size));
+        }
+        assert_eq!(results[0].0, results[1].0);
+        assert!(results[1].1 <= results[0].1);
+    }
+}
+
+#[test]
+fn test_include_bytes() {
+    let machine = compile_run_cycle("minitests/include-bytes.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(15 * 1000 + b'e' as u64)))
+    );
+
+    let mut compile = compile_options("minitests/include-bytes.mini");
+    compile.max_include_size = Some(8);
+    let outcome = compile.compile();
+    assert!(outcome.program.is_none());
+    let errors: Vec<_> = outcome
+        .diagnostics
+        .into_iter()
+        .filter(|issue| !issue.is_warning)
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].description.contains("beyond the limit of 8"));
+    assert_eq!(errors[0].locations[0].line.to_usize(), 5);
+}
+
+#[test]
+fn test_max_tuple_depth() {
+    let mut compile = compile_options("minitests/tuple-depth.mini");
+    compile.test_mode = true;
+    assert!(compile.compile().program.is_some());
+
+    compile.max_tuple_depth = Some(10);
+    assert!(compile.compile().program.is_some());
+
+    compile.max_tuple_depth = Some(8);
+    let outcome = compile.compile();
+    assert!(outcome.program.is_none());
+    let errors: Vec<_> = outcome
+        .diagnostics
+        .into_iter()
+        .filter(|issue| !issue.is_warning)
+        .collect();
+    assert_eq!(errors.len(), 3);
+    assert!(errors
+        .iter()
+        .all(|error| error.description.contains("beyond the limit of 8")));
+    let lines: BTreeSet<_> = errors
+        .iter()
+        .map(|error| error.locations[0].line.to_usize())
+        .collect();
+    assert_eq!(lines, vec![5, 9, 10].into_iter().collect());
+    assert!(errors.iter().any(|error| error.description.contains("big")));
+}
+
+#[test]
+fn test_mao_round_trip() {
+    let mut compile = compile_options("minitests/generics/queue.mini");
+    compile.test_mode = true;
+    let (direct, _) = compile.invoke().ok().expect("failed to compile");
+    let (linked, _) = compile.link_only().ok().expect("failed to link");
+
+    for format in &["json", "bincode"] {
+        let mao_path = scratch_dir("test_mao_round_trip").join(format!("queue.{}.mao", format));
+        let mut file = std::fs::File::create(&mao_path).unwrap();
+        linked.write_mao(&mut file, Some(format)).unwrap();
+
+        compile.input = vec![mao_path.display().to_string()];
+        let (resumed, _) = compile.invoke().ok().expect("failed to compile from mao");
+        assert_eq!(resumed.code, direct.code);
+        assert_eq!(resumed.static_val, direct.static_val);
+    }
+}
+
+#[test]
+fn test_bincode_output_round_trip() {
+    use crate::compile::CompiledProgram;
+
+    let mut compile = compile_options("minitests/generics/queue.mini");
+    compile.test_mode = true;
+    let (linked, _) = compile.link_only().ok().expect("failed to link");
+
+    let mao_path = scratch_dir("test_bincode_output_round_trip").join("queue.mao");
+    let mut file = std::fs::File::create(&mao_path).unwrap();
+    linked.to_output(&mut file, Some("bincode")).unwrap();
+    drop(file);
+
+    let written = std::fs::read(&mao_path).unwrap();
+    let read = CompiledProgram::read_mao(&mut written.as_slice()).unwrap();
+    let mut rewritten = vec![];
+    read.to_output(&mut rewritten, Some("bincode")).unwrap();
+    assert!(
+        written == rewritten,
+        "bincode output changed after a round trip"
+    );
+}
+
+#[test]
+fn test_require_mut() {
+    let machine = compile_run_cycle("minitests/require-mut.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(16)))
+    );
+
+    let mut compile = compile_options("minitests/require-mut.mini");
+    compile.require_mut = true;
+    let errors = match compile.invoke() {
+        Ok((_, error_system)) | Err(error_system) => error_system.errors,
+    };
+    let lines: Vec<Vec<usize>> = errors
+        .iter()
+        .map(|error| {
+            error
+                .locations
+                .iter()
+                .map(|location| location.line.to_usize() + 1)
+                .collect()
+        })
+        .collect();
+    assert_eq!(lines, vec![vec![13, 12]]);
+}
+
+#[test]
+fn test_check_only() {
+    let check = |compile: CompileStruct| match compile.check_only() {
+        Ok(error_system) => (true, error_system),
+        Err(error_system) => (false, error_system),
+    };
+
+    let (success, error_system) = check(compile_options("minitests/arg-patterns.mini"));
+    assert!(success);
+    assert!(error_system.warnings.is_empty());
+
+    let (success, error_system) = check(compile_options("minitests/div-zero.mini"));
+    assert!(!success);
+    assert_eq!(error_system.errors.len(), 1);
+
+    let (success, error_system) = check(compile_options("minitests/arg-patterns-unused.mini"));
+    assert!(success);
+    assert_eq!(error_system.warnings.len(), 1);
+
+    // checking honors the same options compiling does
+    let mut compile = compile_options("minitests/arg-patterns-unused.mini");
+    compile.warnings_are_errors = true;
+    assert!(!check(compile).0);
+
+    let mut compile = compile_options("minitests/require-mut.mini");
+    compile.require_mut = true;
+    let (success, error_system) = check(compile);
+    assert!(!success);
+    assert_eq!(error_system.errors.len(), 1);
+}
+
+#[test]
+fn test_compile_outcome() {
+    let outcome = |file: &str| compile_options(file).compile();
+
+    let failed = outcome("minitests/div-zero.mini");
+    assert!(failed.program.is_none());
+    assert_eq!(
+        failed
+            .diagnostics
+            .iter()
+            .filter(|issue| !issue.is_warning)
+            .count(),
+        1
+    );
+
+    let warned = outcome("minitests/arg-patterns-unused.mini");
+    assert!(warned.program.is_some());
+    assert!(warned.diagnostics.iter().all(|issue| issue.is_warning));
+    assert_eq!(warned.diagnostics.len(), 1);
+}
+
+#[test]
+fn test_dependencies() {
+    let depends = dependencies_from_folder(
+        Path::new("minitests/nested"),
+        None,
+        "main",
+        Some(Path::new("arb_os/constants.json")),
+        true,
+    )
+    .unwrap();
+
+    let main = &depends["minitests/nested/main.mini"];
+    assert!(main.contains("minitests/nested/outer/middle/leaf.mini"));
+    assert!(main.contains("minitests/nested/../builtin/array.mini"));
+    assert!(depends["minitests/nested/outer/middle/leaf.mini"]
+        .iter()
+        .all(|file| file.contains("builtin")));
+}
+
+#[test]
+fn test_sizeof() {
+    let machine = compile_run_cycle("minitests/sizeof.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(
+            2 * 10000 + 3 * 1000 + 12 * 10 + 1
+        )))
+    );
+}
+
+#[test]
+fn test_map_iteration() {
+    let machine = compile_run_cycle("minitests/map-iteration.mini".to_string());
+    assert_eq!(
+        machine.stack_top(),
+        Some(&Value::Int(Uint256::from_u64(3 + 5 + 9 + 30 + 500 + 9000)))
+    );
+}
+
 #[test]
 fn test_codeblocks() {
     let machine = compile_run_cycle("minitests/codeblocks.mini".to_string());
@@ -131,11 +972,13 @@ fn test_error_system() {
     fn check_issues(
         file_path: &str,
         sources: BTreeSet<String>,
+        entry_points: &[&str],
         correct_warnings: &[&[usize]],
         correct_errors: &[&[usize]],
     ) {
         let mut compile = CompileStruct::default();
         compile.input = vec![file_path.to_string()];
+        compile.entry_points = entry_points.iter().map(|name| name.to_string()).collect();
         compile.warnings_are_errors = true;
         compile.consts_file = Some("minitests/constants.json".to_string());
 
@@ -173,6 +1016,7 @@ fn test_error_system() {
     check_issues(
         "minitests/error-system-test.mini",
         vec!["error-system-test".to_string()].into_iter().collect(),
+        &[],
         &[
             &[7],
             &[8],
@@ -181,6 +1025,8 @@ fn test_error_system() {
             &[18],
             &[19],
             &[20],
+            &[26],
+            &[27],
             &[32],
             &[51],
             &[51],
@@ -225,7 +1071,7 @@ fn test_error_system() {
             &[197],
             &[197],
         ],
-        &[&[7], &[45], &[46], &[46], &[105], &[187]],
+        &[&[7], &[45], &[46], &[46, 48], &[105], &[187], &[196]],
     );
 
     // check directory callgraph warnings
@@ -234,6 +1080,7 @@ fn test_error_system() {
         vec!["main".to_string(), "other".to_string()]
             .into_iter()
             .collect(),
+        &[],
         &[
             &[15],
             &[17], // special warning from other.mini
@@ -245,11 +1092,355 @@ fn test_error_system() {
         &[],
     );
 
+    // check that a helper shared by several entry points isn't considered unreachable
+    check_issues(
+        "minitests/entry-points.mini",
+        vec!["entry-points".to_string()].into_iter().collect(),
+        &["deposit", "entry-points::withdraw"],
+        &[&[21]],
+        &[],
+    );
+
+    // check that declared entry points aren't considered unreachable
+    check_issues(
+        "minitests/callgraph",
+        vec!["main".to_string(), "other".to_string()]
+            .into_iter()
+            .collect(),
+        &["externally_unused"],
+        &[
+            &[15],
+            &[17], // special warning from other.mini
+            &[19],
+            &[20],
+            &[21],
+        ],
+        &[],
+    );
+
     // check that replicas aren't allowed
     check_issues(
         "minitests/replicas.mini",
         vec!["replicas".to_string()].into_iter().collect(),
         &[],
+        &[],
         &[&[2, 6]],
     );
+
+    // check that value-position ifs must produce a value on every path
+    check_issues(
+        "minitests/if-value.mini",
+        vec!["if-value".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[7]],
+    );
+
+    // check that side effects in pure-only positions are flagged unless explicitly allowed
+    check_issues(
+        "minitests/impure-positions.mini",
+        vec!["impure-positions".to_string()].into_iter().collect(),
+        &[],
+        &[&[8], &[11]],
+        &[],
+    );
+
+    // check that dividing by a constant zero is caught even when the dividend isn't constant
+    check_issues(
+        "minitests/div-zero.mini",
+        vec!["div-zero".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[10]],
+    );
+
+    // check that tuple indices too large to represent are rejected rather than truncated
+    check_issues(
+        "minitests/tuple-index.mini",
+        vec!["tuple-index".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[7]],
+    );
+
+    // check that constant left shifts losing set bits are reported rather than silently folded
+    check_issues(
+        "minitests/shift-overflow.mini",
+        vec!["shift-overflow".to_string()].into_iter().collect(),
+        &[],
+        &[&[7], &[8]],
+        &[],
+    );
+
+    // check that funcs made impure by their asm point out the instructions responsible
+    check_issues(
+        "minitests/asm-purity.mini",
+        vec!["asm-purity".to_string()].into_iter().collect(),
+        &[],
+        &[&[11]],
+        &[&[5, 7]],
+    );
+
+    // check that funcs made impure by handling gas point out where they do
+    check_issues(
+        "minitests/gas-purity.mini",
+        vec!["gas-purity".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[5, 6], &[9, 10]],
+    );
+
+    // check that including a file that doesn't exist is reported where it's included
+    check_issues(
+        "minitests/include-bytes-missing.mini",
+        vec!["include-bytes-missing".to_string()]
+            .into_iter()
+            .collect(),
+        &[],
+        &[],
+        &[&[6]],
+    );
+
+    // check that address literals too wide to fit in 160 bits are reported
+    check_issues(
+        "minitests/address-literal.mini",
+        vec!["address-literal".to_string()].into_iter().collect(),
+        &[],
+        &[&[7]],
+        &[],
+    );
+
+    // check that allow attributes silence the named warnings for the nodes beneath them
+    check_issues(
+        "minitests/allow-lints.mini",
+        vec!["allow-lints".to_string()].into_iter().collect(),
+        &[],
+        &[&[8]],
+        &[],
+    );
+
+    // check that an if let whose scrutinee is always Some or always None reports its dead branch
+    check_issues(
+        "minitests/constant-scrutinee.mini",
+        vec!["constant-scrutinee".to_string()].into_iter().collect(),
+        &[],
+        &[&[10, 7], &[13, 12]],
+        &[],
+    );
+
+    // check that a call whose result isn't used is rejected unless bound to `_`
+    check_issues(
+        "minitests/discarded-result.mini",
+        vec!["discarded-result".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[6]],
+    );
+
+    // check that assert_eq needs operands that can be compared
+    check_issues(
+        "minitests/assert-eq-mismatch.mini",
+        vec!["assert-eq-mismatch".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[6]],
+    );
+
+    // check that maps can't be keyed by values without meaningful equality
+    check_issues(
+        "minitests/map-keys.mini",
+        vec!["map-keys".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[12]],
+    );
+
+    // check that entry points and the public funcs of main's module can't pass funcs across the
+    // boundary
+    check_issues(
+        "minitests/entry-point-signatures.mini",
+        vec!["entry-point-signatures".to_string()]
+            .into_iter()
+            .collect(),
+        &["twice", "double"],
+        &[],
+        &[&[9], &[13]],
+    );
+
+    // check that struct fields that are never read are reported
+    check_issues(
+        "minitests/struct-fields.mini",
+        vec!["struct-fields".to_string()].into_iter().collect(),
+        &[],
+        &[&[6], &[8], &[9]],
+        &[],
+    );
+
+    // check that args consumed only by asm count as used
+    check_issues(
+        "minitests/asm-args.mini",
+        vec!["asm-args".to_string()].into_iter().collect(),
+        &[],
+        &[&[9]],
+        &[],
+    );
+
+    // check that address literals are checksummed and have exactly 40 digits
+    check_issues(
+        "minitests/address-literals.mini",
+        vec!["address-literals".to_string()].into_iter().collect(),
+        &[],
+        &[&[8]],
+        &[&[9]],
+    );
+
+    // check that array literals need elements of compatible types
+    check_issues(
+        "minitests/array-literal-mismatch.mini",
+        vec!["array-literal-mismatch".to_string()]
+            .into_iter()
+            .collect(),
+        &[],
+        &[],
+        &[&[6]],
+    );
+
+    // check that comparing a pure value to itself is reported, but not when side effects could differ
+    check_issues(
+        "minitests/self-comparison.mini",
+        vec!["self-comparison".to_string()].into_iter().collect(),
+        &[],
+        &[&[7], &[10]],
+        &[],
+    );
+
+    // check that costly values found while producing the linked program are reported
+    check_issues(
+        "minitests/postlink-costs.mini",
+        vec!["postlink-costs".to_string()].into_iter().collect(),
+        &[],
+        &[&[5]],
+        &[],
+    );
+
+    // check that destructured args are held to the same unused-value warnings as locals
+    check_issues(
+        "minitests/arg-patterns-unused.mini",
+        vec!["arg-patterns-unused".to_string()]
+            .into_iter()
+            .collect(),
+        &[],
+        &[&[9]],
+        &[],
+    );
+
+    // check that ifs which just yield their condition or its negation are reported
+    check_issues(
+        "minitests/bool-if.mini",
+        vec!["bool-if".to_string()].into_iter().collect(),
+        &[],
+        &[&[26], &[30]],
+        &[],
+    );
+
+    // check that copies of locals holding funcs are reported
+    check_issues(
+        "minitests/non-copy.mini",
+        vec!["non-copy".to_string()].into_iter().collect(),
+        &[],
+        &[&[12], &[14]],
+        &[],
+    );
+
+    // check that let-assigning needs an existing variable
+    check_issues(
+        "minitests/let-assign-errors.mini",
+        vec!["let-assign-errors".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[6]],
+    );
+
+    // check that a type error in one func doesn't hide those in another
+    check_issues(
+        "minitests/many-type-errors.mini",
+        vec!["many-type-errors".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[10], &[15], &[19]],
+    );
+
+    // check that a func returning from only one branch of an if is rejected
+    check_issues(
+        "minitests/branch-returns-missing.mini",
+        vec!["branch-returns-missing".to_string()]
+            .into_iter()
+            .collect(),
+        &[],
+        &[],
+        &[&[9, 10]],
+    );
+
+    // check that a let with too many patterns points at those without a value
+    check_issues(
+        "minitests/let-arity.mini",
+        vec!["let-arity".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[7]],
+    );
+
+    // check that a let with too few patterns points at the values without one
+    check_issues(
+        "minitests/let-arity-values.mini",
+        vec!["let-arity-values".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[7]],
+    );
+
+    // check that nested let patterns are held to the shape of the tuple they destructure
+    check_issues(
+        "minitests/nested-let-mismatch.mini",
+        vec!["nested-let-mismatch".to_string()]
+            .into_iter()
+            .collect(),
+        &[],
+        &[],
+        &[&[6]],
+    );
+
+    // check that default values aren't made for types containing themselves without an option
+    check_issues(
+        "minitests/recursive-type.mini",
+        vec!["recursive-type".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[16, 7]],
+    );
+    check_issues(
+        "minitests/recursive-newarray.mini",
+        vec!["recursive-newarray".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[11, 7]],
+    );
+
+    // check that constants must fold to a uint
+    check_issues(
+        "minitests/const-unfolded.mini",
+        vec!["const-unfolded".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[5]],
+    );
+
+    // check that break is rejected where it's written, since blocks can't yet be exited early
+    check_issues(
+        "minitests/break-unsupported.mini",
+        vec!["break-unsupported".to_string()].into_iter().collect(),
+        &[],
+        &[],
+        &[&[8]],
+    );
 }
@@ -93,6 +93,10 @@ impl BasicGraph {
                 }
             }
         }
+        // code may end without a jump, such as the label after an if whose branches both return
+        if !block_data.is_empty() {
+            graph.add_node(BasicBlock::Code(block_data));
+        }
         let output = graph.add_node(BasicBlock::Meta("Output"));
 
         // associate labels to blocks
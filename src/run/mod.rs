@@ -96,11 +96,10 @@ pub fn load_from_file_and_env_ret_file_info_table(
 ///
 /// Will panic if s cannot be interpreted as a mini executable.
 fn load_from_string(s: String, env: RuntimeEnvironment) -> (Machine, BTreeMap<u64, FileInfo>) {
-    let parse_result: Result<LinkedProgram, serde_json::Error> = serde_json::from_str(&s);
-    let program = match parse_result {
+    let program = match LinkedProgram::from_reader(&mut s.as_bytes()) {
         Ok(prog) => prog,
         Err(e) => {
-            println!("json parsing error: {:?}", e);
+            println!("json parsing error: {}", e);
             panic!();
         }
     };
@@ -50,4 +50,36 @@ impl StringTable {
     pub fn name_from_id(&self, name: StringId) -> &String {
         &self.by_id[name as usize]
     }
+    /// Adds every name in `other`, the table of the module at `path`, to this table qualified by
+    /// that path, returning the ID each of `other`'s IDs now corresponds to, indexed by the
+    /// original ID. A module's names are its own, so the same name merged from two modules gets
+    /// two IDs, while merging the same module again yields the IDs it got before.
+    pub fn merge(&mut self, other: &StringTable, path: &[String]) -> Vec<StringId> {
+        other
+            .by_id
+            .iter()
+            .map(|name| self.get(format!("{}::{}", path.join("::"), name)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_string_table_merge() {
+    let mut first = StringTable::new();
+    let mut second = StringTable::new();
+    assert_eq!(first.get("counter".to_string()), 0);
+    assert_eq!(second.get("owner".to_string()), 0);
+    assert_eq!(second.get("counter".to_string()), 1);
+
+    let (first_path, second_path) = (vec!["first".to_string()], vec!["second".to_string()]);
+    let mut merged = StringTable::new();
+    let first_remap = merged.merge(&first, &first_path);
+    let second_remap = merged.merge(&second, &second_path);
+
+    assert_eq!(first_remap, vec![0]);
+    assert_eq!(second_remap, vec![1, 2]);
+    assert_eq!(merged.name_from_id(first_remap[0]), "first::counter");
+    assert_eq!(merged.name_from_id(second_remap[0]), "second::owner");
+    assert_eq!(merged.name_from_id(second_remap[1]), "second::counter");
+    assert_eq!(merged.merge(&first, &first_path), first_remap);
 }
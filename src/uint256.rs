@@ -410,6 +410,21 @@ impl Uint256 {
         let hash_result = keccak256(&bytes1);
         Uint256::from_bytes(&hash_result)
     }
+
+    /// Writes the low 160 bits as an address, with letters cased per EIP-55's checksum.
+    pub fn to_checksum_address(&self) -> String {
+        let digits = hex::encode(self.to_h160().as_bytes());
+        let hash = keccak256(digits.as_bytes());
+        let mut checksummed = String::from("0x");
+        for (i, digit) in digits.chars().enumerate() {
+            let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0xf;
+            checksummed.push(match nibble >= 8 {
+                true => digit.to_ascii_uppercase(),
+                false => digit,
+            });
+        }
+        checksummed
+    }
 }
 
 impl PartialOrd for Uint256 {
@@ -464,3 +479,16 @@ fn test_uint256_u32_digit_order() {
         0x6a09e667u32
     );
 }
+
+#[test]
+fn test_checksum_address() {
+    for address in [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ] {
+        let value = Uint256::from_string_hex(&address[2..]).unwrap();
+        assert_eq!(value.to_checksum_address(), address);
+    }
+}
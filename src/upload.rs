@@ -41,8 +41,7 @@ impl CodeUploader {
             Ok(_) => s,
         };
 
-        let parse_result: Result<LinkedProgram, serde_json::Error> = serde_json::from_str(&s);
-        match parse_result {
+        match LinkedProgram::from_reader(&mut s.as_bytes()) {
             Ok(prog) => {
                 let code_len = prog.code.len();
                 let mut ret = CodeUploader::_new(code_len);